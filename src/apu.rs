@@ -0,0 +1,798 @@
+// 2A03 audio processing unit: two pulse channels, triangle, noise and DMC, driven by
+// the frame counter and mixed with the nonlinear NES mixer.
+//
+// Registers:
+// $4000-$4003 pulse 1    $4008-$400B triangle    $4010-$4013 DMC
+// $4004-$4007 pulse 2    $400C-$400F noise       $4015 status    $4017 frame counter
+//
+// tick() is called once per CPU cycle; mixed output is averaged down to `sample_rate`.
+// The PAL 2A07 only differs in its clock, noise/DMC periods and frame counter steps
+
+use crate::region::Region;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15,
+];
+
+// Noise and DMC periods in CPU cycles
+const NOISE_PERIOD_TABLE_NTSC: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NOISE_PERIOD_TABLE_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+const DMC_RATE_TABLE_NTSC: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATE_TABLE_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// Frame counter steps in CPU cycles; the 4-step sequence ends at the 4th, the 5-step
+// one at the 5th
+const FRAME_STEPS_NTSC: [usize; 5] = [7457, 14913, 22371, 29829, 37281];
+const FRAME_STEPS_PAL: [usize; 5] = [8313, 16627, 24939, 33253, 41565];
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.loop_flag = data & 0x20 != 0;
+        self.constant_volume = data & 0x10 != 0;
+        self.volume = data & 0x0f;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.loop_flag {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    // pulse 1 negates with one's complement in the sweep unit
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    sequence_pos: u8,
+    timer_period: u16,
+    timer: u16,
+    length: u8,
+    length_halt: bool,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.length_halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.sequence_pos = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+        if self.sweep_negate {
+            let extra = if self.ones_complement { 1 } else { 0 };
+            self.timer_period.saturating_sub(change + extra)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.target_period() > 0x7ff
+    }
+
+    // clocked every other CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_pos = (self.sequence_pos + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.timer_period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length_halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTY_TABLE[self.duty as usize][self.sequence_pos as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    timer_period: u16,
+    timer: u16,
+    sequence_pos: u8,
+    length: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.linear_reload_value = data & 0x7f;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | data as u16,
+            _ => {
+                self.timer_period = (self.timer_period & 0x00ff) | ((data as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length > 0 && self.linear_counter > 0 {
+                self.sequence_pos = (self.sequence_pos + 1) & 0b11111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.sequence_pos as usize]
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    length_halt: bool,
+    envelope: Envelope,
+    mode: bool,
+    periods: &'static [u16; 16],
+    timer_period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+}
+
+impl Noise {
+    fn new(periods: &'static [u16; 16]) -> Self {
+        Noise {
+            enabled: false,
+            length_halt: false,
+            envelope: Envelope::default(),
+            mode: false,
+            periods,
+            timer_period: periods[0],
+            timer: 0,
+            shift: 1,
+            length: 0,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.length_halt = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {}
+            2 => {
+                self.mode = data & 0x80 != 0;
+                self.timer_period = self.periods[(data & 0x0f) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift & 1) ^ ((self.shift >> tap) & 1);
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length_halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 1 == 1 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+// Delta modulation channel. Sample bytes are fetched by the bus through
+// dmc_sample_request() / dmc_fill_sample()
+struct Dmc {
+    irq_enabled: bool,
+    irq: bool,
+    loop_flag: bool,
+    rates: &'static [u16; 16],
+    rate: u16,
+    timer: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn new(rates: &'static [u16; 16]) -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            loop_flag: false,
+            rates,
+            rate: rates[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.loop_flag = data & 0x40 != 0;
+                self.rate = self.rates[(data & 0x0f) as usize];
+            }
+            1 => self.output_level = data & 0x7f,
+            2 => self.sample_address = 0xc000 | ((data as u16) << 6),
+            _ => self.sample_length = ((data as u16) << 4) + 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn sample_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    fn fill_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xffff {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // clocked every CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    frame_cycle: usize,
+    odd_cycle: bool,
+    frame_steps: [usize; 5],
+
+    cpu_clock: f64,
+    pub sample_rate: u32,
+    sample_timer: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    samples: Vec<f32>,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu::with_region(Region::Ntsc)
+    }
+
+    // The Dendy uses an NTSC-compatible APU clocked at its own rate
+    pub fn with_region(region: Region) -> Self {
+        let pal = region == Region::Pal;
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(if pal { &NOISE_PERIOD_TABLE_PAL } else { &NOISE_PERIOD_TABLE_NTSC }),
+            dmc: Dmc::new(if pal { &DMC_RATE_TABLE_PAL } else { &DMC_RATE_TABLE_NTSC }),
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            frame_steps: if pal { FRAME_STEPS_PAL } else { FRAME_STEPS_NTSC },
+            cpu_clock: region.cpu_clock(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_timer: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    // CPU write of $4000-$4013, $4015 or $4017
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write(addr - 0x4004, data),
+            0x4008..=0x400b => self.triangle.write(addr - 0x4008, data),
+            0x400c..=0x400f => self.noise.write(addr - 0x400c, data),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, data),
+            0x4015 => {
+                self.pulse1.set_enabled(data & 0b0001 != 0);
+                self.pulse2.set_enabled(data & 0b0010 != 0);
+                self.triangle.set_enabled(data & 0b0100 != 0);
+                self.noise.set_enabled(data & 0b1000 != 0);
+                if data & 0b1_0000 == 0 {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                self.five_step_mode = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Value a read of $4015 would return, without clearing the frame IRQ
+    pub fn peek_status(&self) -> u8 {
+        let mut status = 0;
+        if self.pulse1.length > 0 {
+            status |= 0b0000_0001;
+        }
+        if self.pulse2.length > 0 {
+            status |= 0b0000_0010;
+        }
+        if self.triangle.length > 0 {
+            status |= 0b0000_0100;
+        }
+        if self.noise.length > 0 {
+            status |= 0b0000_1000;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0b0001_0000;
+        }
+        if self.frame_irq {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq {
+            status |= 0b1000_0000;
+        }
+        status
+    }
+
+    // CPU read of $4015
+    pub fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // Address of the next DMC sample byte the bus has to fetch, if any
+    pub fn dmc_sample_request(&self) -> Option<u16> {
+        self.dmc.sample_request()
+    }
+
+    pub fn dmc_fill_sample(&mut self, data: u8) {
+        self.dmc.fill_sample(data);
+    }
+
+    // Drain the audio produced so far, mono samples in 0.0..1.0
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_length();
+        self.pulse2.clock_sweep();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let [step1, step2, step3, step4, step5] = self.frame_steps;
+        match (self.frame_cycle, self.five_step_mode) {
+            (cycle, _) if cycle == step1 || cycle == step3 => self.clock_quarter_frame(),
+            (cycle, five_step) if cycle == step2 || (cycle == step5 && five_step) => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (cycle, false) if cycle == step4 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+            }
+            (cycle, false) if cycle > step4 => self.frame_cycle = 0,
+            (cycle, true) if cycle > step5 => self.frame_cycle = 0,
+            _ => {}
+        }
+    }
+
+    // Nonlinear mixer approximation from the NESdev wiki
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output_level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    // Advance by one CPU cycle
+    pub fn tick(&mut self) {
+        self.clock_frame_counter();
+
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        self.sample_sum += self.mix();
+        self.sample_count += 1;
+        self.sample_timer += self.sample_rate as f64;
+        if self.sample_timer >= self.cpu_clock {
+            self.sample_timer -= self.cpu_clock;
+            self.samples.push(self.sample_sum / self.sample_count as f32);
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_length_counter_status() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4003, 0b0000_1000); // ignored while disabled
+        assert_eq!(apu.read_status() & 1, 0);
+
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status() & 1, 1);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status() & 1, 0);
+    }
+
+    #[test]
+    fn test_frame_irq_in_four_step_mode() {
+        let mut apu = Apu::new();
+        for _ in 0..FRAME_STEPS_NTSC[3] {
+            apu.tick();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0x40);
+        for _ in 0..FRAME_STEPS_NTSC[3] {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_samples_at_output_rate() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111); // 50% duty, constant volume 15
+        apu.write_register(0x4002, 0xfd);
+        apu.write_register(0x4003, 0b0000_1000);
+
+        for _ in 0..Region::Ntsc.cpu_clock() as usize / 10 {
+            apu.tick();
+        }
+        let samples = apu.take_samples();
+        assert!((samples.len() as i64 - DEFAULT_SAMPLE_RATE as i64 / 10).abs() <= 1);
+        assert!(samples.iter().any(|&sample| sample > 0.1));
+        assert!(apu.take_samples().is_empty());
+    }
+
+    #[test]
+    fn test_dmc_fetches_samples_and_raises_irq() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x80); // IRQ enabled
+        apu.write_register(0x4012, 0x00); // $C000
+        apu.write_register(0x4013, 0x00); // 1 byte
+        apu.write_register(0x4015, 0b0001_0000);
+
+        assert_eq!(apu.dmc_sample_request(), Some(0xc000));
+        apu.dmc_fill_sample(0xff);
+        assert_eq!(apu.dmc_sample_request(), None);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0x90, 0x80);
+    }
+
+    #[test]
+    fn test_pal_timing() {
+        let mut apu = Apu::with_region(Region::Pal);
+        for _ in 0..FRAME_STEPS_NTSC[3] {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        for _ in FRAME_STEPS_NTSC[3]..FRAME_STEPS_PAL[3] {
+            apu.tick();
+        }
+        assert!(apu.irq());
+
+        apu.write_register(0x400e, 0x02);
+        assert_eq!(apu.noise.timer_period, 14);
+        apu.write_register(0x4010, 0x0f);
+        assert_eq!(apu.dmc.rate, 50);
+
+        let mut dendy = Apu::with_region(Region::Dendy);
+        dendy.write_register(0x4010, 0x0f);
+        assert_eq!(dendy.dmc.rate, 54);
+    }
+}
@@ -0,0 +1,907 @@
+// Two-pass 6502 assembler over the OpCode tables in opcodes.rs.
+//
+//   ; comment
+//   SPEED = 2                  constant
+//   .org $8000                 set the address of what follows
+//   Reset:  ldx #0             label, instruction
+//   @loop:  inx                local label, scoped to the last global label
+//           bne @loop
+//           jmp (vector)
+//   table:  .byte 1, $02, %11, "text"
+//           .word Reset, table+2
+//   .include "other.s"         relative to the including file
+//
+// Operand syntax follows disasm.rs. Expressions take numbers ($10 or 0x10 hex, %1010
+// binary, 16 decimal, 'c' characters), labels, * for the current address, unary - ~
+// and < > for the low and high byte, * / + - << >> & ^ | and parentheses.
+// Mnemonics and directives are case-insensitive, labels are not.
+//
+// Pass one fixes the size of every instruction: zero page addressing is picked when the
+// operand is already known to fit, forward references get absolute addressing. Pass two
+// evaluates everything and emits the bytes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::cartridge::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use crate::cpu::{AddressingMode, CpuVariant};
+use crate::debugger::expr::parse_number;
+use crate::disasm::SymbolTable;
+use crate::opcodes::{self, OpCode};
+
+// .include nesting limit, to catch files that include themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Assembled code: the bytes from the lowest to the highest address written, with gaps
+// filled with zeros, and the labels for the disassembler and debugger
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    pub symbols: SymbolTable,
+}
+
+impl Output {
+    // NROM image with the code as PRG ROM: 16KB if it all fits in $8000-$BFFF or
+    // $C000-$FFFF (mirrored into the other half), else 32KB. `chr_rom` is padded to 8KB
+    pub fn to_ines(&self, chr_rom: &[u8]) -> Result<Vec<u8>, String> {
+        let start = self.origin as usize;
+        let end = start + self.bytes.len();
+        if start < 0x8000 {
+            return Err(format!("code at ${:04X} is outside PRG ROM ($8000-$FFFF)", start));
+        }
+        let (base, size) = if end <= 0xc000 {
+            (0x8000, PRG_ROM_PAGE_SIZE)
+        } else if start >= 0xc000 {
+            (0xc000, PRG_ROM_PAGE_SIZE)
+        } else {
+            (0x8000, 2 * PRG_ROM_PAGE_SIZE)
+        };
+        let mut prg = vec![0; size];
+        prg[start - base..end - base].copy_from_slice(&self.bytes);
+
+        let chr_banks = chr_rom.len().div_ceil(CHR_ROM_PAGE_SIZE).max(1);
+        let mut chr = chr_rom.to_vec();
+        chr.resize(chr_banks * CHR_ROM_PAGE_SIZE, 0);
+
+        // vertical mirroring, mapper 0
+        let mut raw = vec![0x4e, 0x45, 0x53, 0x1a, (size / PRG_ROM_PAGE_SIZE) as u8, chr_banks as u8, 0x01];
+        raw.resize(16, 0);
+        raw.extend(prg);
+        raw.extend(chr);
+        Ok(raw)
+    }
+}
+
+pub struct Assembler {
+    variant: CpuVariant,
+    // where .include looks for files named in inline source
+    base_dir: PathBuf,
+}
+
+impl Assembler {
+    pub fn new(variant: CpuVariant) -> Self {
+        Assembler {
+            variant,
+            base_dir: PathBuf::new(),
+        }
+    }
+
+    // Directory .include paths in inline source are relative to
+    pub fn with_base_dir(mut self, dir: &Path) -> Self {
+        self.base_dir = dir.to_path_buf();
+        self
+    }
+
+    // Assemble `source`, placing code at `origin` until the first .org
+    pub fn assemble(&self, source: &str, origin: u16) -> Result<Output, String> {
+        let mut lines = Vec::new();
+        let mut files = vec!["<source>".to_string()];
+        read_lines(source, 0, &self.base_dir, &mut files, &mut lines, 0)?;
+        self.assemble_lines(&lines, &files, origin)
+    }
+
+    pub fn assemble_file(&self, path: &str, origin: u16) -> Result<Output, String> {
+        let source = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut lines = Vec::new();
+        let mut files = vec![path.to_string()];
+        read_lines(&source, 0, dir, &mut files, &mut lines, 0)?;
+        self.assemble_lines(&lines, &files, origin)
+    }
+
+    fn assemble_lines(&self, lines: &[Line], files: &[String], origin: u16) -> Result<Output, String> {
+        let at = |line: &Line, err: String| format!("{}:{}: {}", files[line.file], line.number, err);
+
+        let mut statements = Vec::new();
+        let mut scope = String::new();
+        for line in lines {
+            let statement = parse_statement(&line.text, &mut scope).map_err(|err| at(line, err))?;
+            statements.push(statement);
+        }
+
+        // pass one: addresses of labels and sizes of instructions
+        let mut symbols: HashMap<String, i64> = HashMap::new();
+        let mut labels = Vec::new();
+        let mut opcodes = Vec::with_capacity(statements.len());
+        let mut pc = origin as i64;
+        for (statement, line) in statements.iter().zip(lines) {
+            let mut opcode = None;
+            self.first_pass(statement, &mut pc, &mut symbols, &mut labels, &mut opcode)
+                .map_err(|err| at(line, err))?;
+            opcodes.push(opcode);
+        }
+
+        // pass two: emit
+        let mut image = Image::new();
+        let mut pc = origin as i64;
+        for ((statement, line), opcode) in statements.iter().zip(lines).zip(&opcodes) {
+            self.second_pass(statement, *opcode, &mut pc, &symbols, &mut image)
+                .map_err(|err| at(line, err))?;
+        }
+
+        let mut table = SymbolTable::new();
+        for (name, addr) in labels {
+            table.insert(addr, &name);
+        }
+        let (origin, bytes) = image.finish();
+        Ok(Output { origin, bytes, symbols: table })
+    }
+
+    fn first_pass(
+        &self,
+        statement: &Statement,
+        pc: &mut i64,
+        symbols: &mut HashMap<String, i64>,
+        labels: &mut Vec<(String, u16)>,
+        opcode: &mut Option<&'static OpCode>,
+    ) -> Result<(), String> {
+        if let Some(label) = &statement.label {
+            define(symbols, label, *pc)?;
+            labels.push((label.clone(), *pc as u16));
+        }
+
+        match &statement.item {
+            Item::None => {}
+            Item::Org(expr) => {
+                *pc = expr.eval(symbols, *pc).map_err(|err| format!(".org needs a known address: {}", err))?;
+                check_range(*pc, 0, 0xffff, "address")?;
+            }
+            Item::Constant(name, expr) => {
+                let value = expr.eval(symbols, *pc)?;
+                define(symbols, name, value)?;
+            }
+            Item::Byte(items) => *pc += items.iter().map(|item| item.len() as i64).sum::<i64>(),
+            Item::Word(exprs) => *pc += 2 * exprs.len() as i64,
+            Item::Instruction(mnemonic, operand) => {
+                let chosen = self.choose_opcode(mnemonic, operand, symbols, *pc)?;
+                *pc += chosen.len as i64;
+                *opcode = Some(chosen);
+            }
+        }
+
+        if *pc > 0x10000 {
+            return Err("code runs past $FFFF".to_string());
+        }
+        Ok(())
+    }
+
+    fn second_pass(
+        &self,
+        statement: &Statement,
+        opcode: Option<&'static OpCode>,
+        pc: &mut i64,
+        symbols: &HashMap<String, i64>,
+        image: &mut Image,
+    ) -> Result<(), String> {
+        match &statement.item {
+            Item::None | Item::Constant(..) => {}
+            Item::Org(expr) => *pc = expr.eval(symbols, *pc)?,
+            Item::Byte(items) => {
+                for item in items {
+                    match item {
+                        ByteItem::Expr(expr) => {
+                            let value = expr.eval(symbols, *pc)?;
+                            check_range(value, -0x80, 0xff, "byte")?;
+                            image.write(pc, &[value as u8])?;
+                        }
+                        ByteItem::Text(text) => image.write(pc, text.as_bytes())?,
+                    }
+                }
+            }
+            Item::Word(exprs) => {
+                for expr in exprs {
+                    let value = expr.eval(symbols, *pc)?;
+                    check_range(value, -0x8000, 0xffff, "word")?;
+                    image.write(pc, &(value as u16).to_le_bytes())?;
+                }
+            }
+            Item::Instruction(_, operand) => {
+                let opcode = opcode.expect("sized in the first pass");
+                let bytes = encode(opcode, operand, symbols, *pc)?;
+                image.write(pc, &bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Opcode of `mnemonic` for the operand syntax, preferring zero page addressing when
+    // the operand is already known to fit
+    fn choose_opcode(
+        &self,
+        mnemonic: &str,
+        operand: &Operand,
+        symbols: &HashMap<String, i64>,
+        pc: i64,
+    ) -> Result<&'static OpCode, String> {
+        let find = |mode: AddressingMode| self.find_opcode(mnemonic, mode);
+        if find_any(self.variant, mnemonic).is_none() {
+            return Err(format!("unknown instruction: {}", mnemonic));
+        }
+        let fits_zero_page = |expr: &Expr| expr.eval(symbols, pc).is_ok_and(|value| (0..=0xff).contains(&value));
+        let zero_page_or = |expr: &Expr, zero_page: AddressingMode, absolute: AddressingMode| {
+            match find(zero_page) {
+                Some(opcode) if fits_zero_page(expr) || find(absolute).is_none() => Some(opcode),
+                _ => find(absolute),
+            }
+        };
+
+        let opcode = match operand {
+            Operand::Implied => find(AddressingMode::NoneAddressing).or_else(|| find(AddressingMode::Accumulator)),
+            Operand::Accumulator => find(AddressingMode::Accumulator),
+            Operand::Immediate(_) => find(AddressingMode::Immediate),
+            Operand::Address(expr, Index::None) => find(AddressingMode::Relative)
+                .or_else(|| zero_page_or(expr, AddressingMode::ZeroPage, AddressingMode::Absolute)),
+            Operand::Address(expr, Index::X) => zero_page_or(expr, AddressingMode::ZeroPage_X, AddressingMode::Absolute_X),
+            Operand::Address(expr, Index::Y) => zero_page_or(expr, AddressingMode::ZeroPage_Y, AddressingMode::Absolute_Y),
+            Operand::IndirectX(_) => find(AddressingMode::Indirect_X).or_else(|| find(AddressingMode::Indirect_Absolute_X)),
+            Operand::IndirectY(_) => find(AddressingMode::Indirect_Y),
+            Operand::Indirect(_) => find(AddressingMode::Indirect).or_else(|| find(AddressingMode::ZeroPage_Indirect)),
+            Operand::ZeroPageRelative(..) => find(AddressingMode::ZeroPage_Relative),
+        };
+        opcode.ok_or_else(|| format!("{} doesn't take this operand", mnemonic))
+    }
+
+    fn find_opcode(&self, mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+        instruction_set(self.variant).find(|opcode| opcode.mnemonic == mnemonic && opcode.mode == mode)
+    }
+}
+
+// Assemble a test program for CPU::load(), which places it at $0600, panicking with
+// the error if it doesn't assemble
+pub fn program(source: &str) -> Vec<u8> {
+    match Assembler::new(CpuVariant::Nes2A03).assemble(source, 0x0600) {
+        Ok(output) => output.bytes,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+// CMOS additions come first so they replace the NMOS entries they change. The 65C02's
+// undefined opcodes run as NOPs, but NOP only ever assembles to $EA
+fn instruction_set(variant: CpuVariant) -> impl Iterator<Item = &'static OpCode> {
+    let cmos: &'static [OpCode] = if variant.is_cmos() { &opcodes::CMOS_OPS_CODES } else { &[] };
+    cmos.iter().filter(|opcode| opcode.mnemonic != "NOP").chain(opcodes::CPU_OPS_CODES.iter())
+}
+
+fn find_any(variant: CpuVariant, mnemonic: &str) -> Option<&'static OpCode> {
+    instruction_set(variant).find(|opcode| opcode.mnemonic == mnemonic)
+}
+
+fn define(symbols: &mut HashMap<String, i64>, name: &str, value: i64) -> Result<(), String> {
+    if symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("{} is defined twice", name));
+    }
+    Ok(())
+}
+
+fn check_range(value: i64, min: i64, max: i64, what: &str) -> Result<(), String> {
+    if value < min || value > max {
+        return Err(format!("{} out of range: {}", what, value));
+    }
+    Ok(())
+}
+
+fn encode(opcode: &OpCode, operand: &Operand, symbols: &HashMap<String, i64>, pc: i64) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![opcode.code];
+    let eval = |expr: &Expr| expr.eval(symbols, pc);
+    // branch offsets are relative to the end of the instruction
+    let branch = |expr: &Expr| -> Result<u8, String> {
+        let offset = eval(expr)? - (pc + opcode.len as i64);
+        if !(-0x80..=0x7f).contains(&offset) {
+            return Err(format!("branch out of range by {} bytes", offset.abs() - 0x80 + (offset > 0) as i64));
+        }
+        Ok(offset as u8)
+    };
+
+    match (opcode.mode, operand) {
+        (AddressingMode::Relative, Operand::Address(expr, _)) => bytes.push(branch(expr)?),
+        (AddressingMode::ZeroPage_Relative, Operand::ZeroPageRelative(zero_page, target)) => {
+            let value = eval(zero_page)?;
+            check_range(value, 0, 0xff, "zero page address")?;
+            bytes.push(value as u8);
+            bytes.push(branch(target)?);
+        }
+        (_, Operand::Immediate(expr)) => {
+            let value = eval(expr)?;
+            check_range(value, -0x80, 0xff, "immediate value")?;
+            bytes.push(value as u8);
+        }
+        (
+            _,
+            Operand::Address(expr, _) | Operand::IndirectX(expr) | Operand::IndirectY(expr) | Operand::Indirect(expr),
+        ) => {
+            let value = eval(expr)?;
+            if opcode.len == 2 {
+                check_range(value, 0, 0xff, "zero page address")?;
+                bytes.push(value as u8);
+            } else {
+                check_range(value, 0, 0xffff, "address")?;
+                bytes.extend((value as u16).to_le_bytes());
+            }
+        }
+        _ => {}
+    }
+    Ok(bytes)
+}
+
+// 64KB of output, remembering which bytes have been written to catch overlapping .orgs
+struct Image {
+    memory: Vec<u8>,
+    written: Vec<bool>,
+}
+
+impl Image {
+    fn new() -> Self {
+        Image {
+            memory: vec![0; 0x10000],
+            written: vec![false; 0x10000],
+        }
+    }
+
+    fn write(&mut self, pc: &mut i64, bytes: &[u8]) -> Result<(), String> {
+        for &byte in bytes {
+            let addr = *pc as usize;
+            if self.written[addr] {
+                return Err(format!("overwrites code already at ${:04X}", addr));
+            }
+            self.memory[addr] = byte;
+            self.written[addr] = true;
+            *pc += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> (u16, Vec<u8>) {
+        let first = self.written.iter().position(|&written| written);
+        let last = self.written.iter().rposition(|&written| written);
+        match (first, last) {
+            (Some(first), Some(last)) => (first as u16, self.memory[first..=last].to_vec()),
+            _ => (0, Vec::new()),
+        }
+    }
+}
+
+struct Line {
+    file: usize,
+    number: usize,
+    text: String,
+}
+
+// Split `source` into lines, splicing in .include files
+fn read_lines(
+    source: &str,
+    file: usize,
+    dir: &Path,
+    files: &mut Vec<String>,
+    lines: &mut Vec<Line>,
+    depth: usize,
+) -> Result<(), String> {
+    for (number, text) in source.lines().enumerate() {
+        let code = strip_comment(text).trim();
+        let include = code
+            .split_once(char::is_whitespace)
+            .filter(|(directive, _)| directive.eq_ignore_ascii_case(".include"));
+        let Some((_, name)) = include else {
+            lines.push(Line { file, number: number + 1, text: text.to_string() });
+            continue;
+        };
+
+        let at = |err: String| format!("{}:{}: {}", files[file], number + 1, err);
+        let name = name.trim();
+        let name = name
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .ok_or_else(|| at("expected .include \"file\"".to_string()))?;
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(at(format!("includes nested deeper than {}", MAX_INCLUDE_DEPTH)));
+        }
+        let path = dir.join(name);
+        let included = std::fs::read_to_string(&path).map_err(|err| at(format!("{}: {}", path.display(), err)))?;
+        files.push(path.to_string_lossy().into_owned());
+        let index = files.len() - 1;
+        read_lines(&included, index, path.parent().unwrap_or(Path::new("")), files, lines, depth + 1)?;
+    }
+    Ok(())
+}
+
+// Cut a `;` comment, leaving semicolons in strings and character literals alone
+fn strip_comment(text: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            (';', None) => return &text[..i],
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    text
+}
+
+// Split on commas outside parentheses and quotes
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(open)) if c == open => quote = None,
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+struct Statement {
+    label: Option<String>,
+    item: Item,
+}
+
+enum Item {
+    None,
+    Org(Expr),
+    Constant(String, Expr),
+    Byte(Vec<ByteItem>),
+    Word(Vec<Expr>),
+    Instruction(String, Operand),
+}
+
+enum ByteItem {
+    Expr(Expr),
+    Text(String),
+}
+
+impl ByteItem {
+    fn len(&self) -> usize {
+        match self {
+            ByteItem::Expr(_) => 1,
+            ByteItem::Text(text) => text.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+enum Operand {
+    Implied,
+    Accumulator,
+    Immediate(Expr),
+    Address(Expr, Index),
+    IndirectX(Expr),
+    IndirectY(Expr),
+    Indirect(Expr),
+    ZeroPageRelative(Expr, Expr),
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Local labels (@name) belong to the global label before them
+fn qualify(name: &str, scope: &str) -> String {
+    if name.starts_with('@') {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn parse_statement(text: &str, scope: &mut String) -> Result<Statement, String> {
+    let mut rest = strip_comment(text).trim();
+
+    let mut label = None;
+    if let Some((name, after)) = rest.split_once(':') {
+        if is_identifier(name.trim()) {
+            let name = name.trim();
+            if !name.starts_with('@') {
+                *scope = name.to_string();
+            }
+            label = Some(qualify(name, scope));
+            rest = after.trim();
+        }
+    }
+
+    let (word, args) = match rest.split_once(char::is_whitespace) {
+        Some((word, args)) => (word, args.trim()),
+        None => (rest, ""),
+    };
+
+    let item = if rest.is_empty() {
+        Item::None
+    } else if let Some((name, value)) = rest.split_once('=').filter(|(name, _)| is_identifier(name.trim())) {
+        Item::Constant(qualify(name.trim(), scope), Expr::parse(value, scope)?)
+    } else if word.starts_with('.') {
+        match word.to_ascii_lowercase().as_str() {
+            ".org" => Item::Org(Expr::parse(args, scope)?),
+            ".byte" | ".db" => {
+                let mut items = Vec::new();
+                for part in split_top_level(args) {
+                    match part.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+                        Some(text) => items.push(ByteItem::Text(text.to_string())),
+                        None => items.push(ByteItem::Expr(Expr::parse(part, scope)?)),
+                    }
+                }
+                Item::Byte(items)
+            }
+            ".word" | ".dw" => {
+                let exprs = split_top_level(args).into_iter().map(|part| Expr::parse(part, scope));
+                Item::Word(exprs.collect::<Result<_, _>>()?)
+            }
+            _ => return Err(format!("unknown directive: {}", word)),
+        }
+    } else {
+        Item::Instruction(word.to_ascii_uppercase(), parse_operand(args, scope)?)
+    };
+
+    Ok(Statement { label, item })
+}
+
+// Index of the parenthesis closing the one `text` starts with
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_operand(text: &str, scope: &str) -> Result<Operand, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(Operand::Implied);
+    }
+    if text.eq_ignore_ascii_case("a") {
+        return Ok(Operand::Accumulator);
+    }
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand::Immediate(Expr::parse(value, scope)?));
+    }
+
+    // (zp,X) (abs,X) (zp),Y (abs) (zp), unless the parentheses only group an expression
+    if text.starts_with('(') && closing_paren(text).is_some() {
+        let close = closing_paren(text).unwrap_or(0);
+        let inner = &text[1..close];
+        let after = text[close + 1..].trim();
+        let inner_parts = split_top_level(inner);
+        if after.is_empty() {
+            return match inner_parts.as_slice() {
+                [expr, index] if index.eq_ignore_ascii_case("x") => Ok(Operand::IndirectX(Expr::parse(expr, scope)?)),
+                [expr] => Ok(Operand::Indirect(Expr::parse(expr, scope)?)),
+                _ => Err(format!("invalid operand: {}", text)),
+            };
+        }
+        if let Some(index) = after.strip_prefix(',') {
+            if index.trim().eq_ignore_ascii_case("y") && inner_parts.len() == 1 {
+                return Ok(Operand::IndirectY(Expr::parse(inner, scope)?));
+            }
+            return Err(format!("invalid operand: {}", text));
+        }
+    }
+
+    match split_top_level(text).as_slice() {
+        [expr] => Ok(Operand::Address(Expr::parse(expr, scope)?, Index::None)),
+        [expr, index] if index.eq_ignore_ascii_case("x") => Ok(Operand::Address(Expr::parse(expr, scope)?, Index::X)),
+        [expr, index] if index.eq_ignore_ascii_case("y") => Ok(Operand::Address(Expr::parse(expr, scope)?, Index::Y)),
+        [zero_page, target] => Ok(Operand::ZeroPageRelative(
+            Expr::parse(zero_page, scope)?,
+            Expr::parse(target, scope)?,
+        )),
+        _ => Err(format!("invalid operand: {}", text)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    Pc,
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+// Binary operators from the loosest binding level to the tightest
+const BINARY_LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+impl Expr {
+    fn parse(text: &str, scope: &str) -> Result<Expr, String> {
+        let tokens = tokenize(text, scope)?;
+        let mut parser = ExprParser { tokens, pos: 0 };
+        let expr = parser.binary(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {} in expression", token)),
+        }
+    }
+
+    fn eval(&self, symbols: &HashMap<String, i64>, pc: i64) -> Result<i64, String> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Symbol(name) => *symbols.get(name).ok_or_else(|| format!("undefined label: {}", name))?,
+            Expr::Pc => pc,
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(symbols, pc)?;
+                match *op {
+                    "-" => -value,
+                    "~" => !value,
+                    "<" => value & 0xff,
+                    _ => (value >> 8) & 0xff,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(symbols, pc)?, rhs.eval(symbols, pc)?);
+                match *op {
+                    "|" => lhs | rhs,
+                    "^" => lhs ^ rhs,
+                    "&" => lhs & rhs,
+                    "<<" => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                    ">>" => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                    "+" => lhs.wrapping_add(rhs),
+                    "-" => lhs.wrapping_sub(rhs),
+                    "*" => lhs.wrapping_mul(rhs),
+                    _ => lhs.checked_div(rhs).ok_or("division by zero")?,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Op(op) => write!(f, "`{}`", op),
+        }
+    }
+}
+
+// Longest operators first so `<<` isn't read as `<`
+const OPERATORS: [&str; 14] = ["<<", ">>", "|", "^", "&", "+", "-", "*", "/", "~", "<", ">", "(", ")"];
+
+fn tokenize(text: &str, scope: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c == '\'' {
+            let mut chars = rest[1..].chars();
+            match (chars.next(), chars.next()) {
+                (Some(value), Some('\'')) => {
+                    tokens.push(Token::Number(value as i64));
+                    rest = &rest[1 + value.len_utf8() + 1..];
+                }
+                _ => return Err(format!("invalid character literal in {}", text.trim())),
+            }
+        } else if c.is_ascii_alphanumeric() || "$%_@".contains(c) {
+            let len = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| !(c.is_ascii_alphanumeric() || c == '_'))
+                .map_or(rest.len(), |(i, _)| i);
+            let word = &rest[..len];
+            if word.starts_with(|c: char| c.is_ascii_digit() || "$%".contains(c)) {
+                tokens.push(Token::Number(parse_number(word)?));
+            } else {
+                tokens.push(Token::Name(qualify(word, scope)));
+            }
+            rest = &rest[len..];
+        } else {
+            return Err(format!("unexpected `{}` in expression", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    // Left-associative chain of the operators at `level` of BINARY_LEVELS
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| BINARY_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("incomplete expression")?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) => Ok(Expr::Symbol(name)),
+            Token::Op("*") => Ok(Expr::Pc),
+            Token::Op(op @ ("-" | "~" | "<" | ">")) => Ok(Expr::Unary(op, Box::new(self.unary()?))),
+            Token::Op("(") => {
+                let expr = self.binary(0)?;
+                match self.peek_op() {
+                    Some(")") => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err("expected `)` in expression".to_string()),
+                }
+            }
+            token => Err(format!("unexpected {} in expression", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assemble(source: &str, origin: u16) -> Result<Vec<u8>, String> {
+        Assembler::new(CpuVariant::Nes2A03).assemble(source, origin).map(|output| output.bytes)
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            lda #$05
+            lda $10
+            ldx $10,y
+            sta $0200,x
+            lda ($10,x)
+            lda ($10),y
+            jmp ($fffc)
+            asl a
+            asl
+            nop
+            lda (1+2)*4
+        ";
+        assert_eq!(
+            assemble(source, 0x8000).unwrap(),
+            vec![
+                0xa9, 0x05, 0xa5, 0x10, 0xb6, 0x10, 0x9d, 0x00, 0x02, 0xa1, 0x10, 0xb1, 0x10,
+                0x6c, 0xfc, 0xff, 0x0a, 0x0a, 0xea, 0xa5, 0x0c,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_directives() {
+        let source = r#"
+            PTR = $10
+            .org $8000
+    Reset:  ldx #0              ; forward references get absolute addressing
+    @loop:  inx
+            stx later
+            bne @loop
+            lda #<table
+            sta PTR
+            jmp Reset
+    Other:
+    @loop:  bne @loop
+    table:  .byte 1, 'A', "hi"
+            .word Reset, *
+    later:  .org $fffc
+            .word Reset
+        "#;
+        let output = Assembler::new(CpuVariant::Nes2A03).assemble(source, 0).unwrap();
+        assert_eq!(output.origin, 0x8000);
+        assert_eq!(
+            output.bytes[..0x19],
+            [
+                0xa2, 0x00, 0xe8, 0x8e, 0x19, 0x80, 0xd0, 0xfa, 0xa9, 0x11, 0x85, 0x10, 0x4c,
+                0x00, 0x80, 0xd0, 0xfe, 0x01, 0x41, 0x68, 0x69, 0x00, 0x80, 0x17, 0x80,
+            ]
+        );
+        assert_eq!(output.bytes.len(), 0x7ffe);
+        assert_eq!(output.bytes[0x7ffc..], [0x00, 0x80]);
+        assert_eq!(output.symbols.label(0x8002), Some("Reset@loop"));
+        assert_eq!(output.symbols.lookup("Other"), Some(0x800f));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source, 0x8000).unwrap_err();
+        assert_eq!(error("nop\nfoo #1"), "<source>:2: unknown instruction: FOO");
+        assert_eq!(error("lda ($10),x"), "<source>:1: invalid operand: ($10),x");
+        assert_eq!(error("ldx ($10),y"), "<source>:1: LDX doesn't take this operand");
+        assert_eq!(error("jmp nowhere"), "<source>:1: undefined label: nowhere");
+        assert_eq!(error("lda #256"), "<source>:1: immediate value out of range: 256");
+        assert_eq!(error("x: nop\nx: nop"), "<source>:2: x is defined twice");
+        assert!(error("bne far\n.org $8100\nfar: nop").contains("branch out of range"));
+        assert!(error(".org $8000\nnop\n.org $8000\nnop").contains("overwrites"));
+        assert!(error(".include \"missing.s\"").starts_with("<source>:1: "));
+    }
+
+    #[test]
+    fn test_65c02() {
+        let output = Assembler::new(CpuVariant::Cmos65C02).assemble("lda ($10)\nbbr0 $10,*\nstz $0200\nnop", 0x8000).unwrap();
+        assert_eq!(output.bytes, vec![0xb2, 0x10, 0x0f, 0x10, 0xfd, 0x9c, 0x00, 0x02, 0xea]);
+        assert!(assemble("stz $10", 0x8000).is_err());
+    }
+
+    #[test]
+    fn test_include_and_ines() {
+        let dir = std::env::temp_dir().join(format!("nes_asm_include_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("vectors.s"), ".org $fffa\n.word Reset, Reset, Reset\n").unwrap();
+        let main = dir.join("main.s");
+        std::fs::write(&main, ".org $c000\nReset: jmp Reset\n.include \"vectors.s\"\n").unwrap();
+
+        let output = Assembler::new(CpuVariant::Nes2A03).assemble_file(main.to_str().unwrap(), 0).unwrap();
+        let rom = crate::cartridge::Rom::new(&output.to_ines(&[]).unwrap()).unwrap();
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.prg_rom[..3], [0x4c, 0x00, 0xc0]);
+        assert_eq!(rom.prg_rom[0x3ffc..], [0x00, 0xc0, 0x00, 0xc0]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
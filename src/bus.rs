@@ -0,0 +1,499 @@
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+
+use crate::apu::Apu;
+use crate::cartridge::Rom;
+use crate::cdl::{self, CodeDataLog, PrgFlags};
+use crate::cpu::{Mem, CYCLES_PER_FRAME};
+use crate::joypad::Joypad;
+use crate::ppu::NesPPU;
+use crate::region::Region;
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const PRG_ROM: u16 = 0x8000;
+
+// Everything the CPU is wired to. Devices are clocked through tick() after every
+// instruction and report interrupts back to the CPU
+pub trait Bus: Mem {
+    // Advance attached devices by `cycles` CPU cycles
+    fn tick(&mut self, cycles: usize);
+
+    // True once for every NMI edge raised since the last poll
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    // Level of the IRQ line driven by devices
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Cycles the CPU is stalled for by DMA since the last poll
+    fn poll_stall_cycles(&mut self) -> usize {
+        0
+    }
+
+    // True once a frame has been completed since the last poll
+    fn poll_frame(&mut self) -> bool;
+
+    // True while the picture unit is in vertical blank, for the profiler
+    fn in_vblank(&self) -> bool {
+        false
+    }
+
+    // TV system the machine is timed for, which traces use to place the PPU
+    fn region(&self) -> Region {
+        Region::Ntsc
+    }
+
+    // The CPU classifies its accesses for the code/data logger: instruction bytes,
+    // data reads and jump targets
+    fn log_prg(&mut self, _addr: u16, _flags: PrgFlags) {}
+}
+
+// Flat 64KB of RAM with no devices attached, for plain 6502 programs and test suites.
+// A "frame" passes every CYCLES_PER_FRAME cycles
+pub struct FlatBus {
+    memory: [u8; 0x10000],
+    frame_cycles: usize,
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        FlatBus {
+            memory: [0; 0x10000],
+            frame_cycles: 0,
+        }
+    }
+
+    // Copy a raw image into memory at `addr`, e.g. a full 64KB image at $0000
+    pub fn load_at(&mut self, addr: u16, image: &[u8]) -> Result<(), String> {
+        let start = addr as usize;
+        if start + image.len() > self.memory.len() {
+            return Err(format!(
+                "image of {} bytes does not fit at ${:04X}",
+                image.len(),
+                addr
+            ));
+        }
+        self.memory[start..start + image.len()].copy_from_slice(image);
+        Ok(())
+    }
+
+    // Map NROM style PRG ROM into $8000-$FFFF; a single 16KB bank is mirrored into $C000
+    pub fn load_prg_rom(&mut self, prg_rom: &[u8]) -> Result<(), String> {
+        match prg_rom.len() {
+            0x4000 => {
+                self.memory[0x8000..0xC000].copy_from_slice(prg_rom);
+                self.memory[0xC000..0x10000].copy_from_slice(prg_rom);
+            }
+            0x8000 => self.memory[0x8000..0x10000].copy_from_slice(prg_rom),
+            len => return Err(format!("PRG ROM of {} bytes needs a mapper", len)),
+        }
+        Ok(())
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        FlatBus::new()
+    }
+}
+
+impl Mem for FlatBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+impl Bus for FlatBus {
+    fn tick(&mut self, cycles: usize) {
+        self.frame_cycles += cycles;
+    }
+
+    fn poll_frame(&mut self) -> bool {
+        if self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frame_cycles -= CYCLES_PER_FRAME;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// NES memory map: 2KB of internal RAM, PPU and APU registers, controllers and an
+// NROM cartridge with 8KB of PRG RAM at $6000
+pub struct NesBus {
+    cpu_vram: [u8; 2048],
+    prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000],
+    pub ppu: NesPPU,
+    pub apu: Apu,
+    pub joypad1: Joypad,
+    pub joypad2: Joypad,
+    // Last value driven on the data bus, returned by unmapped reads
+    open_bus: u8,
+    stall_cycles: usize,
+    // Fraction of a PPU dot carried over between ticks, in units of 1/denominator
+    dot_remainder: usize,
+    // PRG half of the code/data log while one is running; the PPU keeps the CHR half
+    prg_log: Option<Vec<u8>>,
+}
+
+impl NesBus {
+    // Console region from the header, NTSC for multi-region or unmarked cartridges
+    pub fn new(rom: Rom) -> Result<Self, String> {
+        let region = rom.region.unwrap_or_default();
+        NesBus::with_region(rom, region)
+    }
+
+    pub fn with_region(rom: Rom, region: Region) -> Result<Self, String> {
+        if rom.mapper != 0 {
+            return Err(format!("mapper {} is not supported", rom.mapper));
+        }
+        match rom.prg_rom.len() {
+            0x4000 | 0x8000 => {}
+            len => return Err(format!("PRG ROM of {} bytes needs a mapper", len)),
+        }
+
+        Ok(NesBus {
+            cpu_vram: [0; 2048],
+            prg_rom: rom.prg_rom,
+            prg_ram: [0; 0x2000],
+            ppu: NesPPU::with_region(rom.chr_rom, rom.screen_mirroring, region),
+            apu: Apu::with_region(region),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            open_bus: 0,
+            stall_cycles: 0,
+            dot_remainder: 0,
+            prg_log: None,
+        })
+    }
+
+    // 2KB internal RAM, e.g. for dumping game state
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    // Start logging accesses into `log`, e.g. one loaded from an earlier session
+    pub fn start_code_data_log(&mut self, log: CodeDataLog) -> Result<(), String> {
+        if log.prg.len() != self.prg_rom.len() || log.chr.len() != self.ppu.chr_rom_size() {
+            return Err("code/data log doesn't match the cartridge".to_string());
+        }
+        self.prg_log = Some(log.prg);
+        self.ppu.chr_log = Some(log.chr);
+        Ok(())
+    }
+
+    // Snapshot of the running code/data log
+    pub fn code_data_log(&self) -> Option<CodeDataLog> {
+        Some(CodeDataLog {
+            prg: self.prg_log.clone()?,
+            chr: self.ppu.chr_log.clone().unwrap_or_default(),
+        })
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
+        let mut offset = (addr - PRG_ROM) as usize;
+        if self.prg_rom.len() == 0x4000 {
+            // mirror if needed
+            offset %= 0x4000;
+        }
+        offset
+    }
+
+    fn read_prg_rom(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_rom_offset(addr)]
+    }
+
+    // $4014: copy a 256 byte CPU page into OAM. The CPU is halted for 513 cycles,
+    // plus one on odd cycles which isn't tracked here
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        let mut data = [0u8; 256];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = self.mem_read(start + i as u16);
+        }
+        self.ppu.write_oam_dma(&data);
+        self.stall_cycles += 513;
+    }
+}
+
+impl Mem for NesBus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+                self.ppu.read_register(mirror_down_addr)
+            }
+            0x4015 => self.apu.read_status() | (self.open_bus & 0b0010_0000),
+            0x4016 => self.joypad1.read() | (self.open_bus & 0b1110_0000),
+            0x4017 => self.joypad2.read() | (self.open_bus & 0b1110_0000),
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            PRG_ROM..=0xFFFF => self.read_prg_rom(addr),
+            _ => self.open_bus,
+        };
+        self.open_bus = data;
+        data
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu.peek_register(addr & 0b0010_0000_0000_0111)
+            }
+            0x4015 => self.apu.peek_status(),
+            0x4016 => self.joypad1.peek(),
+            0x4017 => self.joypad2.peek(),
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            PRG_ROM..=0xFFFF => self.read_prg_rom(addr),
+            _ => self.open_bus,
+        }
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+                self.ppu.write_register(mirror_down_addr, data);
+            }
+            0x4014 => self.oam_dma(data),
+            0x4016 => {
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            0x4000..=0x4013 | 0x4015 | 0x4017 => self.apu.write_register(addr, data),
+            PRG_RAM..=PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize] = data,
+            // NROM has no registers, writes to ROM are ignored
+            _ => {}
+        }
+    }
+}
+
+impl Bus for NesBus {
+    fn tick(&mut self, cycles: usize) {
+        // 3 dots per CPU cycle, or 16 every 5 cycles on PAL
+        let (num, den) = self.ppu.region.dots_per_cpu_cycle();
+        let dots = cycles * num + self.dot_remainder;
+        self.ppu.tick(dots / den);
+        self.dot_remainder = dots % den;
+
+        for _ in 0..cycles {
+            self.apu.tick();
+            if let Some(addr) = self.apu.dmc_sample_request() {
+                let sample = self.mem_read(addr);
+                self.log_prg(addr, PrgFlags::PCM_DATA);
+                self.apu.dmc_fill_sample(sample);
+                // the DMC steals CPU cycles for each sample fetch
+                self.stall_cycles += 4;
+            }
+        }
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        self.ppu.poll_nmi()
+    }
+
+    fn irq(&self) -> bool {
+        self.apu.irq()
+    }
+
+    fn poll_stall_cycles(&mut self) -> usize {
+        std::mem::take(&mut self.stall_cycles)
+    }
+
+    fn poll_frame(&mut self) -> bool {
+        self.ppu.poll_frame()
+    }
+
+    fn region(&self) -> Region {
+        self.ppu.region
+    }
+
+    // From the vblank scanline up to the pre-render line
+    fn in_vblank(&self) -> bool {
+        let region = self.ppu.region;
+        (region.vblank_scanline()..region.scanlines_per_frame() - 1).contains(&self.ppu.scanline)
+    }
+
+    fn log_prg(&mut self, addr: u16, flags: PrgFlags) {
+        if addr < PRG_ROM {
+            return;
+        }
+        let offset = self.prg_rom_offset(addr);
+        if let Some(log) = &mut self.prg_log {
+            cdl::mark_prg(&mut log[offset], addr, flags);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::{test_ines, test_rom};
+    use crate::cdl::ChrFlags;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn test_ram_is_mirrored() {
+        let mut bus = NesBus::new(test_rom(&[])).unwrap();
+        bus.mem_write(0x0012, 0x55);
+
+        assert_eq!(bus.mem_read(0x0812), 0x55);
+        assert_eq!(bus.mem_read(0x1812), 0x55);
+        assert_eq!(bus.mem_peek(0x1012), 0x55);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page_and_stalls_cpu() {
+        let mut bus = NesBus::new(test_rom(&[])).unwrap();
+        for i in 0..=255u8 {
+            bus.mem_write(0x0200 + i as u16, i);
+        }
+        bus.mem_write(0x4014, 0x02);
+
+        assert_eq!(bus.ppu.oam_data[0x10], 0x10);
+        assert_eq!(bus.ppu.oam_data[0xff], 0xff);
+        assert_eq!(bus.poll_stall_cycles(), 513);
+        assert_eq!(bus.poll_stall_cycles(), 0);
+    }
+
+    #[test]
+    fn test_vblank_nmi_once_per_frame() {
+        // LDA #$80; STA $2000; loop: JMP loop; NMI: INC $10; RTI
+        let mut raw = test_ines(&[
+            0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80, 0xe6, 0x10, 0x40,
+        ]);
+        // NMI vector at $FFFA points to $8008
+        raw[16 + 0x3ffa] = 0x08;
+        raw[16 + 0x3ffb] = 0x80;
+
+        let mut cpu = CPU::with_bus(NesBus::new(Rom::new(&raw).unwrap()).unwrap());
+        cpu.reset();
+        for _ in 0..3 {
+            let result = cpu.run_frame();
+            assert_eq!(result.halt, None);
+        }
+        // the NMI of the last frame is taken before the next instruction
+        cpu.step();
+
+        assert_eq!(cpu.mem_read(0x10), 3);
+        assert_eq!(cpu.bus.ppu.frame_count, 2);
+    }
+
+    #[test]
+    fn test_pal_frame_length() {
+        let mut rom = test_rom(&[0x4c, 0x00, 0x80]); // loop: JMP loop
+        rom.region = Some(Region::Pal);
+        let mut cpu = CPU::with_bus(NesBus::new(rom).unwrap());
+        assert_eq!(cpu.bus.region(), Region::Pal);
+        cpu.reset();
+        cpu.run_frame();
+        cpu.bus.apu.take_samples();
+
+        // 341 dots * 312 scanlines / 3.2 dots per cycle, give or take an instruction
+        let cycles = cpu.run_frame().cycles as i64;
+        assert!((cycles - 33248).abs() <= 3, "{}", cycles);
+        // 50 frames per second of audio
+        assert!((cpu.bus.apu.take_samples().len() as i64 - 882).abs() <= 1);
+    }
+
+    #[test]
+    fn test_code_data_log() {
+        // LDA $8010; LDY #0; LDA ($00),Y; JSR $8020; loop: JMP loop
+        let mut code = vec![
+            0xad, 0x10, 0x80, 0xa0, 0x00, 0xb1, 0x00, 0x20, 0x20, 0x80, 0x4c, 0x0a, 0x80,
+        ];
+        code.resize(0x20, 0);
+        code.push(0x60); // $8020: RTS
+        let mut cpu = CPU::with_bus(NesBus::new(test_rom(&code)).unwrap());
+        cpu.bus.start_code_data_log(CodeDataLog::new(0x4000, 0x2000)).unwrap();
+        assert!(cpu.bus.start_code_data_log(CodeDataLog::new(0x8000, 0)).is_err());
+        cpu.reset();
+        // ($00) points at $8011
+        cpu.mem_write(0x00, 0x11);
+        cpu.mem_write(0x01, 0x80);
+        for _ in 0..6 {
+            cpu.step();
+        }
+
+        let log = cpu.bus.code_data_log().unwrap();
+        assert_eq!(log.prg_flags(0x00), PrgFlags::CODE);
+        assert_eq!(log.prg_flags(0x09), PrgFlags::CODE);
+        assert_eq!(log.prg_flags(0x10), PrgFlags::DATA);
+        assert_eq!(log.prg_flags(0x11), PrgFlags::DATA | PrgFlags::INDIRECT_DATA);
+        assert_eq!(log.prg_flags(0x20), PrgFlags::CODE | PrgFlags::JUMP_TARGET);
+        // JMP loop jumps to itself
+        assert_eq!(log.prg_flags(0x0a), PrgFlags::CODE | PrgFlags::JUMP_TARGET);
+        assert_eq!(log.prg_flags(0x0d), PrgFlags::empty());
+        // the reset vector is read as data at $FFFC, in the $E000 window
+        assert_eq!(log.prg[0x3ffc], 0b0000_1110);
+
+        // a visible background draws from the pattern tables
+        cpu.bus.ppu.write_register(0x2001, 0b0000_1000);
+        cpu.run_frame();
+        cpu.run_frame();
+        let log = cpu.bus.code_data_log().unwrap();
+        assert!(log.chr_flags(0) == ChrFlags::DRAWN);
+        assert_eq!(log.coverage().read, 0);
+    }
+
+    #[test]
+    fn test_flat_bus_frames() {
+        let mut bus = FlatBus::new();
+        bus.tick(CYCLES_PER_FRAME - 1);
+        assert!(!bus.poll_frame());
+        bus.tick(1);
+        assert!(bus.poll_frame());
+        assert!(!bus.poll_frame());
+    }
+}
@@ -0,0 +1,263 @@
+// iNES file format:
+// Header (16 bytes) | Trainer (0 or 512 bytes) | PRG ROM (16KB units) | CHR ROM (8KB units)
+//
+// Header:
+// 0-3: "NES" followed by MS-DOS end-of-file (0x1A)
+// 4: Number of 16KB PRG ROM banks
+// 5: Number of 8KB CHR ROM banks
+// 6: Control byte 1 - mirroring, battery, trainer, lower nybble of mapper number
+// 7: Control byte 2 - upper nybble of mapper number, iNES version
+// 8-15: Reserved
+//
+// NES 2.0 (version bits 0b10 in byte 7) reuses the reserved bytes:
+// 8: mapper bits 8-11 (low nybble), submapper (high nybble)
+// 9: upper bits of the PRG (low nybble) and CHR (high nybble) ROM sizes
+// 10: PRG RAM (low nybble) and battery backed PRG NVRAM (high nybble), 64 << n bytes
+// 11: CHR RAM (low nybble), same encoding
+// 12: CPU/PPU timing - 0 NTSC, 1 PAL, 2 multi-region, 3 Dendy
+// 15: default expansion device (low 6 bits)
+
+use crate::region::Region;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+pub const PRG_ROM_PAGE_SIZE: usize = 16384;
+pub const CHR_ROM_PAGE_SIZE: usize = 8192;
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    // Console the cartridge was made for; None when the header doesn't say or the game
+    // runs on any region
+    pub region: Option<Region>,
+    // RAM sizes in bytes. iNES 1.0 headers only have the battery bit, so they get 8KB of
+    // PRG RAM (or NVRAM with a battery) and 8KB of CHR RAM when there is no CHR ROM
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    // NES 2.0 default expansion device: 0 unspecified, 1 standard controllers,
+    // 2 Four Score, 8 Zapper, ...
+    pub input_device: u8,
+    // Whether the submapper, RAM sizes, region and input device came from a NES 2.0
+    // header rather than defaults
+    pub nes2: bool,
+}
+
+impl Rom {
+
+    // Check whether raw bytes start with the iNES tag
+    pub fn is_ines(raw: &[u8]) -> bool {
+        raw.len() >= 16 && raw[0..4] == NES_TAG
+    }
+
+    // Parse an iNES image into PRG/CHR ROM and cartridge settings
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if !Rom::is_ines(raw) {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        // Anything but NES 2.0 is read as iNES 1.0. Old dumping tools signed bytes 7-15
+        // (e.g. "DiskDude!"), so when bytes 12-15 aren't blank byte 7 is junk too, mapper
+        // high nybble included. The game database can put the real mapper back
+        let nes2 = (raw[7] >> 2) & 0b11 == 0b10;
+        let junk = !nes2 && raw[12..16].iter().any(|&byte| byte != 0);
+        let mut mapper = (raw[6] >> 4) as u16;
+        if !junk {
+            mapper |= (raw[7] & 0b1111_0000) as u16;
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let mut prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let mut chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        // iNES 1.0 images often carry junk in bytes 7-15, so only NES 2.0 headers are
+        // trusted for the region
+        let mut region = None;
+        let battery = raw[6] & 0b10 != 0;
+        let mut submapper = 0;
+        let mut prg_ram_size = if battery { 0 } else { 0x2000 };
+        let mut prg_nvram_size = if battery { 0x2000 } else { 0 };
+        let mut chr_ram_size = if chr_rom_size == 0 { 0x2000 } else { 0 };
+        let mut input_device = 0;
+        if nes2 {
+            mapper |= ((raw[8] & 0b1111) as u16) << 8;
+            submapper = raw[8] >> 4;
+            prg_ram_size = nes2_ram_size(raw[10] & 0b1111);
+            prg_nvram_size = nes2_ram_size(raw[10] >> 4);
+            chr_ram_size = nes2_ram_size(raw[11] & 0b1111);
+            input_device = raw[15] & 0b11_1111;
+            prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE)?;
+            chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE)?;
+            region = match raw[12] & 0b11 {
+                0 => Some(Region::Ntsc),
+                1 => Some(Region::Pal),
+                2 => None,
+                _ => Some(Region::Dendy),
+            };
+        }
+
+        let skip_trainer = raw[6] & 0b100 != 0;
+
+        let prg_rom_start = 16 + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(format!(
+                "File is truncated: header declares {} bytes of PRG/CHR ROM but only {} are present",
+                prg_rom_size + chr_rom_size,
+                raw.len().saturating_sub(prg_rom_start)
+            ));
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
+            chr_rom: raw[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec(),
+            mapper,
+            submapper,
+            screen_mirroring,
+            region,
+            prg_ram_size,
+            prg_nvram_size,
+            chr_ram_size,
+            input_device,
+            nes2,
+        })
+    }
+
+    // Number of 16KB PRG ROM banks
+    pub fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_ROM_PAGE_SIZE
+    }
+}
+
+// NES 2.0 ROM size from the iNES size byte and its upper nybble. An upper nybble of $F
+// switches to exponent-multiplier notation: 2^E * (MM*2+1) bytes with lsb = EEEEEEMM
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Result<usize, String> {
+    if msb != 0b1111 {
+        return Ok(((msb as usize) << 8 | lsb as usize) * page_size);
+    }
+    let exponent = (lsb >> 2) as u32;
+    let multiplier = (lsb & 0b11) as usize * 2 + 1;
+    2usize
+        .checked_pow(exponent)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or_else(|| format!("ROM size 2^{} * {} is too large", exponent, multiplier))
+}
+
+// NES 2.0 RAM size from a shift count: none for 0, otherwise 64 << n bytes
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    // iNES image of an NROM cartridge with one PRG and one CHR bank
+    pub fn test_ines(program: &[u8]) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x01, 0x00];
+        raw.resize(16, 0);
+
+        let mut prg = vec![0; PRG_ROM_PAGE_SIZE];
+        prg[..program.len()].copy_from_slice(program);
+        // reset vector at $FFFC (mirrored from $BFFC) points to $8000
+        prg[0x3FFC] = 0x00;
+        prg[0x3FFD] = 0x80;
+
+        raw.extend(prg);
+        raw.extend(vec![0; CHR_ROM_PAGE_SIZE]);
+        raw
+    }
+
+    // Cartridge running `program` from $8000
+    pub fn test_rom(program: &[u8]) -> Rom {
+        Rom::new(&test_ines(program)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let rom = test_rom(&[0xa9, 0x01]);
+
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_rom[0], 0xa9);
+    }
+
+    #[test]
+    fn test_truncated_file_is_rejected() {
+        let raw = test_ines(&[]);
+        assert!(Rom::new(&raw[..raw.len() - 1]).is_err());
+        assert!(Rom::new(&raw[1..]).is_err());
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut raw = test_ines(&[]);
+        raw[7] |= 0b1000;
+        raw[8] = 0x21; // submapper 2, mapper $100
+        raw[12] = 1;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!((rom.mapper, rom.submapper), (0x100, 2));
+        assert_eq!(rom.region, Some(Region::Pal));
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size, rom.chr_ram_size), (0, 0, 0));
+        assert_eq!(rom.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+
+        raw[12] = 2;
+        assert_eq!(Rom::new(&raw).unwrap().region, None);
+        raw[12] = 3;
+        assert_eq!(Rom::new(&raw).unwrap().region, Some(Region::Dendy));
+
+        raw[10] = 0x07; // 8KB PRG RAM
+        raw[11] = 0x09; // 32KB CHR RAM
+        raw[15] = 8;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!((rom.prg_ram_size, rom.chr_ram_size, rom.input_device), (0x2000, 0x8000, 8));
+
+        // iNES 1.0 ignores the bytes
+        let rom = test_rom(&[]);
+        assert_eq!(rom.region, None);
+        assert_eq!((rom.submapper, rom.prg_ram_size, rom.chr_ram_size, rom.nes2), (0, 0x2000, 0, false));
+    }
+
+    #[test]
+    fn test_legacy_header_junk() {
+        let mut raw = test_ines(&[]);
+        raw[6] |= 0x10;
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!((rom.mapper, rom.nes2), (1, false));
+
+        // version bits 01 or 11 with blank bytes 12-15 still count as iNES 1.0
+        raw[7..16].copy_from_slice(&[0x44, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Rom::new(&raw).unwrap().mapper, 0x41);
+    }
+
+    #[test]
+    fn test_nes2_rom_size() {
+        assert_eq!(nes2_rom_size(2, 0, PRG_ROM_PAGE_SIZE), Ok(2 * PRG_ROM_PAGE_SIZE));
+        assert_eq!(nes2_rom_size(0, 1, CHR_ROM_PAGE_SIZE), Ok(256 * CHR_ROM_PAGE_SIZE));
+        // 2^14 * 3
+        assert_eq!(nes2_rom_size(14 << 2 | 1, 0b1111, PRG_ROM_PAGE_SIZE), Ok(49152));
+    }
+}
@@ -0,0 +1,197 @@
+// Code/Data Logger: how each PRG and CHR ROM byte has been accessed, in the FCEUX .cdl
+// format. A .cdl file is one flag byte per PRG ROM byte followed by one per CHR ROM byte.
+//
+// PRG bit 0  executed as code (opcode or operand)
+//     bit 1  read as data
+//     2-3    8KB CPU window of the first access: 0 $8000, 1 $A000, 2 $C000, 3 $E000
+//     bit 4  jump target: reached by a jump, call, taken branch or interrupt (FCEUX's
+//            "indirect code" bit)
+//     bit 5  indirect data, read through a (zp),Y / (zp,X) / (zp) pointer
+//     bit 6  DMC sample data
+// CHR bit 0  drawn by the PPU
+//     bit 1  read by the CPU through $2007
+
+use std::fmt;
+
+bitflags! {
+    pub struct PrgFlags: u8 {
+        const CODE          = 0b0000_0001;
+        const DATA          = 0b0000_0010;
+        const JUMP_TARGET   = 0b0001_0000;
+        const INDIRECT_DATA = 0b0010_0000;
+        const PCM_DATA      = 0b0100_0000;
+    }
+}
+
+bitflags! {
+    pub struct ChrFlags: u8 {
+        const DRAWN = 0b0000_0001;
+        const READ  = 0b0000_0010;
+    }
+}
+
+const BANK_BITS: u8 = 0b0000_1100;
+
+// Record an access of `flags` at CPU address `addr` in the log entry of a PRG byte
+pub fn mark_prg(entry: &mut u8, addr: u16, flags: PrgFlags) {
+    if *entry == 0 {
+        *entry = ((addr >> 11) as u8) & BANK_BITS;
+    }
+    *entry |= flags.bits();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+}
+
+impl CodeDataLog {
+    // An empty log for a cartridge; CHR RAM has no CHR entries
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+        }
+    }
+
+    pub fn from_bytes(data: &[u8], prg_size: usize, chr_size: usize) -> Result<Self, String> {
+        if data.len() != prg_size + chr_size {
+            return Err(format!(
+                "code/data log of {} bytes doesn't match {} bytes of PRG and {} of CHR ROM",
+                data.len(),
+                prg_size,
+                chr_size
+            ));
+        }
+        Ok(CodeDataLog {
+            prg: data[..prg_size].to_vec(),
+            chr: data[prg_size..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    // Load `path`, or start an empty log if it doesn't exist yet, so repeated sessions
+    // accumulate into the same file
+    pub fn load_or_new(path: &str, prg_size: usize, chr_size: usize) -> Result<Self, String> {
+        match std::fs::read(path) {
+            Ok(data) => CodeDataLog::from_bytes(&data, prg_size, chr_size).map_err(|err| format!("{}: {}", path, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(CodeDataLog::new(prg_size, chr_size)),
+            Err(err) => Err(format!("{}: {}", path, err)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_bytes()).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn prg_flags(&self, offset: usize) -> PrgFlags {
+        PrgFlags::from_bits_truncate(self.prg[offset])
+    }
+
+    pub fn chr_flags(&self, offset: usize) -> ChrFlags {
+        ChrFlags::from_bits_truncate(self.chr[offset])
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        let prg_count = |flags: PrgFlags| self.prg.iter().filter(|&&entry| entry & flags.bits() != 0).count();
+        let chr_count = |flags: ChrFlags| self.chr.iter().filter(|&&entry| entry & flags.bits() != 0).count();
+        Coverage {
+            prg_size: self.prg.len(),
+            code: prg_count(PrgFlags::CODE),
+            data: prg_count(PrgFlags::DATA | PrgFlags::PCM_DATA),
+            prg_unused: self.prg.iter().filter(|&&entry| entry == 0).count(),
+            chr_size: self.chr.len(),
+            drawn: chr_count(ChrFlags::DRAWN),
+            read: chr_count(ChrFlags::READ),
+            chr_unused: self.chr.iter().filter(|&&entry| entry == 0).count(),
+        }
+    }
+}
+
+// Byte counts of a log. A byte used both as code and data counts towards both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage {
+    pub prg_size: usize,
+    pub code: usize,
+    pub data: usize,
+    pub prg_unused: usize,
+    pub chr_size: usize,
+    pub drawn: usize,
+    pub read: usize,
+    pub chr_unused: usize,
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "PRG {} bytes: {} code ({:.1}%), {} data ({:.1}%), {} unused ({:.1}%)",
+            self.prg_size,
+            self.code,
+            percent(self.code, self.prg_size),
+            self.data,
+            percent(self.data, self.prg_size),
+            self.prg_unused,
+            percent(self.prg_unused, self.prg_size)
+        )?;
+        write!(
+            f,
+            "CHR {} bytes: {} drawn ({:.1}%), {} read ({:.1}%), {} unused ({:.1}%)",
+            self.chr_size,
+            self.drawn,
+            percent(self.drawn, self.chr_size),
+            self.read,
+            percent(self.read, self.chr_size),
+            self.chr_unused,
+            percent(self.chr_unused, self.chr_size)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mark_prg_keeps_first_bank() {
+        let mut entry = 0;
+        mark_prg(&mut entry, 0xc123, PrgFlags::CODE);
+        mark_prg(&mut entry, 0x8123, PrgFlags::DATA);
+        assert_eq!(entry, 0b0000_1011);
+
+        let mut entry = 0;
+        mark_prg(&mut entry, 0xe000, PrgFlags::DATA | PrgFlags::INDIRECT_DATA);
+        assert_eq!(entry, 0b0010_1110);
+    }
+
+    #[test]
+    fn test_file_round_trip_and_coverage() {
+        let mut log = CodeDataLog::new(4, 2);
+        log.prg[0] = PrgFlags::CODE.bits();
+        log.prg[1] = (PrgFlags::CODE | PrgFlags::DATA).bits();
+        log.prg[2] = PrgFlags::PCM_DATA.bits();
+        log.chr[1] = ChrFlags::DRAWN.bits();
+
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 6);
+        assert_eq!(CodeDataLog::from_bytes(&bytes, 4, 2).unwrap(), log);
+        assert!(CodeDataLog::from_bytes(&bytes, 4, 0).is_err());
+
+        let coverage = log.coverage();
+        assert_eq!((coverage.code, coverage.data, coverage.prg_unused), (2, 2, 1));
+        assert_eq!((coverage.drawn, coverage.read, coverage.chr_unused), (1, 0, 1));
+        assert!(coverage.to_string().starts_with("PRG 4 bytes: 2 code (50.0%), 2 data (50.0%), 1 unused (25.0%)\n"));
+    }
+}
@@ -0,0 +1,420 @@
+// Cheat codes, applied to what the CPU reads, and a RAM search for finding new ones.
+//
+// Game Genie codes patch a byte of ROM space ($8000-$FFFF). Six letter codes always
+// replace it; eight letter codes only when the ROM holds their compare value, so they
+// leave other banks alone. Freeze codes (Pro Action Replay style `AAAA:VV`, or `AAAAVV`
+// when that isn't also a Game Genie code) pin a byte of RAM: reads return the value, and
+// it is written back every frame so DMA and the PPU see it too.
+//
+// A cheat file has one code per line, then an optional description. A leading `-`
+// keeps a code disabled and `#` starts a comment:
+//
+//   SXIOPO        infinite lives
+//   -0075:09      start in world 8
+
+use std::fmt;
+use std::str::FromStr;
+
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    GameGenie { addr: u16, value: u8, compare: Option<u8> },
+    Freeze { addr: u16, value: u8 },
+}
+
+fn decode_game_genie(code: &str) -> Option<Code> {
+    let n: Vec<u16> = code
+        .chars()
+        .map(|c| GAME_GENIE_LETTERS.find(c.to_ascii_uppercase()).map(|n| n as u16))
+        .collect::<Option<_>>()?;
+    if n.len() != 6 && n.len() != 8 {
+        return None;
+    }
+
+    let addr = 0x8000
+        | ((n[3] & 7) << 12)
+        | ((n[5] & 7) << 8)
+        | ((n[4] & 8) << 8)
+        | ((n[2] & 7) << 4)
+        | ((n[1] & 8) << 4)
+        | (n[4] & 7)
+        | (n[3] & 8);
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    let (value, compare) = if n.len() == 6 {
+        (value | (n[5] & 8), None)
+    } else {
+        let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+        (value | (n[7] & 8), Some(compare as u8))
+    };
+    Some(Code::GameGenie { addr, value: value as u8, compare })
+}
+
+fn encode_game_genie(addr: u16, value: u8, compare: Option<u8>) -> String {
+    let (addr, value) = (addr as usize, value as usize);
+    let mut n = vec![
+        (value & 7) | ((value >> 4) & 8),
+        ((value >> 4) & 7) | ((addr >> 4) & 8),
+        ((addr >> 4) & 7) | if compare.is_some() { 8 } else { 0 },
+        ((addr >> 12) & 7) | (addr & 8),
+        (addr & 7) | ((addr >> 8) & 8),
+        (addr >> 8) & 7,
+    ];
+    match compare {
+        None => n[5] |= value & 8,
+        Some(compare) => {
+            let compare = compare as usize;
+            n[5] |= compare & 8;
+            n.push((compare & 7) | ((compare >> 4) & 8));
+            n.push(((compare >> 4) & 7) | (value & 8));
+        }
+    }
+    n.iter().map(|&i| GAME_GENIE_LETTERS.as_bytes()[i] as char).collect()
+}
+
+fn parse_freeze(code: &str) -> Option<Code> {
+    let (addr, value) = match code.split_once(':') {
+        Some(parts) => parts,
+        None if code.len() == 6 => code.split_at(4),
+        None => return None,
+    };
+    let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()?;
+    let value = u8::from_str_radix(value.trim_start_matches('$'), 16).ok()?;
+    Some(Code::Freeze { addr, value })
+}
+
+impl FromStr for Code {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_game_genie(s)
+            .or_else(|| parse_freeze(s))
+            .ok_or_else(|| format!("invalid cheat code: {} (expected a Game Genie code or AAAA:VV)", s))
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Code::GameGenie { addr, value, compare } => f.write_str(&encode_game_genie(addr, value, compare)),
+            Code::Freeze { addr, value } => write!(f, "{:04X}:{:02X}", addr, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: Code,
+    pub description: String,
+    pub enabled: bool,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.enabled { "" } else { "-" }, self.code)?;
+        if !self.description.is_empty() {
+            write!(f, " {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+    // switches every cheat off without losing which ones are enabled
+    enabled: bool,
+    // some cheat is in effect, so reads need patching
+    active: bool,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats {
+            list: Vec::new(),
+            enabled: true,
+            active: false,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut cheats = Cheats::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (code, description) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let code = code.parse().map_err(|err| format!("line {}: {}", number + 1, err))?;
+            let index = cheats.add(code, description.trim());
+            cheats.set_enabled(index, enabled);
+        }
+        Ok(cheats)
+    }
+
+    // Load a cheat file, or start an empty list if it doesn't exist yet
+    pub fn load_or_new(path: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Cheats::parse(&text).map_err(|err| format!("{}: {}", path, err)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Cheats::new()),
+            Err(err) => Err(format!("{}: {}", path, err)),
+        }
+    }
+
+    // The cheat file kept next to a ROM, game.nes -> game.cht
+    pub fn file_for(rom_path: &str) -> String {
+        std::path::Path::new(rom_path).with_extension("cht").to_string_lossy().into_owned()
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_string()).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    // Returns the index of the new cheat
+    pub fn add(&mut self, code: Code, description: &str) -> usize {
+        self.list.push(Cheat {
+            code,
+            description: description.to_string(),
+            enabled: true,
+        });
+        self.update();
+        self.list.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.list.len() {
+            return None;
+        }
+        let cheat = self.list.remove(index);
+        self.update();
+        Some(cheat)
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.list.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                self.update();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // The master switch, e.g. for a hotkey
+    pub fn set_all_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        self.active = self.enabled && self.list.iter().any(|cheat| cheat.enabled);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn in_effect(&self) -> impl Iterator<Item = &Code> {
+        self.list.iter().filter(|cheat| self.enabled && cheat.enabled).map(|cheat| &cheat.code)
+    }
+
+    // The byte the CPU sees when memory at `addr` holds `data`
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {
+        for code in self.in_effect() {
+            match *code {
+                Code::GameGenie { addr: target, value, compare }
+                    if target == addr && compare.is_none_or(|compare| compare == data) =>
+                {
+                    return value
+                }
+                Code::Freeze { addr: target, value } if target == addr => return value,
+                _ => {}
+            }
+        }
+        data
+    }
+
+    // (address, value) of the freeze codes in effect
+    pub fn freezes(&self) -> Vec<(u16, u8)> {
+        self.in_effect()
+            .filter_map(|code| match *code {
+                Code::Freeze { addr, value } => Some((addr, value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Default for Cheats {
+    fn default() -> Self {
+        Cheats::new()
+    }
+}
+
+// The cheat file text
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cheat in &self.list {
+            writeln!(f, "{}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+// How a RAM search narrows its candidates: against the value each byte had at the
+// previous search, or against a given value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchFilter {
+    Unchanged,
+    Changed,
+    Greater,
+    Less,
+    Value(u8),
+}
+
+impl FromStr for SearchFilter {
+    type Err = String;
+
+    // = != > < or a hex value
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let filter = match s {
+            "=" | "==" => SearchFilter::Unchanged,
+            "!=" => SearchFilter::Changed,
+            ">" => SearchFilter::Greater,
+            "<" => SearchFilter::Less,
+            _ => {
+                let digits = s.trim_start_matches('#').trim_start_matches('$');
+                let value = u8::from_str_radix(digits, 16).map_err(|_| format!("invalid search: {} (expected = != > < or a byte)", s))?;
+                SearchFilter::Value(value)
+            }
+        };
+        Ok(filter)
+    }
+}
+
+// Finds the RAM address holding some game state by elimination: start with every byte,
+// then after each bit of play keep the ones that changed as the state did
+pub struct RamSearch {
+    candidates: Vec<u16>,
+    previous: Vec<u8>,
+}
+
+impl RamSearch {
+    // Every byte of `ram` (starting at address 0) is a candidate
+    pub fn new(ram: &[u8]) -> Self {
+        RamSearch {
+            candidates: (0..ram.len() as u16).collect(),
+            previous: ram.to_vec(),
+        }
+    }
+
+    // Keep the candidates passing `filter` and remember the current values for the next
+    // search. Returns how many are left
+    pub fn filter(&mut self, ram: &[u8], filter: SearchFilter) -> usize {
+        let previous = &self.previous;
+        self.candidates.retain(|&addr| {
+            let (before, now) = (previous[addr as usize], ram[addr as usize]);
+            match filter {
+                SearchFilter::Unchanged => now == before,
+                SearchFilter::Changed => now != before,
+                SearchFilter::Greater => now > before,
+                SearchFilter::Less => now < before,
+                SearchFilter::Value(value) => now == value,
+            }
+        });
+        self.previous = ram.to_vec();
+        self.candidates.len()
+    }
+
+    // (address, value at the last search) of the remaining candidates
+    pub fn candidates(&self) -> Vec<(u16, u8)> {
+        self.candidates.iter().map(|&addr| (addr, self.previous[addr as usize])).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_game_genie_codes() {
+        // Super Mario Bros. infinite lives
+        let code: Code = "SXIOPO".parse().unwrap();
+        assert_eq!(code, Code::GameGenie { addr: 0x91d9, value: 0xad, compare: None });
+        assert_eq!(code.to_string(), "SXIOPO");
+
+        let code = Code::GameGenie { addr: 0xd1dd, value: 0x14, compare: Some(0xc5) };
+        let text = code.to_string();
+        assert_eq!(text.len(), 8);
+        assert_eq!(text.parse::<Code>().unwrap(), code);
+        assert_eq!("gosszzvk".parse::<Code>().unwrap(), "GOSSZZVK".parse::<Code>().unwrap());
+
+        assert!("SXIOP".parse::<Code>().is_err());
+    }
+
+    #[test]
+    fn test_freeze_codes() {
+        assert_eq!("0075:09".parse::<Code>().unwrap(), Code::Freeze { addr: 0x75, value: 9 });
+        assert_eq!("07a109".parse::<Code>().unwrap(), Code::Freeze { addr: 0x7a1, value: 9 });
+        assert_eq!(Code::Freeze { addr: 0x75, value: 9 }.to_string(), "0075:09");
+        assert!("0075:100".parse::<Code>().is_err());
+    }
+
+    #[test]
+    fn test_apply_and_toggle() {
+        let mut cheats = Cheats::parse("SXIOPO lives\n-0075:09 world 8 # comment\n\n").unwrap();
+        assert_eq!(cheats.list().len(), 2);
+        assert_eq!(cheats.apply(0x91d9, 0xce), 0xad);
+        assert_eq!(cheats.apply(0x0075, 0), 0);
+        assert!(cheats.freezes().is_empty());
+
+        cheats.set_enabled(1, true);
+        assert_eq!(cheats.apply(0x0075, 0), 9);
+        assert_eq!(cheats.freezes(), vec![(0x0075, 9)]);
+
+        cheats.set_all_enabled(false);
+        assert!(!cheats.is_active());
+        assert_eq!(cheats.apply(0x91d9, 0xce), 0xce);
+
+        let compare = Code::GameGenie { addr: 0x8000, value: 1, compare: Some(2) };
+        let mut cheats = Cheats::new();
+        cheats.add(compare, "");
+        assert_eq!(cheats.apply(0x8000, 2), 1);
+        assert_eq!(cheats.apply(0x8000, 3), 3);
+
+        assert_eq!(Cheats::parse("SXIOPO lives\n-0075:09 world 8\n").unwrap().to_string(), "SXIOPO lives\n-0075:09 world 8\n");
+        assert!(Cheats::parse("\nXYZ").unwrap_err().starts_with("line 2: "));
+        assert_eq!(Cheats::file_for("roms/smb.nes"), "roms/smb.cht");
+    }
+
+    #[test]
+    fn test_ram_search() {
+        let mut ram = vec![0u8; 8];
+        let mut search = RamSearch::new(&ram);
+        ram[2] = 3;
+        ram[5] = 1;
+        assert_eq!(search.filter(&ram, SearchFilter::Changed), 2);
+        ram[2] = 2;
+        ram[5] = 2;
+        assert_eq!(search.filter(&ram, SearchFilter::Greater), 1);
+        assert_eq!(search.candidates(), vec![(5, 2)]);
+        assert_eq!(search.filter(&ram, SearchFilter::Value(3)), 0);
+
+        assert_eq!("!=".parse::<SearchFilter>(), Ok(SearchFilter::Changed));
+        assert_eq!("$1f".parse::<SearchFilter>(), Ok(SearchFilter::Value(0x1f)));
+        assert!("big".parse::<SearchFilter>().is_err());
+    }
+}
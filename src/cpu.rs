@@ -4,7 +4,11 @@
 // Address packed in big-endian: 80 00
 // Address Packed in little-endian: 00 80
 
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use crate::bus::{Bus, FlatBus};
+use crate::cdl::PrgFlags;
+use crate::cheats::Cheats;
 use crate::opcodes;
 
 bitflags! {
@@ -23,18 +27,201 @@ bitflags! {
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
 
-// Defining a CPU
-pub struct CPU {
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+// NTSC: 341 PPU dots * 262 scanlines / 3 PPU dots per CPU cycle
+pub const CYCLES_PER_FRAME: usize = 29781;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+// KIL/JAM: unofficial opcodes that lock up the NMOS 6502 until reset
+const JAM_OPCODES: [u8; 12] = [
+    0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+];
+
+// Faults raised while decoding or executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    IllegalOpcode { opcode: u8, address: u16 },
+    Jam { opcode: u8, address: u16 },
+    UnsupportedAddressingMode { opcode: u8, address: u16, mode: AddressingMode },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { opcode, address } => {
+                write!(f, "illegal opcode ${:02X} at ${:04X}", opcode, address)
+            }
+            CpuError::Jam { opcode, address } => {
+                write!(f, "CPU jammed by KIL opcode ${:02X} at ${:04X}", opcode, address)
+            }
+            CpuError::UnsupportedAddressingMode { opcode, address, mode } => write!(
+                f,
+                "opcode ${:02X} at ${:04X} does not support addressing mode {:?}",
+                opcode, address, mode
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+// Kind of memory access a watchpoint reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Watched address range `start..=end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: Access, addr: u16) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+        };
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+// First watched access made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub access: Access,
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "read of ${:02X} from ${:04X}", self.value, self.addr),
+            Access::Write => write!(f, "write of ${:02X} to ${:04X}", self.value, self.addr),
+        }
+    }
+}
+
+// Why the CPU stopped executing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    Brk,
+    Breakpoint(u16),
+    // The instruction completed after touching watched memory
+    Watchpoint(WatchHit),
+    Error(CpuError),
+}
+
+impl From<CpuError> for HaltReason {
+    fn from(err: CpuError) -> Self {
+        HaltReason::Error(err)
+    }
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HaltReason::Brk => write!(f, "BRK"),
+            HaltReason::Breakpoint(addr) => write!(f, "breakpoint at ${:04X}", addr),
+            HaltReason::Watchpoint(hit) => write!(f, "watchpoint hit by {}", hit),
+            HaltReason::Error(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+// What happened during a single call to CPU::step()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    pub opcode: u8,
+    pub address: u16,
+    pub cycles: usize,
+    pub interrupt: Option<Interrupt>,
+    pub halt: Option<HaltReason>,
+}
+
+// What happened during a call to CPU::run_for_cycles() / CPU::run_frame()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunResult {
+    pub cycles: usize,
+    pub halt: Option<HaltReason>,
+}
+
+// Which member of the 6502 family is emulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+    // Ricoh 2A03 used in the NES: an NMOS 6502 with BCD arithmetic removed
+    #[default]
+    Nes2A03,
+    // Stock NMOS 6502: BCD arithmetic, JMP ($xxFF) page wrap bug, KIL opcodes jam
+    Nmos6502,
+    // WDC/Rockwell 65C02: extra instructions, (zp) addressing, fixed JMP indirect
+    Cmos65C02,
+}
+
+impl CpuVariant {
+    pub fn has_decimal_mode(self) -> bool {
+        self != CpuVariant::Nes2A03
+    }
+
+    pub fn is_cmos(self) -> bool {
+        self == CpuVariant::Cmos65C02
+    }
+}
+
+impl std::str::FromStr for CpuVariant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "2a03" | "nes" => Ok(CpuVariant::Nes2A03),
+            "6502" | "nmos" => Ok(CpuVariant::Nmos6502),
+            "65c02" | "cmos" => Ok(CpuVariant::Cmos65C02),
+            _ => Err(format!("unknown CPU variant: {} (expected 2a03, 6502 or 65c02)", s)),
+        }
+    }
+}
+
+// Defining a CPU, wired to a flat 64KB RAM unless another bus is given
+pub struct CPU<B: Bus = FlatBus> {
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: CpuFlags,
     pub program_counter: u16, 
     pub stack_pointer: u8,
-    memory: [u8; 0xFFFF]
+    pub cycles: usize,
+    pub variant: CpuVariant,
+    // Stop execution on BRK instead of taking the IRQ/BRK vector
+    pub halt_on_brk: bool,
+    nmi_pending: bool,
+    irq_line: bool,
+    breakpoints: HashSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+    // Bytes of the instruction being executed, whose fetch doesn't count as a read
+    fetch_start: u16,
+    fetch_len: u16,
+    // The instruction has followed a (zp) pointer, so its data read is indirect
+    indirect: bool,
+    // Game Genie and freeze codes patching what the CPU reads
+    pub cheats: Cheats,
+    pub bus: B,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Immediate,
@@ -46,42 +233,125 @@ pub enum AddressingMode {
     Absolute_Y,
     Indirect_X,
     Indirect_Y,
+    Indirect,
+    Relative,
+    Accumulator,
+    // 65C02 only
+    ZeroPage_Indirect,
+    Indirect_Absolute_X,
+    ZeroPage_Relative,
     NoneAddressing,
 }
 
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    // Read without side effects (e.g. without clearing PPUSTATUS) for tracers and debuggers
+    fn mem_peek(&self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8); 
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16; 
-        (hi << 8) | (lo as u16)
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16; 
+        (hi << 8) | lo
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8; 
         let lo = (data & 0xff) as u8; 
         self.mem_write(pos, lo); 
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
     }
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+// Watchpoints and cheats are only looked at when some are set, so plain runs just pay
+// for an empty check per access. Reads other than instruction fetches are logged as data
+impl<B: Bus> Mem for CPU<B> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let mut data = self.bus.mem_read(addr);
+        if self.cheats.is_active() {
+            data = self.cheats.apply(addr, data);
+        }
+        if addr.wrapping_sub(self.fetch_start) >= self.fetch_len {
+            if !self.watchpoints.is_empty() {
+                self.watch(Access::Read, addr, data);
+            }
+            let flags = if self.indirect { PrgFlags::DATA | PrgFlags::INDIRECT_DATA } else { PrgFlags::DATA };
+            self.bus.log_prg(addr, flags);
+        }
+        data
+    }
+
+    fn mem_peek(&self, addr: u16) -> u8 {
+        let data = self.bus.mem_peek(addr);
+        if self.cheats.is_active() {
+            return self.cheats.apply(addr, data);
+        }
+        data
     }
 
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        if !self.watchpoints.is_empty() {
+            self.watch(Access::Write, addr, data);
+        }
+        self.bus.mem_write(addr, data);
     }
 }
 
-impl CPU {
+impl CPU<FlatBus> {
 
-    // Create a new CPU
+    // Create a new CPU with 64KB of RAM
     pub fn new() -> Self {
+        CPU::with_bus(FlatBus::new())
+    }
+
+    // Create a new CPU of the given 6502 variant with 64KB of RAM
+    pub fn with_variant(variant: CpuVariant) -> Self {
+        let mut cpu = CPU::new();
+        cpu.variant = variant;
+        cpu
+    }
+
+    // Load instructions from a Vector and place them in correct memory locations 
+    // pub fn load(&mut self, program: Vec<u8>) {
+    //     self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
+    //     self.mem_write_u16(0xFFFC, 0x8000)
+    // }
+
+    pub fn load(&mut self, program: Vec<u8>) {
+        self.bus.load_at(0x0600, &program).unwrap();
+        self.mem_write_u16(0xFFFC, 0x0600);
+    }
+
+    // Copy a raw image into memory at `addr`, e.g. a full 64KB image at $0000
+    pub fn load_at(&mut self, addr: u16, image: &[u8]) -> Result<(), String> {
+        self.bus.load_at(addr, image)
+    }
+
+    // Map NROM style PRG ROM into $8000-$FFFF; a single 16KB bank is mirrored into $C000
+    pub fn load_prg_rom(&mut self, prg_rom: &[u8]) -> Result<(), String> {
+        self.bus.load_prg_rom(prg_rom)
+    }
+
+    // Load instructions from a Vector, reset the state of the CPU and run it
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> HaltReason {
+        self.load(program);
+        self.reset();
+        self.run()
+    }
+}
+
+impl Default for CPU<FlatBus> {
+    fn default() -> Self {
+        CPU::new()
+    }
+}
+
+impl<B: Bus> CPU<B> {
+
+    // Create a new CPU wired to `bus`
+    pub fn with_bus(bus: B) -> Self {
         CPU {
             register_a: 0,
             register_x: 0,
@@ -89,13 +359,24 @@ impl CPU {
             stack_pointer: STACK_RESET,
             program_counter: 0,
             status: CpuFlags::from_bits_truncate(0b100100),
-            memory: [0; 0xFFFF], // Program ROM
+            cycles: 0,
+            variant: CpuVariant::Nes2A03,
+            halt_on_brk: true,
+            nmi_pending: false,
+            irq_line: false,
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            fetch_start: 0,
+            fetch_len: 0,
+            indirect: false,
+            cheats: Cheats::new(),
+            bus,
         }
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        
-        match mode {
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> Result<u16, CpuError> {
+        let addr = match mode {
             // Value is directly given: LAD #$10
             AddressingMode::Immediate => self.program_counter,
 
@@ -110,81 +391,111 @@ impl CPU {
             // MOV AL, [0x10 + DX]  ; Load the value from (0x10 + X) in zero page into AL 
             AddressingMode::ZeroPage_X => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
+                
+                pos.wrapping_add(self.register_x) as u16
             }
             
             // MOV AL, [0x10 + DY]  ; Load the value from (0x10 + X) in zero page into AL
             AddressingMode::ZeroPage_Y => {
                 let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
+                
+                pos.wrapping_add(self.register_y) as u16
             }
             
             // MOV AL, [0x2000 + DX]  ; Load the value from (0x2000 + X) into AL
             AddressingMode::Absolute_X => {
                 let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                addr
+                
+                base.wrapping_add(self.register_x as u16)
             }
             
             // MOV AL, [0x2000 + DY]  ; Load the value from (0x2000 + Y) into AL
             AddressingMode::Absolute_Y => {
                 let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                addr
+                
+                base.wrapping_add(self.register_y as u16)
             }
             
             // Read the memory address from a given address as operand + offset stored on X
             AddressingMode::Indirect_X => {
                 let base = self.mem_read(self.program_counter);
 
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
+                let ptr: u8 = base.wrapping_add(self.register_x);
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+                self.indirect = true;
                 (hi as u16) << 8 | (lo as u16)
             }
 
-            // Read the memory address from a given address as operand + offset stored in Y 
+            // Read the memory address from a given zero page address, then add offset stored in Y
             AddressingMode::Indirect_Y => {
                 let base = self.mem_read(self.program_counter);
 
-                let ptr: u8 = (base as u8).wrapping_add(self.register_y);
+                let lo = self.mem_read(base as u16);
+                let hi = self.mem_read(base.wrapping_add(1) as u16);
+                let deref_base = (hi as u16) << 8 | (lo as u16);
+                self.indirect = true;
+                deref_base.wrapping_add(self.register_y as u16)
+            }
+
+            // 65C02: read the memory address from a given zero page address
+            AddressingMode::ZeroPage_Indirect => {
+                let ptr = self.mem_read(self.program_counter);
+
                 let lo = self.mem_read(ptr as u16);
                 let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16) 
+                self.indirect = true;
+                (hi as u16) << 8 | (lo as u16)
             }
 
-            AddressingMode::NoneAddressing => {
-                panic!("mode {:?} is not supported", mode);
+            // JMP ($xxxx), branches and accumulator ops are resolved by the instruction itself
+            AddressingMode::Indirect
+            | AddressingMode::Indirect_Absolute_X
+            | AddressingMode::Relative
+            | AddressingMode::ZeroPage_Relative
+            | AddressingMode::Accumulator
+            | AddressingMode::NoneAddressing => {
+                // program_counter already points past the opcode byte
+                let address = self.program_counter.wrapping_sub(1);
+                return Err(CpuError::UnsupportedAddressingMode {
+                    opcode: self.mem_peek(address),
+                    address,
+                    mode: *mode,
+                });
             }
-        }   
+        };
+
+        Ok(addr)
     }
 
-    fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ldy(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
         self.register_y = data; 
         self.update_zero_and_negative_flags(self.register_y);
+        Ok(())
     }
 
-    fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ldx(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
         self.register_x = data;
         self.update_zero_and_negative_flags(self.register_x);
+        Ok(())
     }
 
     // Function for 0xA9 Opscode - Load Value into Accumulator (A)
-    fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
+    fn lda(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let value = self.mem_read(addr);
         self.set_register_a(value);
+        Ok(())
     }
 
-    fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn sta(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         self.mem_write(addr, self.register_a);
+        Ok(())
     }
     
     fn set_register_a(&mut self, value: u8) {
@@ -192,22 +503,25 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_a);
     }
 
-    fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn and(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr); 
-        self.set_register_a(data & self.register_a); 
+        self.set_register_a(data & self.register_a);
+        Ok(())
     }
     
-    fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn eor(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
         self.set_register_a(data ^ self.register_a);
+        Ok(())
     }
 
-    fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn ora(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr); 
         self.set_register_a(data | self.register_a);
+        Ok(())
     }
 
     fn tax(&mut self) {
@@ -224,28 +538,13 @@ impl CPU {
         self.register_y = 0;
         self.stack_pointer = STACK_RESET;
         self.status = CpuFlags::from_bits_truncate(0b100100);
+        self.cycles = 7;
+        self.nmi_pending = false;
+        self.irq_line = false;
 
         self.program_counter = self.mem_read_u16(0xFFFC);
     }
 
-    // Load instructions from a Vector and place them in correct memory locations 
-    // pub fn load(&mut self, program: Vec<u8>) {
-    //     self.memory[0x8000 .. (0x8000 + program.len())].copy_from_slice(&program[..]);
-    //     self.mem_write_u16(0xFFFC, 0x8000)
-    // }
-
-    pub fn load(&mut self, program: Vec<u8>) {
-        self.memory[0x0600..(0x0600 + program.len())].copy_from_slice(&program[..]);
-        self.mem_write_u16(0xFFFC, 0x0600);
-    }
-
-    // Load instructions from a Vector, reset the state of the CPU and run it
-    pub fn load_and_run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.run()
-    }
-   
     // Function for 0xE8 Opscode - Increment value of X
     fn inx(&mut self) {
         self.register_x = self.register_x.wrapping_add(1);
@@ -293,25 +592,98 @@ impl CPU {
         self.set_register_a(result);
     }
 
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(&mode);
+    fn decimal_active(&self) -> bool {
+        self.variant.has_decimal_mode() && self.status.contains(CpuFlags::DECIMAL_MODE)
+    }
+
+    // The 65C02 spends an extra cycle in decimal mode to make N and Z valid
+    fn fix_decimal_flags_65c02(&mut self) {
+        if self.variant.is_cmos() {
+            self.update_zero_and_negative_flags(self.register_a);
+            self.cycles += 1;
+        }
+    }
+
+    // NMOS 6502 decimal mode ADC: Z comes from the binary sum, N and V from the
+    // intermediate result before the high nibble is adjusted
+    fn add_to_register_a_decimal(&mut self, data: u8) {
+        let a = self.register_a as u16;
+        let m = data as u16;
+        let carry_in = if self.status.contains(CpuFlags::CARRY) { 1 } else { 0 };
+
+        let binary = (a + m + carry_in) as u8;
+
+        let mut lo = (a & 0x0f) + (m & 0x0f) + carry_in;
+        let mut hi = (a & 0xf0) + (m & 0xf0);
+        if lo > 0x09 {
+            lo += 0x06;
+            hi += 0x10;
+        }
+
+        self.status.set(CpuFlags::ZERO, binary == 0);
+        self.status.set(CpuFlags::NEGATIV, hi & 0x80 != 0);
+        self.status.set(CpuFlags::OVERFLOW, !(a ^ m) & (a ^ hi) & 0x80 != 0);
+
+        if hi > 0x90 {
+            hi += 0x60;
+        }
+        self.status.set(CpuFlags::CARRY, hi > 0xff);
+
+        self.register_a = ((lo & 0x0f) | (hi & 0xf0)) as u8;
+        self.fix_decimal_flags_65c02();
+    }
+
+    // NMOS 6502 decimal mode SBC: all flags come from the binary subtraction
+    fn sub_from_register_a_decimal(&mut self, data: u8) {
+        let a = self.register_a as i16;
+        let m = data as i16;
+        let borrow = if self.status.contains(CpuFlags::CARRY) { 0 } else { 1 };
+
+        self.add_to_register_a(!data);
+
+        let mut lo = (a & 0x0f) - (m & 0x0f) - borrow;
+        let mut hi = (a & 0xf0) - (m & 0xf0);
+        if lo & 0x10 != 0 {
+            lo -= 0x06;
+            hi -= 0x01;
+        }
+        if hi & 0x0100 != 0 {
+            hi -= 0x60;
+        }
+
+        self.register_a = ((lo & 0x0f) | (hi & 0xf0)) as u8;
+        self.fix_decimal_flags_65c02();
+    }
+
+    fn sbc(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
-        self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        if self.decimal_active() {
+            self.sub_from_register_a_decimal(data);
+        } else {
+            self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        }
+        Ok(())
     }
 
-    fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn adc(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let value = self.mem_read(addr);
-        self.add_to_register_a(value);
+        if self.decimal_active() {
+            self.add_to_register_a_decimal(value);
+        } else {
+            self.add_to_register_a(value);
+        }
+        Ok(())
     }
 
     fn stack_pop(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
-        return self.mem_read((STACK as u16) + self.stack_pointer as u16)
+        self.mem_read(STACK + self.stack_pointer as u16)
     }
 
     fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
+        self.mem_write(STACK + self.stack_pointer as u16, data);
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
@@ -326,7 +698,7 @@ impl CPU {
         let lo = self.stack_pop() as u16;
         let hi = self.stack_pop() as u16; 
         
-        return hi << 8 | lo
+        hi << 8 | lo
     }
 
     fn asl_accumulator(&mut self) {
@@ -336,22 +708,22 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         self.set_register_a(data);
     }
 
-    fn asl(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+    fn asl(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         if data >> 7 == 1 {
             self.set_carry_flag();
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data
+        Ok(data)
     }
 
     fn lsr_accumulator(&mut self) {
@@ -361,26 +733,26 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         self.set_register_a(data);
     }
     
-    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+    fn lsr(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         if data & 1 == 1 {
             self.set_carry_flag();
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1; 
+        data >>= 1; 
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data
+        Ok(data)
     }
 
-    fn rol(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+    fn rol(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         let old_carry = self.status.contains(CpuFlags::CARRY); 
         if data >> 7 == 1 {
@@ -388,13 +760,13 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         if old_carry {
-            data = data | 1;
+            data |= 1;
         }
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data;
+        Ok(data)
     }
 
     fn rol_accumulator(&mut self) {
@@ -405,15 +777,15 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data << 1;
+        data <<= 1;
         if old_carry {
-            data = data | 1;
+            data |= 1;
         }
         self.set_register_a(data);
     }
 
-    fn ror(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+    fn ror(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         let old_carry = self.status.contains(CpuFlags::CARRY);
         if data & 1 == 1 {
@@ -421,13 +793,13 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         } 
-        data = data >> 1;
+        data >>= 1;
         if old_carry {
-            data = data | 0b10000000;
+            data |= 0b10000000;
         }
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data;
+        Ok(data)
     }
 
     fn ror_accumulator(&mut self) {
@@ -438,20 +810,20 @@ impl CPU {
         } else {
             self.clear_carry_flag();
         }
-        data = data >> 1;
+        data >>= 1;
         if old_carry {
-            data = data | 0b10000000;
+            data |= 0b10000000;
         }
         self.set_register_a(data);
     }
 
-    fn inc(&mut self, mode: &AddressingMode) -> u8{
-        let addr = self.get_operand_address(mode);
+    fn inc(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         data = data.wrapping_add(1); 
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data
+        Ok(data)
     }
 
     fn dex(&mut self) {
@@ -464,13 +836,13 @@ impl CPU {
         self.update_zero_and_negative_flags(self.register_y);
     }
 
-    fn dec(&mut self, mode: &AddressingMode) -> u8 {
-        let addr = self.get_operand_address(mode);
+    fn dec(&mut self, mode: &AddressingMode) -> Result<u8, CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let mut data = self.mem_read(addr);
         data = data.wrapping_sub(1);
         self.mem_write(addr, data);
         self.update_zero_and_negative_flags(data);
-        return data;
+        Ok(data)
     }
 
     fn pla(&mut self) {
@@ -485,14 +857,14 @@ impl CPU {
     }
 
     fn php(&mut self) {
-        let mut flags = self.status.clone();
+        let mut flags = self.status;
         flags.insert(CpuFlags::BREAK);
         flags.insert(CpuFlags::BREAK2);
         self.stack_push(flags.bits());
     }
 
-    fn bit(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
+    fn bit(&mut self, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
         let and = self.register_a & data;
         if and == 0 {
@@ -501,12 +873,63 @@ impl CPU {
             self.status.remove(CpuFlags::ZERO);
         }
 
+        // 65C02 BIT #imm only affects Z
+        if *mode == AddressingMode::Immediate {
+            return Ok(());
+        }
+
         self.status.set(CpuFlags::NEGATIV, data & 0b10000000 > 0);
         self.status.set(CpuFlags::OVERFLOW, data & 0b01000000 > 0);
+        Ok(())
     }
 
-    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
-        let addr = self.get_operand_address(mode);
+    // 65C02 TSB/TRB: Z from A & M, then set or reset the bits of A in memory
+    fn test_and_modify_bits(&mut self, mode: &AddressingMode, set: bool) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
+        let data = self.mem_read(addr);
+        self.status.set(CpuFlags::ZERO, data & self.register_a == 0);
+
+        let result = if set {
+            data | self.register_a
+        } else {
+            data & !self.register_a
+        };
+        self.mem_write(addr, result);
+        Ok(())
+    }
+
+    // 65C02 RMBn/SMBn: opcode bits 4-6 select the bit, bit 7 selects set vs reset
+    fn modify_zero_page_bit(&mut self, code: u8, mode: &AddressingMode) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
+        let mask = 1 << ((code >> 4) & 0b111);
+        let data = self.mem_read(addr);
+
+        if code & 0x80 != 0 {
+            self.mem_write(addr, data | mask);
+        } else {
+            self.mem_write(addr, data & !mask);
+        }
+        Ok(())
+    }
+
+    // 65C02 BBRn/BBSn: branch on a zero page bit, operands are the address and the offset
+    fn branch_on_zero_page_bit(&mut self, code: u8) {
+        let mask = 1 << ((code >> 4) & 0b111);
+        let addr = self.mem_read(self.program_counter) as u16;
+        let data = self.mem_read(addr);
+        let bit_set = data & mask != 0;
+        let condition = if code & 0x80 != 0 { bit_set } else { !bit_set };
+
+        self.program_counter = self.program_counter.wrapping_add(1);
+        if condition {
+            self.branch(true);
+        } else {
+            self.program_counter = self.program_counter.wrapping_add(1);
+        }
+    }
+
+    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) -> Result<(), CpuError> {
+        let addr = self.get_operand_address(mode)?;
         let data = self.mem_read(addr);
         if data <= compare_with {
             self.status.insert(CpuFlags::CARRY);
@@ -514,16 +937,21 @@ impl CPU {
             self.status.remove(CpuFlags::CARRY);
         }
         self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+        Ok(())
     }
 
     fn branch(&mut self, condition: bool) {
         if condition {
             let jump: i8 = self.mem_read(self.program_counter) as i8;
-            let jump_addr = self
-                .program_counter
-                .wrapping_add(1)
-                .wrapping_add(jump as u16);
-        
+            let next = self.program_counter.wrapping_add(1);
+            let jump_addr = next.wrapping_add(jump as u16);
+
+            // +1 cycle if branch succeeds, +1 more if it lands on a new page
+            self.cycles += 1;
+            if next & 0xFF00 != jump_addr & 0xFF00 {
+                self.cycles += 1;
+            }
+
             self.program_counter = jump_addr;
         }
     }
@@ -541,293 +969,604 @@ impl CPU {
         }
     }
 
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+    pub fn run(&mut self) -> HaltReason {
+        self.run_with_callback(|_| {})
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> HaltReason
     where
-        F: FnMut(&mut CPU),
-
+        F: FnMut(&mut CPU<B>),
     {
-    // Interpret Opscode and Excute
-    // pub fn run(&mut self) {
-    // Looping through all instructions
-        
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;    
-
-        // Program counter is initialized in load() with value 0x8000
+        let mut first = true;
         loop {
+            if !first && self.at_breakpoint() {
+                return HaltReason::Breakpoint(self.program_counter);
+            }
+            first = false;
 
-            // Opscode would be read from memory
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter; 
+            if let Some(halt) = self.step().halt {
+                return halt;
+            }
+            callback(self);
+        }
+    }
 
-            let opcode = opcodes.get(&code).unwrap();
+    // Run instructions until at least `cycles` CPU cycles have elapsed or the CPU halts.
+    // Returns the number of cycles actually consumed (may overshoot by one instruction)
+    pub fn run_for_cycles(&mut self, cycles: usize) -> RunResult {
+        let start = self.cycles;
+        let mut halt = None;
 
-            match code {
-                // LDA - Load Data Accumulator
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&opcode.mode);
-                }
-                
-                0xAA => self.tax(),
+        while self.cycles - start < cycles {
+            if self.cycles != start && self.at_breakpoint() {
+                halt = Some(HaltReason::Breakpoint(self.program_counter));
+                break;
+            }
 
-                0xe8 => self.inx(),
+            halt = self.step().halt;
+            if halt.is_some() {
+                break;
+            }
+        }
 
-                /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
+        RunResult {
+            cycles: self.cycles - start,
+            halt,
+        }
+    }
 
-                /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
+    // Run until the bus reports a completed frame (the start of vblank on the NES)
+    pub fn run_frame(&mut self) -> RunResult {
+        self.write_freezes();
+        let start = self.cycles;
 
-                /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
+        let halt = loop {
+            if self.cycles != start && self.at_breakpoint() {
+                break Some(HaltReason::Breakpoint(self.program_counter));
+            }
 
-                /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
+            let halt = self.step().halt;
+            if halt.is_some() || self.bus.poll_frame() {
+                break halt;
+            }
+        };
 
-                /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
+        RunResult {
+            cycles: self.cycles - start,
+            halt,
+        }
+    }
 
-                /* PHA */ 0x48 => self.stack_push(self.register_a),
+    // Store the values of the freeze codes in effect, once a frame, so reads that don't
+    // go through the CPU (sprite DMA, the PPU) see them too
+    pub fn write_freezes(&mut self) {
+        if self.cheats.is_active() {
+            for (addr, value) in self.cheats.freezes() {
+                self.bus.mem_write(addr, value);
+            }
+        }
+    }
 
-                /* CLC */ 0x18 => self.clear_carry_flag(),
+    // Breakpoints stop run()/run_for_cycles() before the instruction at `addr` executes.
+    // step() always executes, so resuming from a breakpoint is just another run call
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
 
-                /* SEC */ 0x38 => self.set_carry_flag(),
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
 
-                0x68 => {
-                    self.pla();
-                }
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
 
-                0x08 => {
-                    self.php();
-                }
+    fn at_breakpoint(&self) -> bool {
+        !self.breakpoints.is_empty() && self.breakpoints.contains(&self.program_counter)
+    }
 
-                0x28 => {
-                    self.plp();
-                }
+    // Watchpoints let the instruction that touches the memory finish, then halt step()
+    // and the run calls with HaltReason::Watchpoint. Opcode and operand fetches are not
+    // reads, and DMA done by the bus itself is not seen
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
 
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
 
-                0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-                    self.sbc(&opcode.mode);
-                }
+    fn watch(&mut self, access: Access, addr: u16, value: u8) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|wp| wp.matches(access, addr)) {
+            self.watch_hit = Some(WatchHit { access, addr, value });
+        }
+    }
 
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+    // Raise the (edge triggered) NMI line; it is serviced before the next instruction
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
 
-                0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
+    // Set the level of the IRQ line; it is serviced while asserted and I flag is clear
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
 
-                0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
+    // BRK pushes the address after its padding byte and the status with B set
+    fn brk(&mut self) {
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
 
-                /* LSR */ 0x4a => self.lsr_accumulator(), 
+        let mut flags = self.status;
+        flags.insert(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        if self.variant.is_cmos() {
+            self.status.remove(CpuFlags::DECIMAL_MODE);
+        }
 
-                0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
-                
-                /* ASR */ 0x0a => self.asl_accumulator(),
+        self.program_counter = self.mem_read_u16(IRQ_VECTOR);
+    }
 
-                0x06 | 0x16 | 0x0e | 0x1e => {
-                    self.asl(&opcode.mode);
-                }
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        self.stack_push_u16(self.program_counter);
 
-                /* ROL */ 0x2a => self.rol_accumulator(),
+        let mut flags = self.status;
+        flags.remove(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        if self.variant.is_cmos() {
+            self.status.remove(CpuFlags::DECIMAL_MODE);
+        }
 
-                0x26 | 0x36 | 0x2e | 0x3e => {
-                    self.rol(&opcode.mode);
-                }
+        let vector = match interrupt {
+            Interrupt::Nmi => NMI_VECTOR,
+            Interrupt::Irq => IRQ_VECTOR,
+        };
+        self.program_counter = self.mem_read_u16(vector);
+        self.cycles += 7;
+    }
 
-                /* ROR */ 0x6a => self.ror_accumulator(),
+    // Extra cycle taken by read instructions when indexing crosses a page boundary
+    // Operands are peeked so the lookahead has no side effects on the bus
+    fn page_cross_penalty(&self, opcode: &opcodes::OpCode) -> usize {
+        let peek_u16 = |addr: u16| {
+            (self.mem_peek(addr.wrapping_add(1)) as u16) << 8 | self.mem_peek(addr) as u16
+        };
 
-                0x66 | 0x76 | 0x6e | 0x7e => {
-                    self.ror(&opcode.mode);
-                }
+        let (base, index) = match opcode.mode {
+            AddressingMode::Absolute_X => (peek_u16(self.program_counter), self.register_x),
+            AddressingMode::Absolute_Y => (peek_u16(self.program_counter), self.register_y),
+            AddressingMode::Indirect_Y => {
+                let ptr = self.mem_peek(self.program_counter);
+                let lo = self.mem_peek(ptr as u16);
+                let hi = self.mem_peek(ptr.wrapping_add(1) as u16);
+                ((hi as u16) << 8 | (lo as u16), self.register_y)
+            }
+            _ => return 0,
+        };
 
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+        match opcode.mnemonic {
+            "ADC" | "SBC" | "AND" | "EOR" | "ORA" | "CMP" | "LDA" | "LDX" | "LDY" | "BIT" => {
+                let addr = base.wrapping_add(index as u16);
+                if base & 0xFF00 != addr & 0xFF00 { 1 } else { 0 }
+            }
+            _ => 0,
+        }
+    }
 
-                /* INY */ 0xc8 => self.iny(),
+    // Execute exactly one instruction, servicing a pending interrupt first.
+    // The bus is clocked by the cycles taken, including any DMA stall
+    pub fn step(&mut self) -> StepResult {
+        let start_cycles = self.cycles;
 
-                0xc6 | 0xd6 | 0xce | 0xde => {
-                    self.dec(&opcode.mode);
-                }
+        if self.bus.poll_nmi() {
+            self.nmi_pending = true;
+        }
+        let irq = self.irq_line || self.bus.irq();
 
-                0xca => {
-                    self.dex();
-                }
+        let interrupt = if self.nmi_pending {
+            self.nmi_pending = false;
+            Some(Interrupt::Nmi)
+        } else if irq && !self.status.contains(CpuFlags::INTERRUPT_DISABLE) {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
 
-                0x88 => {
-                    self.dey();
-                }
+        if let Some(interrupt) = interrupt {
+            self.interrupt(interrupt);
+            self.bus.log_prg(self.program_counter, PrgFlags::JUMP_TARGET);
+        }
 
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(&opcode.mode, self.register_a);
+        // Opscode would be read from memory
+        let address = self.program_counter;
+        self.fetch_start = address;
+        self.fetch_len = 1;
+        let code = self.mem_read(self.program_counter);
+
+        let halt = match opcodes::lookup(self.variant, code) {
+            Some(opcode) => {
+                self.fetch_len = opcode.len as u16;
+                for i in 0..self.fetch_len {
+                    self.bus.log_prg(address.wrapping_add(i), PrgFlags::CODE);
                 }
-
-                0xc0 | 0xc4 | 0xcc => {
-                    self.compare(&opcode.mode, self.register_y);
+                self.program_counter = self.program_counter.wrapping_add(1);
+                let halt = self.execute(opcode).err();
+                // anything but the next instruction or a return is a jump target
+                let next = address.wrapping_add(self.fetch_len);
+                if halt.is_none() && self.program_counter != next && !matches!(opcode.mnemonic, "RTS" | "RTI") {
+                    self.bus.log_prg(self.program_counter, PrgFlags::JUMP_TARGET);
                 }
+                halt
+            }
+            // Unknown opcodes leave the program counter on the offending byte
+            None if !self.variant.is_cmos() && JAM_OPCODES.contains(&code) => Some(HaltReason::Error(CpuError::Jam {
+                opcode: code,
+                address,
+            })),
+            None => Some(HaltReason::Error(CpuError::IllegalOpcode {
+                opcode: code,
+                address,
+            })),
+        };
+
+        self.fetch_len = 0;
+        self.indirect = false;
+        let halt = halt.or_else(|| self.watch_hit.take().map(HaltReason::Watchpoint));
+
+        self.cycles += self.bus.poll_stall_cycles();
+        self.bus.tick(self.cycles - start_cycles);
+
+        StepResult {
+            opcode: code,
+            address,
+            cycles: self.cycles - start_cycles,
+            interrupt,
+            halt,
+        }
+    }
 
-                0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
+    fn execute(&mut self, opcode: &opcodes::OpCode) -> Result<(), HaltReason> {
+        let code = opcode.code;
+        let program_counter_state = self.program_counter;
 
-                0x4c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = mem_address;
-                }
+        self.cycles += opcode.cycles as usize + self.page_cross_penalty(opcode);
 
-                /* JMP Indirect */
-                0x6c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
+        match code {
+            // LDA - Load Data Accumulator
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 | 0xb2 => {
+                self.lda(&opcode.mode)?;
+            }
+            
+            0xAA => self.tax(),
 
-                    let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-                        let lo = self.mem_read(mem_address);
-                        let hi = self.mem_read(mem_address & 0xFF00);
-                        (hi as u16) << 8 | (lo as u16)
-                    } else {
-                        self.mem_read_u16(mem_address)
-                    };
+            0xe8 => self.inx(),
 
-                    self.program_counter = indirect_ref;
-                }
+            /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
 
-                /* JSR */
-                0x20 => {
-                    self.stack_push_u16(self.program_counter + 2 - 1);
-                    let target_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = target_address
-                }
+            /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
 
-                /* RTS */
-                0x60 => {
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
+            /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
 
-                0x40 => {
-                    self.status.bits = self.stack_pop();
-                    self.status.remove(CpuFlags::BREAK);
-                    self.status.insert(CpuFlags::BREAK2);
+            /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
 
-                    self.program_counter = self.stack_pop_u16();
-                }
+            /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
 
-                /* BNE */
-                0xd0 => {
-                    self.branch(!self.status.contains(CpuFlags::ZERO));
-                }
+            /* PHA */ 0x48 => self.stack_push(self.register_a),
 
-                /* BVS */
-                0x70 => {
-                    self.branch(self.status.contains(CpuFlags::OVERFLOW));
-                }
+            /* CLC */ 0x18 => self.clear_carry_flag(),
 
-                /* BVC */
-                0x50 => {
-                    self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-                }
+            /* SEC */ 0x38 => self.set_carry_flag(),
 
-                /* BPL */
-                0x10 => {
-                    self.branch(!self.status.contains(CpuFlags::NEGATIV));
-                }
+            0x68 => {
+                self.pla();
+            }
 
-                /* BMI */
-                0x30 => {
-                    self.branch(self.status.contains(CpuFlags::NEGATIV));
-                }
+            0x08 => {
+                self.php();
+            }
 
-                /* BEQ */
-                0xf0 => {
-                    self.branch(self.status.contains(CpuFlags::ZERO));
-                }
+            0x28 => {
+                self.plp();
+            }
 
-                /* BCS */
-                0xb0 => {
-                    self.branch(self.status.contains(CpuFlags::CARRY));
-                }
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 | 0x72 => {
+                self.adc(&opcode.mode)?;
+            }
 
-                /* BCC */
-                0x90 => {
-                    self.branch(!self.status.contains(CpuFlags::CARRY));
-                }
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 | 0xf2 => {
+                self.sbc(&opcode.mode)?;
+            }
 
-                /* BIT */
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
-                
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 | 0x32 => {
+                self.and(&opcode.mode)?;
+            }
 
-                0x86 | 0x96 | 0x8e => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.register_x);
-                }
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 | 0x52 => {
+                self.eor(&opcode.mode)?;
+            }
 
-                0x84 | 0x94 | 0x8c => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.register_y);
-                }
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 | 0x12 => {
+                self.ora(&opcode.mode)?;
+            }
 
-                0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-                    self.ldx(&opcode.mode);
-                }
+            /* LSR */ 0x4a => self.lsr_accumulator(), 
 
-                0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-                    self.ldy(&opcode.mode);
-                }
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode)?;
+            }
+            
+            /* ASR */ 0x0a => self.asl_accumulator(),
 
-                /* NOP */
-                0xea => {
-                    //do nothing
-                }
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&opcode.mode)?;
+            }
 
-                /* TAY */
-                0xa8 => {
-                    self.register_y = self.register_a;
-                    self.update_zero_and_negative_flags(self.register_y);
-                }
+            /* ROL */ 0x2a => self.rol_accumulator(),
 
-                /* TSX */
-                0xba => {
-                    self.register_x = self.stack_pointer;
-                    self.update_zero_and_negative_flags(self.register_x);
-                }
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&opcode.mode)?;
+            }
 
-                /* TXA */
-                0x8a => {
-                    self.register_a = self.register_x;
-                    self.update_zero_and_negative_flags(self.register_a);
-                }
+            /* ROR */ 0x6a => self.ror_accumulator(),
 
-                /* TXS */
-                0x9a => {
-                    self.stack_pointer = self.register_x;
-                }
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&opcode.mode)?;
+            }
+
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode)?;
+            }
+
+            /* INY */ 0xc8 => self.iny(),
+
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&opcode.mode)?;
+            }
+
+            0xca => {
+                self.dex();
+            }
+
+            0x88 => {
+                self.dey();
+            }
+
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 | 0xd2 => {
+                self.compare(&opcode.mode, self.register_a)?;
+            }
+
+            0xc0 | 0xc4 | 0xcc => {
+                self.compare(&opcode.mode, self.register_y)?;
+            }
+
+            0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x)?,
+
+            0x4c => {
+                let mem_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = mem_address;
+            }
+
+            /* JMP Indirect */
+            0x6c => {
+                let mem_address = self.mem_read_u16(self.program_counter);
+
+                // NMOS parts fetch the high byte of JMP ($xxFF) from $xx00
+                let indirect_ref = if mem_address & 0x00FF == 0x00FF && !self.variant.is_cmos() {
+                    let lo = self.mem_read(mem_address);
+                    let hi = self.mem_read(mem_address & 0xFF00);
+                    (hi as u16) << 8 | (lo as u16)
+                } else {
+                    self.mem_read_u16(mem_address)
+                };
+
+                self.program_counter = indirect_ref;
+            }
+
+            /* JSR */
+            0x20 => {
+                self.stack_push_u16(self.program_counter.wrapping_add(1));
+                let target_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = target_address
+            }
+
+            /* RTS */
+            0x60 => {
+                self.program_counter = self.stack_pop_u16().wrapping_add(1);
+            }
+
+            0x40 => {
+                self.status.bits = self.stack_pop();
+                self.status.remove(CpuFlags::BREAK);
+                self.status.insert(CpuFlags::BREAK2);
+
+                self.program_counter = self.stack_pop_u16();
+            }
+
+            /* BNE */
+            0xd0 => {
+                self.branch(!self.status.contains(CpuFlags::ZERO));
+            }
+
+            /* BVS */
+            0x70 => {
+                self.branch(self.status.contains(CpuFlags::OVERFLOW));
+            }
+
+            /* BVC */
+            0x50 => {
+                self.branch(!self.status.contains(CpuFlags::OVERFLOW));
+            }
+
+            /* BPL */
+            0x10 => {
+                self.branch(!self.status.contains(CpuFlags::NEGATIV));
+            }
+
+            /* BMI */
+            0x30 => {
+                self.branch(self.status.contains(CpuFlags::NEGATIV));
+            }
+
+            /* BEQ */
+            0xf0 => {
+                self.branch(self.status.contains(CpuFlags::ZERO));
+            }
+
+            /* BCS */
+            0xb0 => {
+                self.branch(self.status.contains(CpuFlags::CARRY));
+            }
+
+            /* BCC */
+            0x90 => {
+                self.branch(!self.status.contains(CpuFlags::CARRY));
+            }
+
+            /* BIT */
+            0x24 | 0x2c | 0x89 | 0x34 | 0x3c => {
+                self.bit(&opcode.mode)?;
+            }
+            
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 | 0x92 => {
+                self.sta(&opcode.mode)?;
+            }
+
+            0x86 | 0x96 | 0x8e => {
+                let addr = self.get_operand_address(&opcode.mode)?;
+                self.mem_write(addr, self.register_x);
+            }
+
+            0x84 | 0x94 | 0x8c => {
+                let addr = self.get_operand_address(&opcode.mode)?;
+                self.mem_write(addr, self.register_y);
+            }
+
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&opcode.mode)?;
+            }
+
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&opcode.mode)?;
+            }
+
+            /* NOP */
+            0xea => {
+                //do nothing
+            }
+
+            /* TAY */
+            0xa8 => {
+                self.register_y = self.register_a;
+                self.update_zero_and_negative_flags(self.register_y);
+            }
+
+            /* TSX */
+            0xba => {
+                self.register_x = self.stack_pointer;
+                self.update_zero_and_negative_flags(self.register_x);
+            }
+
+            /* TXA */
+            0x8a => {
+                self.register_a = self.register_x;
+                self.update_zero_and_negative_flags(self.register_a);
+            }
+
+            /* TXS */
+            0x9a => {
+                self.stack_pointer = self.register_x;
+            }
+
+            /* TYA */
+            0x98 => {
+                self.register_a = self.register_y;
+                self.update_zero_and_negative_flags(self.register_a);
+            }
+            
+            /* 65C02 */
+            /* BRA */
+            0x80 => self.branch(true),
+
+            /* JMP (abs,X) */
+            0x7c => {
+                let base = self.mem_read_u16(self.program_counter);
+                let ptr = base.wrapping_add(self.register_x as u16);
+                self.program_counter = self.mem_read_u16(ptr);
+            }
+
+            /* INC A */
+            0x1a => self.set_register_a(self.register_a.wrapping_add(1)),
+
+            /* DEC A */
+            0x3a => self.set_register_a(self.register_a.wrapping_sub(1)),
+
+            /* PHX */ 0xda => self.stack_push(self.register_x),
+
+            /* PHY */ 0x5a => self.stack_push(self.register_y),
+
+            /* PLX */
+            0xfa => {
+                self.register_x = self.stack_pop();
+                self.update_zero_and_negative_flags(self.register_x);
+            }
 
-                /* TYA */
-                0x98 => {
-                    self.register_a = self.register_y;
-                    self.update_zero_and_negative_flags(self.register_a);
+            /* PLY */
+            0x7a => {
+                self.register_y = self.stack_pop();
+                self.update_zero_and_negative_flags(self.register_y);
+            }
+
+            /* STZ */
+            0x64 | 0x74 | 0x9c | 0x9e => {
+                let addr = self.get_operand_address(&opcode.mode)?;
+                self.mem_write(addr, 0);
+            }
+
+            /* TSB */
+            0x04 | 0x0c => self.test_and_modify_bits(&opcode.mode, true)?,
+
+            /* TRB */
+            0x14 | 0x1c => self.test_and_modify_bits(&opcode.mode, false)?,
+
+            /* RMBn / SMBn */
+            0x07 | 0x17 | 0x27 | 0x37 | 0x47 | 0x57 | 0x67 | 0x77
+            | 0x87 | 0x97 | 0xa7 | 0xb7 | 0xc7 | 0xd7 | 0xe7 | 0xf7 => {
+                self.modify_zero_page_bit(code, &opcode.mode)?;
+            }
+
+            /* BBRn / BBSn */
+            0x0f | 0x1f | 0x2f | 0x3f | 0x4f | 0x5f | 0x6f | 0x7f
+            | 0x8f | 0x9f | 0xaf | 0xbf | 0xcf | 0xdf | 0xef | 0xff => {
+                self.branch_on_zero_page_bit(code);
+            }
+
+            /* 65C02 undefined opcodes */
+            _ if opcode.mnemonic == "NOP" => {}
+
+            /* BRK */
+            0x00 => {
+                if self.halt_on_brk {
+                    return Err(HaltReason::Brk);
                 }
-                
-                0x00 => return,
-                
-                _ => todo!(),
+                self.brk();
             }
 
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16;
+            _ => {
+                return Err(HaltReason::Error(CpuError::IllegalOpcode {
+                    opcode: code,
+                    address: program_counter_state.wrapping_sub(1),
+                }))
             }
+        }
 
-            callback(self);
+        if program_counter_state == self.program_counter {
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
         }
+
+        Ok(())
     }
 }
 
@@ -847,8 +1586,12 @@ mod test {
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
         let mut cpu = CPU::new();
+        cpu.load(vec![0xaa, 0x00]);
+        // reset() clears A, so it's set between reset and run rather than before
+        // load_and_run
+        cpu.reset();
         cpu.register_a = 10;
-        cpu.load_and_run(vec![0xaa, 0x00]);
+        cpu.run();
 
         assert_eq!(cpu.register_x, 10)
     }
@@ -879,4 +1622,316 @@ mod test {
 
         assert_eq!(cpu.register_a, 0x55);
     }
+
+    #[test]
+    fn test_lda_indirect_y() {
+        let mut cpu = CPU::new();
+        // ($10),Y reads the pointer at $10 and adds Y to it, not the pointer at $10 + Y
+        cpu.mem_write_u16(0x10, 0x0200);
+        cpu.mem_write_u16(0x15, 0x0300);
+        cpu.mem_write(0x0205, 0x55);
+        cpu.mem_write(0x0305, 0x66);
+        // the pointer's high byte wraps around the zero page
+        cpu.mem_write(0xff, 0x80);
+        cpu.mem_write(0x00, 0x02);
+        cpu.mem_write(0x0285, 0x77);
+
+        cpu.load_and_run(vec![0xa0, 0x05, 0xb1, 0x10, 0xaa, 0xb1, 0xff, 0x00]);
+
+        assert_eq!(cpu.register_x, 0x55);
+        assert_eq!(cpu.register_a, 0x77);
+    }
+
+    #[test]
+    fn test_program_counter_wraps_at_top_of_memory() {
+        let mut cpu = CPU::new();
+        // LDA #$42 at $FFFF takes its operand from $0000
+        cpu.mem_write(0xffff, 0xa9);
+        cpu.mem_write(0x0000, 0x42);
+        cpu.program_counter = 0xffff;
+        let result = cpu.step();
+        assert!(result.halt.is_none());
+        assert_eq!((cpu.register_a, cpu.program_counter), (0x42, 0x0001));
+
+        // JSR $0300 at $FFFE pushes $0000, the address of its last byte
+        cpu.load_at(0xfffe, &[0x20, 0x00]).unwrap();
+        cpu.mem_write(0x0000, 0x03);
+        cpu.mem_write(0x0300, 0x60);
+        cpu.program_counter = 0xfffe;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0300);
+        // RTS returns to $0001
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0001);
+
+        // RTS popping $FFFF goes to $0000
+        cpu.stack_push_u16(0xffff);
+        cpu.program_counter = 0x0300;
+        assert!(cpu.step().halt.is_none());
+        assert_eq!(cpu.program_counter, 0x0000);
+    }
+
+    #[test]
+    fn test_step_executes_single_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0x00]);
+        cpu.reset();
+
+        let result = cpu.step();
+
+        assert_eq!(result.opcode, 0xa9);
+        assert_eq!(result.address, 0x0600);
+        assert_eq!(result.cycles, 2);
+        assert_eq!(result.interrupt, None);
+        assert_eq!(result.halt, None);
+        assert_eq!(cpu.register_a, 5);
+        assert_eq!(cpu.register_x, 0);
+        assert_eq!(cpu.program_counter, 0x0602);
+    }
+
+    #[test]
+    fn test_run_for_cycles_stops_on_brk() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]);
+        cpu.reset();
+
+        let result = cpu.run_for_cycles(1000);
+
+        assert_eq!(result.halt, Some(HaltReason::Brk));
+        assert_eq!(result.cycles, 2 + 2 + 2 + 7);
+        assert_eq!(cpu.register_x, 6);
+    }
+
+    #[test]
+    fn test_nmi_serviced_before_next_instruction() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xea, 0x00]);
+        cpu.reset();
+        cpu.mem_write_u16(0xFFFA, 0x0700);
+        cpu.mem_write(0x0700, 0xe8);
+
+        cpu.trigger_nmi();
+        let result = cpu.step();
+
+        assert_eq!(result.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(result.opcode, 0xe8);
+        assert_eq!(result.address, 0x0700);
+        assert_eq!(result.cycles, 7 + 2);
+        assert_eq!(cpu.stack_pointer, STACK_RESET.wrapping_sub(3));
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+    }
+
+    #[test]
+    fn test_illegal_opcode_halts_with_error() {
+        let mut cpu = CPU::new();
+        let reason = cpu.load_and_run(vec![0xa9, 0x01, 0xff, 0x00]);
+
+        assert_eq!(
+            reason,
+            HaltReason::Error(CpuError::IllegalOpcode { opcode: 0xff, address: 0x0602 })
+        );
+        assert_eq!(cpu.program_counter, 0x0602);
+    }
+
+    #[test]
+    fn test_jam_opcode_halts_with_error() {
+        let mut cpu = CPU::new();
+        let reason = cpu.load_and_run(vec![0x02, 0x00]);
+
+        assert_eq!(reason, HaltReason::Error(CpuError::Jam { opcode: 0x02, address: 0x0600 }));
+    }
+
+    #[test]
+    fn test_breakpoint_stops_run_and_resumes() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xe8, 0xe8, 0xe8, 0x00]);
+        cpu.reset();
+        cpu.add_breakpoint(0x0602);
+
+        assert_eq!(cpu.run(), HaltReason::Breakpoint(0x0602));
+        assert_eq!(cpu.register_x, 2);
+
+        assert_eq!(cpu.run(), HaltReason::Brk);
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_watchpoint_halts_after_access() {
+        let mut cpu = CPU::new();
+        // LDA #$05; STA $0200; LDA $0200; BRK
+        cpu.load(vec![0xa9, 0x05, 0x8d, 0x00, 0x02, 0xad, 0x00, 0x02, 0x00]);
+        cpu.reset();
+        cpu.add_watchpoint(Watchpoint { start: 0x0200, end: 0x0200, read: true, write: false });
+        // fetching the program itself doesn't count
+        cpu.add_watchpoint(Watchpoint { start: 0x0600, end: 0x0608, read: true, write: false });
+
+        let hit = WatchHit { access: Access::Read, addr: 0x0200, value: 0x05 };
+        assert_eq!(cpu.run(), HaltReason::Watchpoint(hit));
+        assert_eq!(cpu.program_counter, 0x0608);
+
+        cpu.clear_watchpoints();
+        cpu.add_watchpoint(Watchpoint { start: 0x01f0, end: 0x01ff, read: false, write: true });
+        cpu.halt_on_brk = false;
+        // BRK pushes the return address
+        assert!(matches!(cpu.step().halt, Some(HaltReason::Watchpoint(WatchHit { access: Access::Write, .. }))));
+    }
+
+    #[test]
+    fn test_decimal_adc_and_sbc_on_nmos() {
+        let mut cpu = CPU::with_variant(CpuVariant::Nmos6502);
+        // SED; CLC; LDA #$09; ADC #$01; STA $10; SEC; LDA #$10; SBC #$01; BRK
+        cpu.load_and_run(vec![
+            0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x85, 0x10, 0x38, 0xa9, 0x10, 0xe9, 0x01, 0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x10);
+        assert_eq!(cpu.register_a, 0x09);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_decimal_flag_ignored_without_bcd() {
+        let mut cpu = CPU::new();
+        // SED; CLC; LDA #$09; ADC #$01; BRK
+        cpu.load_and_run(vec![0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x00]);
+
+        assert_eq!(cpu.register_a, 0x0a);
+    }
+
+    #[test]
+    fn test_brk_through_irq_vector() {
+        let mut cpu = CPU::new();
+        cpu.halt_on_brk = false;
+        cpu.load(vec![0x00, 0xea]);
+        cpu.reset();
+        cpu.mem_write_u16(0xFFFE, 0x0700);
+
+        cpu.step();
+
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert_eq!(cpu.mem_read(0x01fd), 0x06);
+        assert_eq!(cpu.mem_read(0x01fc), 0x02);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0100);
+    }
+
+    #[test]
+    fn test_65c02_stz_and_zero_page_indirect() {
+        let mut cpu = CPU::with_variant(CpuVariant::Cmos65C02);
+        // LDA #$55; STA $0300; STZ $0300; LDA #$03; STA $11; STZ $10; LDA #$aa; LDA ($10); BRK
+        cpu.load_and_run(vec![
+            0xa9, 0x55, 0x8d, 0x00, 0x03, 0x9c, 0x00, 0x03, 0xa9, 0x03, 0x85, 0x11, 0x64, 0x10,
+            0xa9, 0xaa, 0xb2, 0x10, 0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x0300), 0x00);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_65c02_opcodes_illegal_on_2a03() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x80, 0x02]);
+        cpu.reset();
+
+        assert!(matches!(cpu.step().halt, Some(HaltReason::Error(CpuError::IllegalOpcode { .. }))));
+    }
+
+    #[test]
+    fn test_65c02_bra_tsb_and_bbr() {
+        let mut cpu = CPU::with_variant(CpuVariant::Cmos65C02);
+        // BRA +2; BRK; BRK; LDA #$81; STA $10; LDA #$06; TSB $10; BBR1 $10,+1; BRK; INX; BRK
+        cpu.load_and_run(vec![
+            0x80, 0x02, 0x00, 0x00, 0xa9, 0x81, 0x85, 0x10, 0xa9, 0x06, 0x04, 0x10, 0x1f, 0x10,
+            0x01, 0x00, 0xe8, 0x00,
+        ]);
+
+        assert_eq!(cpu.mem_read(0x10), 0x87);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_65c02_undefined_opcodes_are_nops() {
+        for code in 0..=0xffu8 {
+            assert!(opcodes::lookup(CpuVariant::Cmos65C02, code).is_some(), "${:02X}", code);
+        }
+
+        let mut cpu = CPU::with_variant(CpuVariant::Cmos65C02);
+        // NOP #; NOP $1234 (8 cycles); NOP (x3 column); LDA #$01
+        cpu.load(vec![0x02, 0xff, 0x5c, 0x34, 0x12, 0x03, 0xa9, 0x01]);
+        cpu.reset();
+        let cycles: Vec<usize> = (0..4).map(|_| cpu.step().cycles).collect();
+        assert_eq!(cycles, vec![2, 8, 1, 2]);
+        assert_eq!((cpu.register_a, cpu.program_counter), (0x01, 0x0608));
+    }
+
+    #[test]
+    fn test_65c02_bra_cycles() {
+        let mut cpu = CPU::with_variant(CpuVariant::Cmos65C02);
+        cpu.load(vec![0x80, 0x02]);
+        cpu.reset();
+        assert_eq!(cpu.step().cycles, 3);
+        assert_eq!(cpu.program_counter, 0x0604);
+
+        // across a page
+        cpu.load_at(0x06f0, &[0x80, 0x20]).unwrap();
+        cpu.program_counter = 0x06f0;
+        assert_eq!(cpu.step().cycles, 4);
+        assert_eq!(cpu.program_counter, 0x0712);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap_only_on_nmos() {
+        for (variant, target) in [(CpuVariant::Nes2A03, 0x0700), (CpuVariant::Cmos65C02, 0x0800)] {
+            let mut cpu = CPU::with_variant(variant);
+            cpu.load(vec![0x6c, 0xff, 0x02]);
+            cpu.reset();
+            cpu.mem_write(0x02ff, 0x00);
+            cpu.mem_write(0x0200, 0x07);
+            cpu.mem_write(0x0300, 0x08);
+
+            cpu.step();
+
+            assert_eq!(cpu.program_counter, target);
+        }
+    }
+
+    #[test]
+    fn test_program_from_assembly() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(crate::asm::program(
+            "
+                    ldx #5
+                    lda #0
+            @sum:   clc
+                    adc #3
+                    dex
+                    bne @sum
+                    sta $10
+                    brk
+            ",
+        ));
+
+        assert_eq!(cpu.mem_read(0x10), 15);
+        assert_eq!(cpu.register_x, 0);
+    }
+
+    #[test]
+    fn test_cheats_patch_reads() {
+        let mut cpu = CPU::new();
+        cpu.load_at(0x8000, &[0xa9, 0x01, 0x85, 0x11, 0xa5, 0x10, 0x85, 0x12, 0x00]).unwrap();
+        cpu.mem_write_u16(0xfffc, 0x8000);
+        // LDA #$01 becomes LDA #$07, and $10 reads as $42
+        cpu.cheats.add(crate::cheats::Code::GameGenie { addr: 0x8001, value: 7, compare: Some(1) }, "");
+        cpu.cheats.add("0010:42".parse().unwrap(), "");
+        cpu.reset();
+        cpu.run();
+
+        assert_eq!(cpu.mem_peek(0x11), 7);
+        assert_eq!(cpu.mem_peek(0x12), 0x42);
+        assert_eq!(cpu.bus.mem_peek(0x10), 0);
+        cpu.write_freezes();
+        assert_eq!(cpu.bus.mem_peek(0x10), 0x42);
+    }
 }
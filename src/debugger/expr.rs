@@ -0,0 +1,379 @@
+// Debugger expressions, e.g. `A == #$10 && X > 3` or `[$0200+X] & $80 != 0`.
+//
+// Operands: numbers ($10 or 0x10 hex, %1010 binary, 16 decimal, an optional leading #),
+// registers A X Y SP P PC, flags C Z I D V N (0 or 1), memory bytes [addr] and, with a
+// symbol table, labels standing for their address.
+// Operators, loosest first: ||  &&  == != < <= > >=  + - & |  and unary ! -.
+// Comparisons give 1 or 0, && and || treat any non-zero value as true. Memory is read
+// with mem_peek, so evaluating has no side effects
+
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::{CpuFlags, Mem, CPU};
+use crate::disasm::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    P,
+    Pc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+    BitOr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Flag(CpuFlags),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+// A parsed expression, keeping its source text for listings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    text: String,
+    root: Node,
+}
+
+impl Expr {
+    // Parse `text`, resolving names other than registers and flags through `symbols`
+    pub fn parse(text: &str, symbols: Option<&SymbolTable>) -> Result<Self, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0, symbols };
+        let root = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(Expr {
+                text: text.trim().to_string(),
+                root,
+            }),
+            Some(token) => Err(format!("unexpected {} in expression", token)),
+        }
+    }
+
+    pub fn eval<B: Bus>(&self, cpu: &CPU<B>) -> i64 {
+        eval(&self.root, cpu)
+    }
+
+    pub fn holds<B: Bus>(&self, cpu: &CPU<B>) -> bool {
+        self.eval(cpu) != 0
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Expr::parse(s, None)
+    }
+}
+
+fn eval<B: Bus>(node: &Node, cpu: &CPU<B>) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(register) => match register {
+            Register::A => cpu.register_a as i64,
+            Register::X => cpu.register_x as i64,
+            Register::Y => cpu.register_y as i64,
+            Register::Sp => cpu.stack_pointer as i64,
+            Register::P => cpu.status.bits() as i64,
+            Register::Pc => cpu.program_counter as i64,
+        },
+        Node::Flag(flag) => cpu.status.contains(*flag) as i64,
+        Node::Memory(addr) => cpu.mem_peek(eval(addr, cpu) as u16) as i64,
+        Node::Not(node) => (eval(node, cpu) == 0) as i64,
+        Node::Negate(node) => -eval(node, cpu),
+        Node::Binary(op, lhs, rhs) => {
+            let lhs = eval(lhs, cpu);
+            // && and || short-circuit
+            match op {
+                BinaryOp::Or if lhs != 0 => return 1,
+                BinaryOp::And if lhs == 0 => return 0,
+                _ => {}
+            }
+            let rhs = eval(rhs, cpu);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (rhs != 0) as i64,
+                BinaryOp::Eq => (lhs == rhs) as i64,
+                BinaryOp::Ne => (lhs != rhs) as i64,
+                BinaryOp::Lt => (lhs < rhs) as i64,
+                BinaryOp::Le => (lhs <= rhs) as i64,
+                BinaryOp::Gt => (lhs > rhs) as i64,
+                BinaryOp::Ge => (lhs >= rhs) as i64,
+                BinaryOp::Add => lhs.wrapping_add(rhs),
+                BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                BinaryOp::BitAnd => lhs & rhs,
+                BinaryOp::BitOr => lhs | rhs,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Op(op) => write!(f, "`{}`", op),
+        }
+    }
+}
+
+// Longest operators first so `<=` isn't read as `<`
+const OPERATORS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "|", "!", "(", ")", "[",
+];
+
+// Parse a number in the syntax described at the top of the file
+pub fn parse_number(text: &str) -> Result<i64, String> {
+    let digits = text.strip_prefix('#').unwrap_or(text);
+    let (digits, radix) = if let Some(hex) = digits.strip_prefix('$') {
+        (hex, 16)
+    } else if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(bin) = digits.strip_prefix('%') {
+        (bin, 2)
+    } else {
+        (digits, 10)
+    };
+    i64::from_str_radix(digits, radix).map_err(|_| format!("invalid number: {}", text))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if c == ']' {
+            tokens.push(Token::Op("]"));
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c.is_ascii_alphanumeric() || "#$%_".contains(c) {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "#$%_".contains(c)))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if word.starts_with(|c: char| c.is_ascii_digit() || "#$%".contains(c)) {
+                tokens.push(Token::Number(parse_number(word)?));
+            } else {
+                tokens.push(Token::Name(word.to_string()));
+            }
+            rest = &rest[len..];
+        } else {
+            return Err(format!("unexpected `{}` in expression", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek_op() {
+            Some(found) if found == op => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(format!("expected `{}` in expression", op)),
+        }
+    }
+
+    // Left-associative chain of the operators in `ops` over operands parsed by `next`
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        next: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut lhs = next(self)?;
+        while let Some(&(_, op)) = ops.iter().find(|(text, _)| Some(*text) == self.peek_op()) {
+            self.pos += 1;
+            let rhs = next(self)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        self.binary(&[("||", BinaryOp::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        self.binary(&[("&&", BinaryOp::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("==", BinaryOp::Eq),
+                ("!=", BinaryOp::Ne),
+                ("<", BinaryOp::Lt),
+                ("<=", BinaryOp::Le),
+                (">", BinaryOp::Gt),
+                (">=", BinaryOp::Ge),
+            ],
+            Parser::term,
+        )
+    }
+
+    fn term(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("+", BinaryOp::Add),
+                ("-", BinaryOp::Sub),
+                ("&", BinaryOp::BitAnd),
+                ("|", BinaryOp::BitOr),
+            ],
+            Parser::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("incomplete expression")?;
+        self.pos += 1;
+
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Name(name) => name_node(&name, self.symbols),
+            Token::Op("!") => Ok(Node::Not(Box::new(self.unary()?))),
+            Token::Op("-") => Ok(Node::Negate(Box::new(self.unary()?))),
+            Token::Op("(") => {
+                let node = self.or()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Op("[") => {
+                let addr = self.or()?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(addr)))
+            }
+            token => Err(format!("unexpected {} in expression", token)),
+        }
+    }
+}
+
+// Registers and flags in any case, then labels as written
+fn name_node(name: &str, symbols: Option<&SymbolTable>) -> Result<Node, String> {
+    let node = match name.to_ascii_uppercase().as_str() {
+        "A" => Node::Register(Register::A),
+        "X" => Node::Register(Register::X),
+        "Y" => Node::Register(Register::Y),
+        "SP" | "S" => Node::Register(Register::Sp),
+        "P" => Node::Register(Register::P),
+        "PC" => Node::Register(Register::Pc),
+        "C" => Node::Flag(CpuFlags::CARRY),
+        "Z" => Node::Flag(CpuFlags::ZERO),
+        "I" => Node::Flag(CpuFlags::INTERRUPT_DISABLE),
+        "D" => Node::Flag(CpuFlags::DECIMAL_MODE),
+        "V" => Node::Flag(CpuFlags::OVERFLOW),
+        "N" => Node::Flag(CpuFlags::NEGATIV),
+        _ => match symbols.and_then(|symbols| symbols.lookup(name)) {
+            Some(addr) => Node::Number(addr as i64),
+            None => return Err(format!("unknown register, flag or label: {}", name)),
+        },
+    };
+    Ok(node)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval_with(text: &str, cpu: &CPU) -> i64 {
+        text.parse::<Expr>().unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn test_registers_and_logic() {
+        let mut cpu = CPU::new();
+        cpu.register_a = 0x10;
+        cpu.register_x = 4;
+
+        assert_eq!(eval_with("A == #$10 && X > 3", &cpu), 1);
+        assert_eq!(eval_with("A == #$10 && X > 4", &cpu), 0);
+        assert_eq!(eval_with("a != 16 || (x >= 4 && !z)", &cpu), 1);
+        assert_eq!(eval_with("X - 5", &cpu), -1);
+        assert_eq!(eval_with("A & %110000 | 1", &cpu), 0x11);
+    }
+
+    #[test]
+    fn test_memory_operand() {
+        let mut cpu = CPU::new();
+        cpu.register_x = 2;
+        cpu.mem_write(0x0202, 0x80);
+
+        assert_eq!(eval_with("[$0200+X]", &cpu), 0x80);
+        assert_eq!(eval_with("[0x200 + x] & $80 != 0", &cpu), 1);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("A ==".parse::<Expr>().is_err());
+        assert!("Q > 1".parse::<Expr>().is_err());
+        assert!("(A".parse::<Expr>().is_err());
+        assert!("A 1".parse::<Expr>().is_err());
+        assert!("$zz".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0010, "player_x");
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0010, 0x30);
+
+        let expr = Expr::parse("[player_x] == $30 && PC != player_x", Some(&symbols)).unwrap();
+        assert_eq!(expr.eval(&cpu), 1);
+        assert!(Expr::parse("[player_y] == 0", Some(&symbols)).is_err());
+        assert!("[player_x] == 0".parse::<Expr>().is_err());
+    }
+}
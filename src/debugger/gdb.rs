@@ -0,0 +1,576 @@
+// GDB remote serial protocol stub, so gdb and IDE debuggers can drive a Debugger over
+// TCP (`target remote localhost:6502`).
+//
+// Registers are numbered a x y p sp pc; all are 8 bits wide except the 16 bit pc, and
+// `g` sends them in that order as little-endian hex. Memory reads use mem_peek, so they
+// don't disturb the PPU or controllers. Memory writes go straight to the bus, bypassing
+// watchpoints. Z0/Z1 set execute breakpoints, Z2/Z3/Z4 write/read/access watchpoints.
+// A Ctrl-C (0x03) from the client interrupts a continue
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use super::{BreakKind, Debugger, Stop};
+use crate::bus::Bus;
+use crate::cpu::{CpuError, CpuFlags, HaltReason, Mem};
+
+// Cycles run between polls for a Ctrl-C while continuing
+const POLL_CYCLES: usize = 100_000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.m6502.core">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="x" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="y" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="p" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="sp" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="pc" bitsize="16" regnum="5" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// What to do after a packet
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    // reply, then end the session
+    Close(Option<String>),
+}
+
+pub struct GdbStub<B: Bus> {
+    pub debugger: Debugger<B>,
+    // debugger breakpoint ids by Z packet type and address
+    breakpoints: HashMap<(u8, u16), usize>,
+    no_ack: bool,
+}
+
+impl<B: Bus> GdbStub<B> {
+    pub fn new(debugger: Debugger<B>) -> Self {
+        GdbStub {
+            debugger,
+            breakpoints: HashMap::new(),
+            no_ack: false,
+        }
+    }
+
+    // Serve one client until it detaches, kills the target or disconnects. Returns true
+    // when the client asked to kill the target
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<bool> {
+        let mut connection = Connection { stream, buffer: Vec::new() };
+        self.no_ack = false;
+
+        while let Some(packet) = connection.read_packet()? {
+            let packet = match packet {
+                Incoming::Packet(packet) => packet,
+                // nothing is running, so there's nothing to interrupt
+                Incoming::Interrupt => continue,
+            };
+            if !self.no_ack {
+                connection.stream.write_all(b"+")?;
+            }
+
+            let reply = match self.handle(&packet) {
+                Action::Reply(reply) => reply,
+                Action::Step => {
+                    let stop = self.debugger.step_into(1);
+                    self.stop_reply(stop)
+                }
+                Action::Continue => self.run(&mut connection)?,
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        connection.write_packet(&reply)?;
+                    }
+                    return Ok(packet.starts_with('k'));
+                }
+            };
+            connection.write_packet(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(false)
+    }
+
+    // Continue in slices, checking the connection for a Ctrl-C in between
+    fn run(&mut self, connection: &mut Connection) -> io::Result<String> {
+        loop {
+            match self.debugger.resume_for(POLL_CYCLES) {
+                Stop::Pause => {
+                    if connection.poll_interrupt()? {
+                        self.debugger.interrupt();
+                        return Ok(format!("S{:02x}", SIGINT));
+                    }
+                }
+                stop => return Ok(self.stop_reply(stop)),
+            }
+        }
+    }
+
+    fn handle(&mut self, packet: &str) -> Action {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => {
+                let cpu = &self.debugger.cpu;
+                let pc = cpu.program_counter;
+                let [pc_lo, pc_hi] = pc.to_le_bytes();
+                hex(&[cpu.register_a, cpu.register_x, cpu.register_y, cpu.status.bits(), cpu.stack_pointer, pc_lo, pc_hi])
+            }
+            "G" => match unhex(args) {
+                Some(bytes) if bytes.len() == 7 => {
+                    for (reg, value) in [0, 1, 2, 3, 4].into_iter().zip(&bytes) {
+                        self.set_register(reg, *value as u16);
+                    }
+                    self.set_register(5, u16::from_le_bytes([bytes[5], bytes[6]]));
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(reg) if reg < 6 => {
+                    let value = self.register(reg);
+                    if reg == 5 {
+                        hex(&value.to_le_bytes())
+                    } else {
+                        hex(&[value as u8])
+                    }
+                }
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(reg, value)| {
+                    let reg = usize::from_str_radix(reg, 16).ok().filter(|&reg| reg < 6)?;
+                    let bytes = unhex(value).filter(|bytes| !bytes.is_empty() && bytes.len() <= 2)?;
+                    Some((reg, bytes.iter().rev().fold(0u16, |value, &b| value << 8 | b as u16)))
+                });
+                match parsed {
+                    Some((reg, value)) => {
+                        self.set_register(reg, value);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let cpu = &self.debugger.cpu;
+                    let bytes: Vec<u8> = (0..len).map(|i| cpu.mem_peek(addr.wrapping_add(i as u16))).collect();
+                    hex(&bytes)
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_addr_len(range)?, unhex(data)?)));
+                match parsed {
+                    Some(((addr, len), bytes)) if bytes.len() == len => {
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            self.debugger.cpu.bus.mem_write(addr.wrapping_add(i as u16), byte);
+                        }
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "c" | "s" | "C" | "S" => {
+                // optional resume address after the signal
+                let addr = match command {
+                    "c" | "s" => Some(args),
+                    _ => args.split_once(';').map(|(_, addr)| addr),
+                };
+                let addr = addr.filter(|addr| !addr.is_empty()).map(|addr| u16::from_str_radix(addr, 16));
+                if let Some(Ok(addr)) = addr {
+                    self.debugger.cpu.program_counter = addr;
+                }
+                return if command.eq_ignore_ascii_case("s") { Action::Step } else { Action::Continue };
+            }
+            "v" => {
+                if args == "Cont?" {
+                    "vCont;c;C;s;S".to_string()
+                } else if let Some(actions) = args.strip_prefix("Cont;") {
+                    // single threaded: the first action applies
+                    return match actions.chars().next() {
+                        Some('s') | Some('S') => Action::Step,
+                        Some('c') | Some('C') => Action::Continue,
+                        _ => Action::Reply("E01".to_string()),
+                    };
+                } else {
+                    String::new()
+                }
+            }
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some((kind, addr, len)) => self.breakpoint(command == "Z", kind, addr, len),
+                None => "E01".to_string(),
+            },
+            "q" => self.query(args),
+            "Q" if args == "StartNoAckMode" => "OK".to_string(),
+            "H" | "T" => "OK".to_string(),
+            "D" => return Action::Close(Some("OK".to_string())),
+            "k" => return Action::Close(None),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = query.strip_prefix("Xfer:features:read:target.xml:") {
+            let (offset, len) = match range.split_once(',') {
+                Some((offset, len)) => (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16)),
+                None => return "E01".to_string(),
+            };
+            let (Ok(offset), Ok(len)) = (offset, len) else {
+                return "E01".to_string();
+            };
+            let data = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+            return if data.len() > len {
+                format!("m{}", &data[..len])
+            } else {
+                format!("l{}", data)
+            };
+        }
+        match query {
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            "Attached" => "1".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, kind: u8, addr: u16, len: u16) -> String {
+        let break_kind = match kind {
+            0 | 1 => BreakKind::Execute,
+            2 => BreakKind::Write,
+            3 => BreakKind::Read,
+            4 => BreakKind::Access,
+            _ => return String::new(),
+        };
+        if insert {
+            if !self.breakpoints.contains_key(&(kind, addr)) {
+                // Z0/Z1 give the instruction size as the kind, only watchpoints have a length
+                let end = match break_kind {
+                    BreakKind::Execute => addr,
+                    _ => addr.saturating_add(len.max(1) - 1),
+                };
+                let id = self.debugger.add_breakpoint(break_kind, Some((addr, end)), None);
+                self.breakpoints.insert((kind, addr), id);
+            }
+        } else if let Some(id) = self.breakpoints.remove(&(kind, addr)) {
+            self.debugger.remove_breakpoint(id);
+        }
+        "OK".to_string()
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Halt(HaltReason::Error(CpuError::IllegalOpcode { .. } | CpuError::Jam { .. })) => {
+                format!("S{:02x}", SIGILL)
+            }
+            Stop::Done | Stop::Pause | Stop::Halt(_) => format!("S{:02x}", SIGTRAP),
+            Stop::Breakpoint(id, hit) => {
+                let kind = self.breakpoints.iter().find(|(_, &bp)| bp == id).map_or(0, |(&(kind, _), _)| kind);
+                let reason = match (kind, hit) {
+                    (2, Some(hit)) => format!("watch:{:04x};", hit.addr),
+                    (3, Some(hit)) => format!("rwatch:{:04x};", hit.addr),
+                    (4, Some(hit)) => format!("awatch:{:04x};", hit.addr),
+                    (1, _) => "hwbreak:;".to_string(),
+                    _ => "swbreak:;".to_string(),
+                };
+                format!("T{:02x}{}", SIGTRAP, reason)
+            }
+        }
+    }
+
+    fn register(&self, reg: usize) -> u16 {
+        let cpu = &self.debugger.cpu;
+        match reg {
+            0 => cpu.register_a as u16,
+            1 => cpu.register_x as u16,
+            2 => cpu.register_y as u16,
+            3 => cpu.status.bits() as u16,
+            4 => cpu.stack_pointer as u16,
+            _ => cpu.program_counter,
+        }
+    }
+
+    fn set_register(&mut self, reg: usize, value: u16) {
+        let cpu = &mut self.debugger.cpu;
+        match reg {
+            0 => cpu.register_a = value as u8,
+            1 => cpu.register_x = value as u8,
+            2 => cpu.register_y = value as u8,
+            3 => cpu.status = CpuFlags::from_bits_truncate(value as u8),
+            4 => cpu.stack_pointer = value as u8,
+            _ => cpu.program_counter = value,
+        }
+    }
+}
+
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    // Next packet or Ctrl-C, None once the client hung up. Acks are skipped and packets
+    // with a bad checksum are nacked
+    fn read_packet(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            if let Some(incoming) = self.take_packet()? {
+                return Ok(Some(incoming));
+            }
+            let mut chunk = [0; 1024];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    fn take_packet(&mut self) -> io::Result<Option<Incoming>> {
+        while let Some(&byte) = self.buffer.first() {
+            match byte {
+                0x03 => {
+                    self.buffer.remove(0);
+                    return Ok(Some(Incoming::Interrupt));
+                }
+                b'$' => break,
+                // acks, nacks and noise between packets
+                _ => {
+                    self.buffer.remove(0);
+                }
+            }
+        }
+        let Some(end) = self.buffer.iter().position(|&b| b == b'#') else {
+            return Ok(None);
+        };
+        if self.buffer.len() < end + 3 {
+            return Ok(None);
+        }
+
+        let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+        let payload = &packet[1..end];
+        let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|cs| u8::from_str_radix(cs, 16).ok());
+        if checksum != Some(sum(payload)) {
+            self.stream.write_all(b"-")?;
+            return Ok(None);
+        }
+        Ok(Some(Incoming::Packet(String::from_utf8_lossy(&unescape(payload)).into_owned())))
+    }
+
+    // True when a Ctrl-C arrived while the target was running
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0; 1024];
+        let result = self.stream.read(&mut chunk);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+
+        match self.buffer.iter().position(|&b| b == 0x03) {
+            Some(pos) => {
+                self.buffer.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn write_packet(&mut self, payload: &str) -> io::Result<()> {
+        let payload = escape(payload.as_bytes());
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&payload);
+        packet.extend_from_slice(format!("#{:02x}", sum(&payload)).as_bytes());
+        self.stream.write_all(&packet)
+    }
+}
+
+fn sum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+// `}` escapes the next byte XORed with $20; `#`, `$`, `}` and `*` need escaping
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for &byte in data {
+        if b"#$}*".contains(&byte) {
+            out.push(b'}');
+            out.push(byte ^ 0x20);
+        } else {
+            out.push(byte);
+        }
+    }
+    out
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut iter = data.iter();
+    while let Some(&byte) = iter.next() {
+        match byte {
+            b'}' => out.extend(iter.next().map(|b| b ^ 0x20)),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// "addr,len" in hex
+fn parse_addr_len(text: &str) -> Option<(u16, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((u16::from_str_radix(addr, 16).ok()?, len.min(0x10000)))
+}
+
+// "type,addr,kind" of Z/z packets, kind being the watched length for watchpoints
+fn parse_breakpoint(text: &str) -> Option<(u8, u16, u16)> {
+    let mut fields = text.split(',');
+    let kind = fields.next()?.parse().ok()?;
+    let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+    let len = u16::from_str_radix(fields.next()?.split(';').next()?, 16).ok()?;
+    Some((kind, addr, len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::FlatBus;
+    use crate::cpu::CPU;
+    use std::net::TcpListener;
+
+    // $0600 LDX #0; loop: INX; STX $0200; JMP loop
+    fn stub() -> GdbStub<FlatBus> {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xa2, 0x00, 0xe8, 0x8e, 0x00, 0x02, 0x4c, 0x02, 0x06]);
+        cpu.reset();
+        GdbStub::new(Debugger::new(cpu))
+    }
+
+    fn reply(stub: &mut GdbStub<FlatBus>, packet: &str) -> String {
+        match stub.handle(packet) {
+            Action::Reply(reply) => reply,
+            action => panic!("{:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "g"), "00000024fd0006");
+        assert_eq!(reply(&mut stub, "G0102032401ff80"), "OK");
+        assert_eq!(stub.debugger.cpu.program_counter, 0x80ff);
+        assert_eq!(reply(&mut stub, "p1"), "02");
+        assert_eq!(reply(&mut stub, "P5=0206"), "OK");
+        assert_eq!(reply(&mut stub, "p5"), "0206");
+
+        assert_eq!(reply(&mut stub, "m0600,3"), "a200e8");
+        assert_eq!(reply(&mut stub, "M0300,2:beef"), "OK");
+        assert_eq!(reply(&mut stub, "m0300,2"), "beef");
+        assert_eq!(reply(&mut stub, "M0300,2:be"), "E01");
+        assert_eq!(reply(&mut stub, "qXfer:features:read:target.xml:0,10"), "m<?xml version=\"1");
+        assert_eq!(reply(&mut stub, "vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn test_breakpoint_ranges() {
+        let mut stub = stub();
+        assert_eq!(reply(&mut stub, "Z0,0600,3"), "OK");
+        assert_eq!(reply(&mut stub, "Z1,0700,2"), "OK");
+        assert_eq!(reply(&mut stub, "Z2,0200,4"), "OK");
+        let ranges: Vec<_> = stub.debugger.breakpoints().iter().map(|bp| bp.range).collect();
+        assert_eq!(ranges, vec![Some((0x0600, 0x0600)), Some((0x0700, 0x0700)), Some((0x0200, 0x0203))]);
+    }
+
+    #[test]
+    fn test_packet_framing() {
+        assert_eq!(escape(b"a#b}"), b"a}\x03b}]");
+        assert_eq!(unescape(&escape(b"$*#}")), b"$*#}");
+        assert_eq!(sum(b"OK"), 0x9a);
+    }
+
+    #[test]
+    fn test_session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let mut stub = stub();
+            let (stream, _) = listener.accept().unwrap();
+            stub.serve(stream).unwrap()
+        });
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut exchange = |packet: &str| {
+            client.write_all(format!("${}#{:02x}", packet, sum(packet.as_bytes())).as_bytes()).unwrap();
+            let mut response = Vec::new();
+            let mut byte = [0];
+            // "+" ack, then "$...#xx"
+            while !(response.len() > 3 && response[response.len() - 3] == b'#') {
+                client.read_exact(&mut byte).unwrap();
+                if !(response.is_empty() && byte[0] == b'+') {
+                    response.push(byte[0]);
+                }
+            }
+            client.write_all(b"+").unwrap();
+            String::from_utf8(response[1..response.len() - 3].to_vec()).unwrap()
+        };
+
+        assert!(exchange("qSupported:swbreak+").contains("swbreak+"));
+        assert_eq!(exchange("s"), "S05");
+        assert_eq!(exchange("Z0,0606,1"), "OK");
+        assert_eq!(exchange("c"), "T05swbreak:;");
+        assert_eq!(exchange("p5"), "0606");
+        assert_eq!(exchange("z0,0606,1"), "OK");
+        assert_eq!(exchange("Z2,0200,1"), "OK");
+        assert_eq!(exchange("c"), "T05watch:0200;");
+        assert_eq!(exchange("m0200,1"), "02");
+        assert_eq!(exchange("z2,0200,1"), "OK");
+
+        // runs forever until interrupted
+        client.write_all(b"$c#63").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        client.write_all(&[0x03]).unwrap();
+        let mut response = [0; 8];
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"+$S02#b5");
+
+        client.write_all(b"+$k#6b").unwrap();
+        assert!(server.join().unwrap());
+    }
+}
@@ -0,0 +1,269 @@
+// Hex memory editor for the debug windows: a page of CPU or PPU address space, 16 bytes
+// a row, with bytes that changed in the last second highlighted in red, fading to white.
+//
+// The view doesn't own the memory. update() is given a side effect free read (mem_peek
+// or NesPPU::peek) once a frame, and key() hands back the byte a pair of typed hex
+// digits writes for the caller to apply
+
+use std::fmt;
+
+use crate::render::font::{CHAR_HEIGHT, CHAR_WIDTH};
+use crate::render::viewer::{Image, TEXT_COLOR};
+
+pub const ROWS: usize = 32;
+const COLUMNS: usize = 16;
+// Frames a changed byte stays highlighted
+const HIGHLIGHT_FRAMES: u8 = 60;
+const CURSOR_COLOR: (u8, u8, u8) = (0x30, 0x30, 0xc0);
+// "$0000: " before the bytes
+const ADDRESS_CHARS: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+    Cpu,
+    Ppu,
+}
+
+impl AddressSpace {
+    pub fn size(self) -> usize {
+        match self {
+            AddressSpace::Cpu => 0x10000,
+            AddressSpace::Ppu => 0x4000,
+        }
+    }
+}
+
+impl fmt::Display for AddressSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressSpace::Cpu => f.write_str("CPU"),
+            AddressSpace::Ppu => f.write_str("PPU"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    // toggle between CPU and PPU memory
+    SwitchSpace,
+    // a hex digit, 0-15
+    Digit(u8),
+}
+
+pub struct MemoryView {
+    space: AddressSpace,
+    cursor: u16,
+    // address of the first row shown
+    top: u16,
+    // high nibble typed at the cursor, waiting for the low one
+    pending: Option<u8>,
+    // contents at the last update, and frames left highlighting each byte
+    contents: Vec<u8>,
+    age: Vec<u8>,
+}
+
+impl MemoryView {
+    pub fn new() -> Self {
+        MemoryView {
+            space: AddressSpace::Cpu,
+            cursor: 0,
+            top: 0,
+            pending: None,
+            contents: Vec::new(),
+            age: Vec::new(),
+        }
+    }
+
+    pub fn space(&self) -> AddressSpace {
+        self.space
+    }
+
+    pub fn cursor(&self) -> u16 {
+        self.cursor
+    }
+
+    // Read the whole address space, marking the bytes that differ from last time
+    pub fn update(&mut self, read: impl Fn(u16) -> u8) {
+        let size = self.space.size();
+        let first = self.contents.len() != size;
+        if first {
+            self.contents = vec![0; size];
+            self.age = vec![0; size];
+        }
+        for addr in 0..size {
+            let value = read(addr as u16);
+            if !first && value != self.contents[addr] {
+                self.age[addr] = HIGHLIGHT_FRAMES;
+            } else {
+                self.age[addr] = self.age[addr].saturating_sub(1);
+            }
+            self.contents[addr] = value;
+        }
+    }
+
+    // Returns the (address, value) write completed by a second hex digit
+    pub fn key(&mut self, key: MemoryKey) -> Option<(u16, u8)> {
+        let page = (ROWS * COLUMNS) as i32;
+        let mut write = None;
+        match key {
+            MemoryKey::Up => self.move_cursor(-(COLUMNS as i32)),
+            MemoryKey::Down => self.move_cursor(COLUMNS as i32),
+            MemoryKey::Left => self.move_cursor(-1),
+            MemoryKey::Right => self.move_cursor(1),
+            MemoryKey::PageUp => self.move_cursor(-page),
+            MemoryKey::PageDown => self.move_cursor(page),
+            MemoryKey::Home => self.cursor = 0,
+            MemoryKey::End => self.cursor = (self.space.size() - 1) as u16,
+            MemoryKey::SwitchSpace => {
+                self.space = match self.space {
+                    AddressSpace::Cpu => AddressSpace::Ppu,
+                    AddressSpace::Ppu => AddressSpace::Cpu,
+                };
+                self.cursor = 0;
+                self.contents.clear();
+                self.age.clear();
+            }
+            MemoryKey::Digit(digit) => match self.pending.take() {
+                None => self.pending = Some(digit & 0xf),
+                Some(high) => {
+                    write = Some((self.cursor, high << 4 | (digit & 0xf)));
+                    self.move_cursor(1);
+                }
+            },
+        }
+        if !matches!(key, MemoryKey::Digit(_)) {
+            self.pending = None;
+        }
+
+        // keep the cursor on screen
+        let row = self.cursor & !(COLUMNS as u16 - 1);
+        if row < self.top {
+            self.top = row;
+        } else if row as usize >= self.top as usize + page as usize {
+            self.top = row - ((ROWS - 1) * COLUMNS) as u16;
+        }
+        write
+    }
+
+    fn move_cursor(&mut self, delta: i32) {
+        let last = self.space.size() as i32 - 1;
+        self.cursor = (self.cursor as i32 + delta).clamp(0, last) as u16;
+    }
+
+    // A title line, then ROWS rows of "$ADDR: bytes"
+    pub fn render(&self) -> Image {
+        let width = (ADDRESS_CHARS + COLUMNS * 3) * CHAR_WIDTH + 2;
+        let mut image = Image::new(width, (ROWS + 2) * CHAR_HEIGHT + 2);
+        let title = format!("{} ${:04X}   TAB: CPU/PPU", self.space, self.cursor);
+        image.draw_text(2, 2, &title, TEXT_COLOR);
+
+        for row in 0..ROWS {
+            let addr = self.top as usize + row * COLUMNS;
+            if addr >= self.space.size() {
+                break;
+            }
+            let y = (row + 2) * CHAR_HEIGHT + 2;
+            image.draw_text(2, y, &format!("${:04X}:", addr), TEXT_COLOR);
+
+            for column in 0..COLUMNS {
+                let addr = addr + column;
+                let x = 2 + (ADDRESS_CHARS + column * 3) * CHAR_WIDTH;
+                let value = self.contents.get(addr).copied().unwrap_or(0);
+                let text = if addr == self.cursor as usize {
+                    image.fill_rect(x - 1, y - 1, 2 * CHAR_WIDTH + 1, CHAR_HEIGHT, CURSOR_COLOR);
+                    match self.pending {
+                        Some(high) => format!("{:X}-", high),
+                        None => format!("{:02X}", value),
+                    }
+                } else {
+                    format!("{:02X}", value)
+                };
+                image.draw_text(x, y, &text, self.color(addr));
+            }
+        }
+        image
+    }
+
+    fn color(&self, addr: usize) -> (u8, u8, u8) {
+        let age = self.age.get(addr).copied().unwrap_or(0) as usize;
+        let fade = (0xff - age * 0xff / HIGHLIGHT_FRAMES as usize) as u8;
+        (0xff, fade, fade)
+    }
+}
+
+impl Default for MemoryView {
+    fn default() -> Self {
+        MemoryView::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_changes_are_highlighted() {
+        let mut memory = vec![0u8; 0x10000];
+        let mut view = MemoryView::new();
+        view.update(|addr| memory[addr as usize]);
+        assert_eq!(view.color(0x10), TEXT_COLOR);
+
+        memory[0x10] = 1;
+        view.update(|addr| memory[addr as usize]);
+        assert_eq!(view.color(0x10), (0xff, 0, 0));
+        assert_eq!(view.color(0x11), TEXT_COLOR);
+
+        for _ in 0..HIGHLIGHT_FRAMES {
+            view.update(|addr| memory[addr as usize]);
+        }
+        assert_eq!(view.color(0x10), TEXT_COLOR);
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut view = MemoryView::new();
+        view.key(MemoryKey::Down);
+        view.key(MemoryKey::Right);
+        assert_eq!(view.cursor(), 0x11);
+        assert_eq!(view.key(MemoryKey::Digit(0xa)), None);
+        assert_eq!(view.key(MemoryKey::Digit(0x5)), Some((0x11, 0xa5)));
+        assert_eq!(view.cursor(), 0x12);
+
+        // moving drops a half typed byte
+        view.key(MemoryKey::Digit(1));
+        view.key(MemoryKey::Left);
+        assert_eq!(view.key(MemoryKey::Digit(2)), None);
+
+        view.key(MemoryKey::Left);
+        view.key(MemoryKey::Up);
+        view.key(MemoryKey::Up);
+        assert_eq!(view.cursor(), 0);
+    }
+
+    #[test]
+    fn test_scrolling_and_spaces() {
+        let mut view = MemoryView::new();
+        view.key(MemoryKey::PageDown);
+        assert_eq!(view.cursor(), 0x200);
+        assert_eq!(view.top, 0x10);
+        view.key(MemoryKey::End);
+        assert_eq!((view.cursor(), view.top), (0xffff, 0xfe00));
+
+        view.key(MemoryKey::SwitchSpace);
+        assert_eq!((view.space(), view.cursor(), view.top), (AddressSpace::Ppu, 0, 0));
+        view.key(MemoryKey::End);
+        view.key(MemoryKey::Right);
+        assert_eq!(view.cursor(), 0x3fff);
+
+        let image = view.render();
+        assert_eq!(image.width, 222);
+    }
+}
@@ -0,0 +1,907 @@
+// Monitor style debugger wrapped around a CPU.
+//
+// Execute breakpoints are handed to the CPU's breakpoint set and memory breakpoints to
+// its watchpoints, so `continue` runs at full speed until one of them fires; conditions
+// are only evaluated then. Breakpoints with a condition but no address are checked
+// before every instruction instead.
+//
+// execute() takes one command line. Addresses and values typed in commands are hex
+// (`b 8000`, `r a=1f`, a leading $ or 0x is allowed); conditions use the expression
+// syntax from expr.rs, where bare numbers are decimal.
+//
+// `cheat` manages the CPU's cheat codes and `search` narrows down the RAM byte holding
+// some value across `frame` steps, see cheats.rs
+
+pub mod expr;
+pub mod gdb;
+pub mod memview;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use crate::bus::Bus;
+use crate::cheats::{Code, RamSearch, SearchFilter};
+use crate::cpu::{CpuFlags, HaltReason, Mem, WatchHit, Watchpoint, CPU};
+use crate::disasm::SymbolTable;
+use crate::{disasm, headless, trace};
+use expr::Expr;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+// Cycles run between checks while continuing, about a frame
+const RUN_CHUNK: usize = 30_000;
+
+// RAM covered by `search`, the NES's internal 2KB
+const SEARCH_RAM: u16 = 0x0800;
+// Candidates a search lists
+const SEARCH_SHOWN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakKind {
+    Execute,
+    Read,
+    Write,
+    // reads and writes
+    Access,
+}
+
+impl fmt::Display for BreakKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BreakKind::Execute => "exec",
+            BreakKind::Read => "read",
+            BreakKind::Write => "write",
+            BreakKind::Access => "access",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakKind,
+    // Address range `start..=end`, None to check `condition` before every instruction
+    pub range: Option<(u16, u16)>,
+    pub condition: Option<Expr>,
+}
+
+impl Breakpoint {
+    fn watchpoint(&self) -> Option<Watchpoint> {
+        let (start, end) = self.range?;
+        let (read, write) = match self.kind {
+            BreakKind::Execute => return None,
+            BreakKind::Read => (true, false),
+            BreakKind::Write => (false, true),
+            BreakKind::Access => (true, true),
+        };
+        Some(Watchpoint { start, end, read, write })
+    }
+
+    fn covers(&self, addr: u16) -> bool {
+        self.range.is_none_or(|(start, end)| (start..=end).contains(&addr))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} {}", self.id, self.kind)?;
+        match self.range {
+            Some((start, end)) if start == end => write!(f, " ${:04X}", start)?,
+            Some((start, end)) => write!(f, " ${:04X}-${:04X}", start, end)?,
+            None => {}
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+// Why a step or continue stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // The requested steps completed
+    Done,
+    Breakpoint(usize, Option<WatchHit>),
+    Halt(HaltReason),
+    // resume_for() ran out of cycles
+    Pause,
+}
+
+pub struct Debugger<B: Bus> {
+    pub cpu: CPU<B>,
+    // Labels accepted in place of addresses and shown in listings
+    pub symbols: SymbolTable,
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+    // The last resume_for() paused; the breakpoint at PC hasn't been checked yet
+    paused: bool,
+    // Source files shown when stopping, loaded on first use
+    sources: HashMap<String, Option<Vec<String>>>,
+    // Where `cheat save` writes the cheats
+    pub cheat_file: Option<String>,
+    search: Option<RamSearch>,
+}
+
+impl<B: Bus> Debugger<B> {
+    pub fn new(cpu: CPU<B>) -> Self {
+        Debugger {
+            cpu,
+            symbols: SymbolTable::new(),
+            breakpoints: Vec::new(),
+            next_id: 1,
+            paused: false,
+            sources: HashMap::new(),
+            cheat_file: None,
+            search: None,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // Returns the id of the new breakpoint
+    pub fn add_breakpoint(&mut self, kind: BreakKind, range: Option<(u16, u16)>, condition: Option<Expr>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, kind, range, condition });
+        self.sync();
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.id != id);
+        self.sync();
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.sync();
+    }
+
+    // Mirror the breakpoint list into the CPU
+    fn sync(&mut self) {
+        self.cpu.clear_breakpoints();
+        self.cpu.clear_watchpoints();
+        for bp in &self.breakpoints {
+            match (bp.kind, bp.range) {
+                (BreakKind::Execute, Some((start, end))) => {
+                    for addr in start..=end {
+                        self.cpu.add_breakpoint(addr);
+                    }
+                }
+                _ => {
+                    if let Some(watchpoint) = bp.watchpoint() {
+                        self.cpu.add_watchpoint(watchpoint);
+                    }
+                }
+            }
+        }
+    }
+
+    fn has_global_conditions(&self) -> bool {
+        self.breakpoints.iter().any(|bp| bp.kind == BreakKind::Execute && bp.range.is_none())
+    }
+
+    // Execute breakpoint whose address and condition match the CPU before the next
+    // instruction
+    fn execute_hit(&self) -> Option<usize> {
+        let pc = self.cpu.program_counter;
+        self.breakpoints
+            .iter()
+            .find(|bp| {
+                bp.kind == BreakKind::Execute
+                    && bp.covers(pc)
+                    && bp.condition.as_ref().is_none_or(|condition| condition.holds(&self.cpu))
+            })
+            .map(|bp| bp.id)
+    }
+
+    // Memory breakpoint matching an access, checked after the instruction completed
+    fn watch_hit(&self, hit: WatchHit) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .find(|bp| {
+                bp.watchpoint().is_some_and(|wp| wp.matches(hit.access, hit.addr))
+                    && bp.condition.as_ref().is_none_or(|condition| condition.holds(&self.cpu))
+            })
+            .map(|bp| bp.id)
+    }
+
+    // Execute one instruction, reporting memory breakpoints it triggers
+    fn step_once(&mut self) -> Result<u8, Stop> {
+        let result = self.cpu.step();
+        match result.halt {
+            None => Ok(result.opcode),
+            Some(HaltReason::Watchpoint(hit)) => match self.watch_hit(hit) {
+                Some(id) => Err(Stop::Breakpoint(id, Some(hit))),
+                None => Ok(result.opcode),
+            },
+            Some(halt) => Err(Stop::Halt(halt)),
+        }
+    }
+
+    // Step until `done` holds after an instruction, stopping at breakpoints on the way.
+    // The breakpoint at the starting PC only counts with `check_first`
+    fn step_until<F>(&mut self, mut done: F, check_first: bool, budget: usize) -> Stop
+    where
+        F: FnMut(&mut CPU<B>, u8) -> bool,
+    {
+        let start = self.cpu.cycles;
+        let mut check = check_first;
+        loop {
+            if check {
+                if let Some(id) = self.execute_hit() {
+                    return Stop::Breakpoint(id, None);
+                }
+            }
+            check = true;
+            if self.cpu.cycles - start >= budget {
+                self.paused = true;
+                return Stop::Pause;
+            }
+
+            match self.step_once() {
+                Ok(opcode) if done(&mut self.cpu, opcode) => return Stop::Done,
+                Ok(_) => {}
+                Err(stop) => return stop,
+            }
+        }
+    }
+
+    // Execute `count` instructions (at least one), entering subroutines
+    pub fn step_into(&mut self, count: usize) -> Stop {
+        self.paused = false;
+        let mut remaining = count.max(1);
+        let done = |_: &mut CPU<B>, _| {
+            remaining -= 1;
+            remaining == 0
+        };
+        self.step_until(done, false, usize::MAX)
+    }
+
+    // Execute one instruction, running a JSR through to its return
+    pub fn step_over(&mut self) -> Stop {
+        if self.cpu.mem_peek(self.cpu.program_counter) != JSR {
+            return self.step_into(1);
+        }
+        self.paused = false;
+        let target = self.cpu.program_counter.wrapping_add(3);
+        let stack_pointer = self.cpu.stack_pointer;
+        let done = |cpu: &CPU<B>| cpu.program_counter == target && cpu.stack_pointer == stack_pointer;
+        if self.has_global_conditions() {
+            return self.step_until(|cpu, _| done(cpu), false, usize::MAX);
+        }
+        self.cpu.add_breakpoint(target);
+        let stop = self.run(done, false, usize::MAX);
+        self.sync();
+        stop
+    }
+
+    // Run until the current subroutine returns with RTS (or an interrupt handler with RTI)
+    pub fn step_out(&mut self) -> Stop {
+        self.paused = false;
+        let stack_pointer = self.cpu.stack_pointer;
+        let done = |cpu: &mut CPU<B>, opcode| (opcode == RTS || opcode == RTI) && cpu.stack_pointer > stack_pointer;
+        self.step_until(done, false, usize::MAX)
+    }
+
+    // Run `count` frames (at least one), stopping at breakpoints. Freeze codes are
+    // written back at each frame like CPU::run_frame() does
+    pub fn run_frames(&mut self, count: usize) -> Stop {
+        self.paused = false;
+        // a frame finished before now doesn't count
+        self.cpu.bus.poll_frame();
+        let mut remaining = count.max(1);
+        let done = |cpu: &mut CPU<B>, _| {
+            if cpu.bus.poll_frame() {
+                cpu.write_freezes();
+                remaining -= 1;
+            }
+            remaining == 0
+        };
+        self.step_until(done, false, usize::MAX)
+    }
+
+    // Run until a breakpoint fires or the CPU halts
+    pub fn resume(&mut self) -> Stop {
+        self.paused = false;
+        self.resume_for(usize::MAX)
+    }
+
+    // Same as resume(), giving up with Stop::Pause after about `budget` cycles so the
+    // caller can check for user input. Calling it again carries on where it paused
+    pub fn resume_for(&mut self, budget: usize) -> Stop {
+        let check_first = std::mem::take(&mut self.paused);
+        if self.has_global_conditions() {
+            return self.step_until(|_, _| false, check_first, budget);
+        }
+        self.run(|_| false, check_first, budget)
+    }
+
+    // Give up on a paused resume_for(); the next one starts afresh
+    pub fn interrupt(&mut self) {
+        self.paused = false;
+    }
+
+    // Full speed run on the CPU's own breakpoints and watchpoints. `done` is checked
+    // whenever the CPU stops at an execute breakpoint
+    fn run<F>(&mut self, done: F, check_first: bool, budget: usize) -> Stop
+    where
+        F: Fn(&CPU<B>) -> bool,
+    {
+        if check_first {
+            if let Some(id) = self.execute_hit() {
+                return Stop::Breakpoint(id, None);
+            }
+        }
+        let start = self.cpu.cycles;
+        // a breakpoint at the current PC must not stop us straight away
+        if let Err(stop) = self.step_once() {
+            return stop;
+        }
+        loop {
+            if done(&self.cpu) {
+                return Stop::Done;
+            }
+            // run_for_cycles() doesn't stop at a breakpoint on the PC it starts from
+            if let Some(id) = self.execute_hit() {
+                return Stop::Breakpoint(id, None);
+            }
+            let elapsed = self.cpu.cycles - start;
+            if elapsed >= budget {
+                self.paused = true;
+                return Stop::Pause;
+            }
+            match self.cpu.run_for_cycles(RUN_CHUNK.min(budget - elapsed)).halt {
+                None => {}
+                Some(HaltReason::Breakpoint(_)) => {
+                    if done(&self.cpu) {
+                        return Stop::Done;
+                    }
+                    if let Some(id) = self.execute_hit() {
+                        return Stop::Breakpoint(id, None);
+                    }
+                    // condition didn't hold, carry on past it
+                    if let Err(stop) = self.step_once() {
+                        return stop;
+                    }
+                }
+                Some(HaltReason::Watchpoint(hit)) => {
+                    if let Some(id) = self.watch_hit(hit) {
+                        return Stop::Breakpoint(id, Some(hit));
+                    }
+                }
+                Some(halt) => return Stop::Halt(halt),
+            }
+        }
+    }
+
+    // Disassemble `before` instructions leading up to `addr` and `after` from it on
+    pub fn disassemble_around(&self, addr: u16, before: usize, after: usize) -> Vec<disasm::Instruction> {
+        let decode_at = |addr: u16| {
+            let bytes: Vec<u8> = (0..3).map(|i| self.cpu.mem_peek(addr.wrapping_add(i))).collect();
+            disasm::decode_variant(self.cpu.variant, &bytes, addr, Some(&self.symbols))
+        };
+
+        // find the furthest start address whose instruction stream lands on `addr`
+        let mut leading = Vec::new();
+        for distance in (1..=before * 3).rev() {
+            let mut pos = addr.wrapping_sub(distance as u16);
+            let mut instructions = Vec::new();
+            while addr.wrapping_sub(pos) as usize <= distance && pos != addr {
+                let instruction = decode_at(pos);
+                pos = pos.wrapping_add(instruction.len() as u16);
+                instructions.push(instruction);
+            }
+            if pos == addr {
+                leading = instructions;
+                break;
+            }
+        }
+        let skip = leading.len().saturating_sub(before);
+
+        let mut result: Vec<_> = leading.into_iter().skip(skip).collect();
+        let mut pos = addr;
+        for _ in 0..after {
+            let instruction = decode_at(pos);
+            pos = pos.wrapping_add(instruction.len() as u16);
+            result.push(instruction);
+        }
+        result
+    }
+
+    // Run one command line and return what it prints
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command.to_ascii_lowercase(),
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+
+        match command.as_str() {
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| format!("invalid step count: {}", count))?,
+                    None => 1,
+                };
+                let stop = self.step_into(count);
+                Ok(self.report(stop))
+            }
+            "n" | "next" => {
+                let stop = self.step_over();
+                Ok(self.report(stop))
+            }
+            "o" | "out" | "finish" => {
+                let stop = self.step_out();
+                Ok(self.report(stop))
+            }
+            "c" | "continue" => {
+                let stop = self.resume();
+                Ok(self.report(stop))
+            }
+            "f" | "frame" => {
+                let count = match args.first() {
+                    Some(count) => count.parse().map_err(|_| format!("invalid frame count: {}", count))?,
+                    None => 1,
+                };
+                let stop = self.run_frames(count);
+                Ok(self.report(stop))
+            }
+            "b" | "break" => self.break_command(BreakKind::Execute, &args),
+            "w" | "watch" => {
+                let (kind, args) = match args.first().map(|mode| mode.to_ascii_lowercase()).as_deref() {
+                    Some("r") => (BreakKind::Read, &args[1..]),
+                    Some("w") => (BreakKind::Write, &args[1..]),
+                    Some("rw") => (BreakKind::Access, &args[1..]),
+                    _ => (BreakKind::Write, &args[..]),
+                };
+                if args.is_empty() {
+                    return Err("usage: watch [r|w|rw] <addr>[-<end>] [if <condition>]".to_string());
+                }
+                self.break_command(kind, args)
+            }
+            "bl" | "list" => Ok(self.breakpoints.iter().map(|bp| format!("{}\n", bp)).collect()),
+            "d" | "delete" => match args.first() {
+                Some(&"all") => {
+                    self.clear_breakpoints();
+                    Ok(String::new())
+                }
+                Some(id) => {
+                    let id = id.trim_start_matches('#');
+                    let id = id.parse().map_err(|_| format!("invalid breakpoint: {}", id))?;
+                    if self.remove_breakpoint(id) {
+                        Ok(String::new())
+                    } else {
+                        Err(format!("no breakpoint #{}", id))
+                    }
+                }
+                None => Err("usage: delete <id>|all".to_string()),
+            },
+            "r" | "regs" => {
+                for assignment in &args {
+                    self.set_register(assignment)?;
+                }
+                Ok(format!("{}\n", trace::trace_with_symbols(&self.cpu, Some(&self.symbols))))
+            }
+            "m" | "mem" => {
+                let addr = self.address(args.first().ok_or("usage: mem <addr> [len]")?)?;
+                let len = match args.get(1) {
+                    Some(len) => parse_hex(len)? as usize,
+                    None => 0x40,
+                };
+                let data: Vec<u8> = (0..len).map(|i| self.cpu.mem_peek(addr.wrapping_add(i as u16))).collect();
+                Ok(headless::hex_dump(&data, addr))
+            }
+            "e" | "edit" => {
+                let (addr, bytes) = args.split_first().ok_or("usage: edit <addr> <byte>...")?;
+                let addr = self.address(addr)?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    let value = parse_hex(byte)?;
+                    if value > 0xff {
+                        return Err(format!("not a byte: {}", byte));
+                    }
+                    self.cpu.mem_write(addr.wrapping_add(i as u16), value as u8);
+                }
+                Ok(String::new())
+            }
+            "u" | "dis" | "disasm" => {
+                let (addr, before) = match args.first() {
+                    Some(addr) => (self.address(addr)?, 0),
+                    None => (self.cpu.program_counter, 5),
+                };
+                let count = match args.get(1) {
+                    Some(count) => count.parse().map_err(|_| format!("invalid count: {}", count))?,
+                    None => 10,
+                };
+                Ok(self.listing(addr, before, count))
+            }
+            "cheat" | "cheats" => self.cheat_command(&args),
+            "search" => self.search_command(&args),
+            "h" | "help" | "?" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command: {} (try help)", command)),
+        }
+    }
+
+    // `<addr>[-<end>] [if <condition>]` or `if <condition>`
+    fn break_command(&mut self, kind: BreakKind, args: &[&str]) -> Result<String, String> {
+        let (range, rest) = match args.first() {
+            Some(word) if word.eq_ignore_ascii_case("if") => (None, args),
+            Some(range) => (Some(self.range(range)?), &args[1..]),
+            None => return Err("usage: break <addr>[-<end>] [if <condition>] | break if <condition>".to_string()),
+        };
+        let condition = match rest.split_first() {
+            Some((word, condition)) if word.eq_ignore_ascii_case("if") && !condition.is_empty() => {
+                Some(Expr::parse(&condition.join(" "), Some(&self.symbols))?)
+            }
+            None => None,
+            _ => return Err("expected `if <condition>` after the address".to_string()),
+        };
+        if range.is_none() && kind != BreakKind::Execute {
+            return Err("watchpoints need an address".to_string());
+        }
+
+        self.add_breakpoint(kind, range, condition);
+        Ok(format!("{}\n", self.breakpoints[self.breakpoints.len() - 1]))
+    }
+
+    // `cheat [list]`, `cheat add <code> [description]`, `cheat on|off <n>|all`,
+    // `cheat del <n>` and `cheat save [file]`; cheats are numbered from 1
+    fn cheat_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let index = |arg: Option<&&str>| -> Result<usize, String> {
+            let arg = arg.ok_or("expected a cheat number")?;
+            match arg.parse::<usize>() {
+                Ok(number) if number >= 1 => Ok(number - 1),
+                _ => Err(format!("invalid cheat number: {}", arg)),
+            }
+        };
+        let cheats = &mut self.cpu.cheats;
+        match args.first().map(|word| word.to_ascii_lowercase()).as_deref() {
+            None | Some("list") => {
+                let mut out = String::new();
+                if !cheats.is_enabled() {
+                    out.push_str("(all cheats off)\n");
+                }
+                for (i, cheat) in cheats.list().iter().enumerate() {
+                    let _ = writeln!(out, "{}: {}", i + 1, cheat);
+                }
+                Ok(out)
+            }
+            Some("add") => {
+                let code: Code = args.get(1).ok_or("usage: cheat add <code> [description]")?.parse()?;
+                let index = cheats.add(code, &args[2..].join(" "));
+                Ok(format!("{}: {}\n", index + 1, cheats.list()[index]))
+            }
+            Some(switch @ ("on" | "off")) => {
+                let enabled = switch == "on";
+                if args.get(1) == Some(&"all") {
+                    cheats.set_all_enabled(enabled);
+                } else {
+                    let index = index(args.get(1))?;
+                    if !cheats.set_enabled(index, enabled) {
+                        return Err(format!("no cheat {}", index + 1));
+                    }
+                }
+                Ok(String::new())
+            }
+            Some("del" | "delete") => {
+                let index = index(args.get(1))?;
+                match cheats.remove(index) {
+                    Some(_) => Ok(String::new()),
+                    None => Err(format!("no cheat {}", index + 1)),
+                }
+            }
+            Some("save") => {
+                let file = match args.get(1) {
+                    Some(file) => file.to_string(),
+                    None => self.cheat_file.clone().ok_or("usage: cheat save <file>")?,
+                };
+                cheats.save(&file)?;
+                Ok(format!("saved {}\n", file))
+            }
+            Some(word) => Err(format!("unknown cheat command: {} (list, add, on, off, del or save)", word)),
+        }
+    }
+
+    // `search` starts over with every RAM byte, `search <filter>` keeps the ones that
+    // compare with their value at the previous search (= != > <) or equal a value
+    fn search_command(&mut self, args: &[&str]) -> Result<String, String> {
+        let ram: Vec<u8> = (0..SEARCH_RAM).map(|addr| self.cpu.mem_peek(addr)).collect();
+        let filter = match args.first() {
+            None => {
+                self.search = Some(RamSearch::new(&ram));
+                return Ok(format!("{} candidates\n", SEARCH_RAM));
+            }
+            Some(filter) => filter.parse::<SearchFilter>()?,
+        };
+        let search = self.search.get_or_insert_with(|| RamSearch::new(&ram));
+        let count = search.filter(&ram, filter);
+
+        let mut out = format!("{} candidates\n", count);
+        for (addr, value) in search.candidates().into_iter().take(SEARCH_SHOWN) {
+            let _ = writeln!(out, "${:04X} = {:02X}", addr, value);
+        }
+        if count > SEARCH_SHOWN {
+            out.push_str("...\n");
+        }
+        Ok(out)
+    }
+
+    // `<register>=<value>` with registers A X Y SP P PC
+    fn set_register(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("expected <register>=<value>: {}", assignment))?;
+        let value = parse_hex(value)?;
+        let byte = || u8::try_from(value).map_err(|_| format!("not a byte: {}", assignment));
+
+        match name.to_ascii_uppercase().as_str() {
+            "A" => self.cpu.register_a = byte()?,
+            "X" => self.cpu.register_x = byte()?,
+            "Y" => self.cpu.register_y = byte()?,
+            "SP" | "S" => self.cpu.stack_pointer = byte()?,
+            "P" => self.cpu.status = CpuFlags::from_bits_truncate(byte()?),
+            "PC" => self.cpu.program_counter = value,
+            _ => return Err(format!("unknown register: {}", name)),
+        }
+        Ok(())
+    }
+
+    // A label or a hex address
+    fn address(&self, text: &str) -> Result<u16, String> {
+        match self.symbols.lookup(text) {
+            Some(addr) => Ok(addr),
+            None => parse_hex(text).map_err(|_| format!("unknown label or address: {}", text)),
+        }
+    }
+
+    // "8000", "8000-80ff" or the same with labels
+    fn range(&self, text: &str) -> Result<(u16, u16), String> {
+        if let Some(addr) = self.symbols.lookup(text) {
+            return Ok((addr, addr));
+        }
+        match text.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (self.address(start)?, self.address(end)?);
+                if start > end {
+                    return Err(format!("empty range: {}", text));
+                }
+                Ok((start, end))
+            }
+            None => {
+                let addr = self.address(text)?;
+                Ok((addr, addr))
+            }
+        }
+    }
+
+    fn listing(&self, addr: u16, before: usize, count: usize) -> String {
+        let mut out = String::new();
+        for instruction in self.disassemble_around(addr, before, count) {
+            if let Some(label) = self.symbols.label(instruction.address) {
+                let _ = writeln!(out, "{}:", label);
+            }
+            let marker = if instruction.address == self.cpu.program_counter { ">" } else { " " };
+            let raw: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let _ = writeln!(out, "{} ${:04X}  {:<8}  {}", marker, instruction.address, raw.join(" "), instruction);
+        }
+        out
+    }
+
+    // "file:line: text" for the source line at PC, if the symbols have one
+    fn source_line(&mut self) -> Option<String> {
+        let (path, line) = self.symbols.source_line(self.cpu.program_counter)?;
+        let lines = self.sources.entry(path.to_string()).or_insert_with(|| {
+            std::fs::read_to_string(path).ok().map(|text| text.lines().map(str::to_string).collect())
+        });
+        let text = lines.as_ref().and_then(|lines| lines.get(line.wrapping_sub(1)));
+        Some(match text {
+            Some(text) => format!("{}:{}: {}", path, line, text.trim()),
+            None => format!("{}:{}", path, line),
+        })
+    }
+
+    // Stop reason, the source line and the next instruction and registers
+    fn report(&mut self, stop: Stop) -> String {
+        let reason = match stop {
+            Stop::Done | Stop::Pause => String::new(),
+            Stop::Breakpoint(id, hit) => {
+                let bp = self.breakpoints.iter().find(|bp| bp.id == id);
+                let mut line = format!("hit {}", bp.map_or(format!("#{}", id), |bp| bp.to_string()));
+                if let Some(hit) = hit {
+                    let _ = write!(line, " ({})", hit);
+                }
+                line + "\n"
+            }
+            Stop::Halt(halt) => format!("halted: {}\n", halt),
+        };
+        let source = self.source_line().map_or(String::new(), |line| line + "\n");
+        format!("{}{}{}\n", reason, source, trace::trace_with_symbols(&self.cpu, Some(&self.symbols)))
+    }
+}
+
+// Parse an address or value given as "$8000", "0x8000" or "8000"
+pub fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text
+        .trim_start_matches('#')
+        .trim_start_matches('$')
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value: {}", text))
+}
+
+const HELP: &str = "\
+s|step [n]                         step n instructions (into subroutines)
+n|next                             step over JSR
+o|out                              run until the current subroutine returns
+c|continue                         run until a breakpoint or halt
+f|frame [n]                        run n frames
+b|break <addr>[-<end>] [if <cond>] break before executing in the range
+b|break if <cond>                  break before any instruction where cond holds
+w|watch [r|w|rw] <addr>[-<end>] [if <cond>]
+                                   break after memory is read and/or written (default w)
+bl|list, d|delete <id>|all         list or delete breakpoints
+r|regs [<reg>=<value>...]          show or set A X Y SP P PC
+m|mem <addr> [len]                 hex dump memory
+e|edit <addr> <byte>...            write memory
+u|dis [addr] [count]               disassemble, around PC by default
+cheat [list]                       list the cheat codes
+cheat add <code> [description]     add a Game Genie (SXIOPO) or RAM freeze (0075:09) code
+cheat on|off <n>|all, cheat del <n>
+cheat save [file]                  write the cheat file
+search [= | != | > | < | <value>]  RAM search: start over, or keep the bytes that are
+                                   unchanged, changed, greater or less since the last
+                                   search, or equal to a value
+q|quit
+Addresses and values are hex; addresses can also be labels. Conditions: A == #$10 && X > 3, [$0200+X] & $80 != 0
+";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Access;
+
+    // $0600 LDX #0; JSR $060A; INX; STA $0200; BRK
+    // $060A LDA #$10; INX; RTS
+    fn debugger() -> Debugger<crate::bus::FlatBus> {
+        let mut cpu = CPU::new();
+        cpu.load(vec![
+            0xa2, 0x00, 0x20, 0x0a, 0x06, 0xe8, 0x8d, 0x00, 0x02, 0x00, 0xa9, 0x10, 0xe8, 0x60,
+        ]);
+        cpu.reset();
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.step_into(1), Stop::Done);
+        assert_eq!(dbg.step_over(), Stop::Done);
+        assert_eq!((dbg.cpu.program_counter, dbg.cpu.register_a, dbg.cpu.register_x), (0x0605, 0x10, 1));
+
+        let mut dbg = debugger();
+        assert_eq!(dbg.step_into(2), Stop::Done);
+        assert_eq!(dbg.cpu.program_counter, 0x060a);
+        assert_eq!(dbg.step_out(), Stop::Done);
+        assert_eq!(dbg.cpu.program_counter, 0x0605);
+    }
+
+    #[test]
+    fn test_breakpoints_and_conditions() {
+        let mut dbg = debugger();
+        let id = dbg.add_breakpoint(BreakKind::Execute, Some((0x060a, 0x060d)), Some("X == 5".parse().unwrap()));
+        assert_eq!(dbg.resume(), Stop::Halt(HaltReason::Brk));
+        assert!(dbg.remove_breakpoint(id));
+
+        let mut dbg = debugger();
+        let id = dbg.add_breakpoint(BreakKind::Execute, Some((0x060c, 0x060c)), None);
+        assert_eq!(dbg.resume(), Stop::Breakpoint(id, None));
+        assert_eq!(dbg.cpu.program_counter, 0x060c);
+
+        let mut dbg = debugger();
+        let id = dbg.add_breakpoint(BreakKind::Execute, None, Some("X == 2".parse().unwrap()));
+        assert_eq!(dbg.resume(), Stop::Breakpoint(id, None));
+        assert_eq!(dbg.cpu.program_counter, 0x0606);
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut dbg = debugger();
+        let id = dbg.add_breakpoint(BreakKind::Write, Some((0x0200, 0x0200)), Some("A == $10".parse().unwrap()));
+        let hit = WatchHit { access: Access::Write, addr: 0x0200, value: 0x10 };
+        assert_eq!(dbg.resume(), Stop::Breakpoint(id, Some(hit)));
+        assert_eq!(dbg.cpu.program_counter, 0x0609);
+    }
+
+    #[test]
+    fn test_disassemble_around() {
+        let mut dbg = debugger();
+        dbg.cpu.program_counter = 0x0605;
+        let addresses: Vec<u16> = dbg.disassemble_around(0x0605, 2, 2).iter().map(|i| i.address).collect();
+        assert_eq!(addresses, vec![0x0600, 0x0602, 0x0605, 0x0606]);
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut dbg = debugger();
+        dbg.execute("r a=42 x=$1").unwrap();
+        assert_eq!((dbg.cpu.register_a, dbg.cpu.register_x), (0x42, 1));
+        assert!(dbg.execute("r q=1").is_err());
+
+        dbg.execute("e 0300 01 ff").unwrap();
+        assert!(dbg.execute("m 0300 2").unwrap().starts_with("0300: 01 FF"));
+
+        assert_eq!(dbg.execute("b 060a if x > 0").unwrap(), "#1 exec $060A if x > 0\n");
+        assert_eq!(dbg.execute("w rw 0200-02ff").unwrap(), "#2 access $0200-$02FF\n");
+        assert!(dbg.execute("w").is_err());
+        assert!(dbg.execute("b 0300 0400").is_err());
+        assert_eq!(dbg.execute("bl").unwrap().lines().count(), 2);
+        dbg.execute("d 1").unwrap();
+        assert!(dbg.execute("d 1").is_err());
+
+        assert!(dbg.execute("c").unwrap().starts_with("hit #2 access $0200-$02FF (write of $10 to $0200)"));
+        assert!(dbg.execute("u").unwrap().contains("> $0609"));
+        assert!(dbg.execute("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let dir = std::env::temp_dir().join(format!("nes_debugger_symbols_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.s");
+        std::fs::write(&source, "ldx #0\njsr UpdatePlayer\n").unwrap();
+
+        let mut dbg = debugger();
+        dbg.symbols.insert(0x060a, "UpdatePlayer");
+        dbg.symbols.insert(0x0200, "screen");
+        let file = dbg.symbols.add_file(source.to_str().unwrap());
+        dbg.symbols.insert_line(0x0602, file, 2);
+
+        assert!(dbg.execute("u 0600 2").unwrap().contains("JSR UpdatePlayer"));
+        assert!(dbg.execute("u UpdatePlayer 1").unwrap().starts_with("UpdatePlayer:\n"));
+        assert_eq!(dbg.execute("b UpdatePlayer").unwrap(), "#1 exec $060A\n");
+        assert!(dbg.execute("w screen if [screen] == 0").is_ok());
+        assert!(dbg.execute("b NoSuchLabel").is_err());
+
+        let report = dbg.execute("s").unwrap();
+        assert!(report.contains(&format!("{}:2: jsr UpdatePlayer", source.display())), "{}", report);
+        assert!(report.contains("JSR UpdatePlayer"));
+        assert!(dbg.execute("c").unwrap().starts_with("hit #1"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cheats_and_ram_search() {
+        let mut cpu = CPU::new();
+        cpu.load(crate::asm::program("loop: inc $20\n jmp loop"));
+        cpu.reset();
+        let mut dbg = Debugger::new(cpu);
+
+        assert_eq!(dbg.execute("search").unwrap(), "2048 candidates\n");
+        dbg.execute("frame").unwrap();
+        assert!(dbg.execute("search !=").unwrap().starts_with("1 candidates\n$0020 = "));
+        dbg.execute("frame 2").unwrap();
+        assert_eq!(dbg.execute("search =").unwrap(), "0 candidates\n");
+
+        assert_eq!(dbg.execute("cheat add 0020:05 counter").unwrap(), "1: 0020:05 counter\n");
+        dbg.execute("frame").unwrap();
+        assert_eq!(dbg.cpu.bus.mem_peek(0x20), 5);
+        dbg.execute("search").unwrap();
+        assert!(dbg.execute("search 5").unwrap().starts_with("1 candidates\n"));
+
+        dbg.execute("cheat off 1").unwrap();
+        assert_eq!(dbg.execute("cheat").unwrap(), "1: -0020:05 counter\n");
+        assert!(dbg.execute("cheat on 2").is_err());
+        assert!(dbg.execute("cheat add XYZ").is_err());
+        dbg.execute("cheat del 1").unwrap();
+        assert_eq!(dbg.execute("cheat list").unwrap(), "");
+    }
+}
@@ -0,0 +1,377 @@
+// 6502 disassembler driven by the OpCode table in opcodes.rs
+//
+// Operand syntax per addressing mode:
+// Immediate    LDA #$10        Indirect_X   LDA ($10,X)
+// ZeroPage     LDA $10         Indirect_Y   LDA ($10),Y
+// ZeroPage_X   LDA $10,X       Indirect     JMP ($FFFC)
+// Absolute     LDA $0200       Relative     BNE $8010 (resolved branch target)
+// Absolute_X   STA $0200,X     Accumulator  ASL A
+//
+// 65C02 only:
+// ZeroPage_Indirect    LDA ($10)
+// Indirect_Absolute_X  JMP ($1234,X)
+// ZeroPage_Relative    BBR0 $10,$8012
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cdl::PrgFlags;
+use crate::cpu::{AddressingMode, CpuVariant, Mem};
+use crate::opcodes;
+
+// Address -> label mapping used to print names instead of raw addresses, plus the
+// reverse lookup and source line mapping filled in by the symbol file loaders
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    labels: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+    files: Vec<String>,
+    // address -> (index into files, line number)
+    lines: HashMap<u16, (usize, usize)>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    // Later labels for the same address replace earlier ones in listings; every name
+    // stays available to lookup()
+    pub fn insert(&mut self, addr: u16, label: &str) {
+        self.labels.insert(addr, label.to_string());
+        self.addresses.insert(label.to_string(), addr);
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    pub fn lookup(&self, label: &str) -> Option<u16> {
+        self.addresses.get(label).copied()
+    }
+
+    // Register a source file, returning its index for insert_line()
+    pub fn add_file(&mut self, path: &str) -> usize {
+        self.files.push(path.to_string());
+        self.files.len() - 1
+    }
+
+    pub fn insert_line(&mut self, addr: u16, file: usize, line: usize) {
+        self.lines.insert(addr, (file, line));
+    }
+
+    // Source file and line the byte at `addr` was assembled from
+    pub fn source_line(&self, addr: u16) -> Option<(&str, usize)> {
+        let &(file, line) = self.lines.get(&addr)?;
+        Some((self.files.get(file)?.as_str(), line))
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+// A single decoded instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+}
+
+impl Instruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+fn format_address(addr: u16, zero_page: bool, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.label(addr)) {
+        Some(label) => label.to_string(),
+        None if zero_page => format!("${:02X}", addr),
+        None => format!("${:04X}", addr),
+    }
+}
+
+// Format the operand of an instruction located at `address` from its operand bytes
+pub fn format_operand(
+    mode: &AddressingMode,
+    address: u16,
+    operand: &[u8],
+    symbols: Option<&SymbolTable>,
+) -> String {
+    let byte = || operand[0] as u16;
+    let word = || (operand[1] as u16) << 8 | operand[0] as u16;
+
+    match mode {
+        AddressingMode::Immediate => format!("#${:02X}", byte()),
+        AddressingMode::ZeroPage => format_address(byte(), true, symbols),
+        AddressingMode::ZeroPage_X => format!("{},X", format_address(byte(), true, symbols)),
+        AddressingMode::ZeroPage_Y => format!("{},Y", format_address(byte(), true, symbols)),
+        AddressingMode::Absolute => format_address(word(), false, symbols),
+        AddressingMode::Absolute_X => format!("{},X", format_address(word(), false, symbols)),
+        AddressingMode::Absolute_Y => format!("{},Y", format_address(word(), false, symbols)),
+        AddressingMode::Indirect_X => format!("({},X)", format_address(byte(), true, symbols)),
+        AddressingMode::Indirect_Y => format!("({}),Y", format_address(byte(), true, symbols)),
+        AddressingMode::Indirect => format!("({})", format_address(word(), false, symbols)),
+        AddressingMode::Relative => {
+            let target = address
+                .wrapping_add(2)
+                .wrapping_add(operand[0] as i8 as u16);
+            format_address(target, false, symbols)
+        }
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::ZeroPage_Indirect => format!("({})", format_address(byte(), true, symbols)),
+        AddressingMode::Indirect_Absolute_X => format!("({},X)", format_address(word(), false, symbols)),
+        AddressingMode::ZeroPage_Relative => {
+            let target = address
+                .wrapping_add(3)
+                .wrapping_add(operand[1] as i8 as u16);
+            format!(
+                "{},{}",
+                format_address(byte(), true, symbols),
+                format_address(target, false, symbols)
+            )
+        }
+        AddressingMode::NoneAddressing => String::new(),
+    }
+}
+
+// Decode the NES (2A03) instruction at the start of `bytes`, which is located at `address`.
+// Unknown opcodes and instructions cut off by the end of the slice become `.byte` directives
+pub fn decode(bytes: &[u8], address: u16, symbols: Option<&SymbolTable>) -> Instruction {
+    decode_variant(CpuVariant::Nes2A03, bytes, address, symbols)
+}
+
+// Same as decode() using the instruction set of the given CPU variant
+pub fn decode_variant(
+    variant: CpuVariant,
+    bytes: &[u8],
+    address: u16,
+    symbols: Option<&SymbolTable>,
+) -> Instruction {
+    let code = bytes[0];
+
+    match opcodes::lookup(variant, code) {
+        Some(opcode) if bytes.len() >= opcode.len as usize => {
+            let len = opcode.len as usize;
+            Instruction {
+                address,
+                bytes: bytes[..len].to_vec(),
+                mnemonic: opcode.mnemonic,
+                operand: format_operand(&opcode.mode, address, &bytes[1..len], symbols),
+            }
+        }
+        _ => Instruction {
+            address,
+            bytes: vec![code],
+            mnemonic: ".byte",
+            operand: format!("${:02X}", code),
+        },
+    }
+}
+
+// Disassemble a byte slice loaded at `origin`
+pub fn disassemble(bytes: &[u8], origin: u16, symbols: Option<&SymbolTable>) -> Vec<Instruction> {
+    disassemble_variant(CpuVariant::Nes2A03, bytes, origin, symbols)
+}
+
+// Same as disassemble() using the instruction set of the given CPU variant
+pub fn disassemble_variant(
+    variant: CpuVariant,
+    bytes: &[u8],
+    origin: u16,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Instruction> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        let instruction = decode_variant(variant, &bytes[offset..], address, symbols);
+        offset += instruction.len();
+        result.push(instruction);
+    }
+
+    result
+}
+
+// Bytes of a `.byte` line in logged listings
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// Disassemble with a code/data log (cdl.rs) entry for each byte: only bytes logged as
+// code are decoded, data and never accessed bytes become `.byte` lines. Lines break at
+// labels so they stay visible
+pub fn disassemble_logged(
+    variant: CpuVariant,
+    bytes: &[u8],
+    origin: u16,
+    symbols: Option<&SymbolTable>,
+    log: &[u8],
+) -> Vec<Instruction> {
+    let is_code = |offset: usize| log.get(offset).is_some_and(|&entry| entry & PrgFlags::CODE.bits() != 0);
+    let has_label = |offset: usize| symbols.is_some_and(|symbols| symbols.label(origin.wrapping_add(offset as u16)).is_some());
+
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = origin.wrapping_add(offset as u16);
+        if is_code(offset) {
+            let instruction = decode_variant(variant, &bytes[offset..], address, symbols);
+            offset += instruction.len();
+            result.push(instruction);
+            continue;
+        }
+
+        let mut end = offset + 1;
+        while end < bytes.len() && end - offset < DATA_BYTES_PER_LINE && !is_code(end) && !has_label(end) {
+            end += 1;
+        }
+        let data = &bytes[offset..end];
+        let operand: Vec<String> = data.iter().map(|b| format!("${:02X}", b)).collect();
+        result.push(Instruction {
+            address,
+            bytes: data.to_vec(),
+            mnemonic: ".byte",
+            operand: operand.join(","),
+        });
+        offset = end;
+    }
+
+    result
+}
+
+// Disassemble the memory range `start..=end`
+pub fn disassemble_mem<M: Mem>(
+    mem: &M,
+    start: u16,
+    end: u16,
+    symbols: Option<&SymbolTable>,
+) -> Vec<Instruction> {
+    let mut result = Vec::new();
+    let mut addr = start as u32;
+
+    while addr <= end as u32 {
+        let bytes: Vec<u8> = (0..3)
+            .map(|i| mem.mem_peek((addr as u16).wrapping_add(i)))
+            .collect();
+        let instruction = decode(&bytes, addr as u16, symbols);
+        addr += instruction.len() as u32;
+        result.push(instruction);
+    }
+
+    result
+}
+
+// Render instructions as a listing: address, raw bytes, assembly and label lines
+pub fn format_listing(instructions: &[Instruction], symbols: Option<&SymbolTable>) -> String {
+    let mut out = String::new();
+
+    for instruction in instructions {
+        if let Some(label) = symbols.and_then(|symbols| symbols.label(instruction.address)) {
+            out.push_str(&format!("{}:\n", label));
+        }
+
+        let raw: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        out.push_str(&format!(
+            "${:04X}  {:<8}  {}\n",
+            instruction.address,
+            raw.join(" "),
+            instruction
+        ));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(bytes: &[u8], address: u16) -> String {
+        decode(bytes, address, None).to_string()
+    }
+
+    #[test]
+    fn test_operand_syntax_per_addressing_mode() {
+        assert_eq!(text(&[0xa9, 0x05], 0), "LDA #$05");
+        assert_eq!(text(&[0xa5, 0x10], 0), "LDA $10");
+        assert_eq!(text(&[0xb6, 0x10], 0), "LDX $10,Y");
+        assert_eq!(text(&[0x9d, 0x00, 0x02], 0), "STA $0200,X");
+        assert_eq!(text(&[0xa1, 0x10], 0), "LDA ($10,X)");
+        assert_eq!(text(&[0xb1, 0x10], 0), "LDA ($10),Y");
+        assert_eq!(text(&[0x6c, 0xfc, 0xff], 0), "JMP ($FFFC)");
+        assert_eq!(text(&[0x0a], 0), "ASL A");
+        assert_eq!(text(&[0xea], 0), "NOP");
+    }
+
+    #[test]
+    fn test_branch_target_is_resolved() {
+        assert_eq!(text(&[0xd0, 0x0e], 0x8000), "BNE $8010");
+        assert_eq!(text(&[0xd0, 0xfb], 0x0620), "BNE $061D");
+    }
+
+    #[test]
+    fn test_labels_replace_addresses() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x8a3c, "UpdatePlayer");
+        symbols.insert(0x10, "ptr");
+
+        assert_eq!(decode(&[0x20, 0x3c, 0x8a], 0x8000, Some(&symbols)).to_string(), "JSR UpdatePlayer");
+        assert_eq!(decode(&[0xb1, 0x10], 0x8000, Some(&symbols)).to_string(), "LDA (ptr),Y");
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let cmos = |bytes: &[u8]| decode_variant(CpuVariant::Cmos65C02, bytes, 0x8000, None).to_string();
+
+        assert_eq!(cmos(&[0xb2, 0x10]), "LDA ($10)");
+        assert_eq!(cmos(&[0x7c, 0x34, 0x12]), "JMP ($1234,X)");
+        assert_eq!(cmos(&[0x0f, 0x10, 0x0f]), "BBR0 $10,$8012");
+        assert_eq!(cmos(&[0x9c, 0x00, 0x02]), "STZ $0200");
+        assert_eq!(text(&[0xb2, 0x10], 0x8000), ".byte $B2");
+    }
+
+    #[test]
+    fn test_unknown_and_truncated_bytes_become_data() {
+        let listing = disassemble(&[0xa9, 0x01, 0xff, 0x05, 0x00, 0x8d], 0x8000, None);
+        let text: Vec<String> = listing.iter().map(|i| i.to_string()).collect();
+
+        assert_eq!(text, vec!["LDA #$01", ".byte $FF", "ORA $00", ".byte $8D"]);
+        assert_eq!(listing[1].address, 0x8002);
+    }
+
+    #[test]
+    fn test_logged_data_is_not_decoded() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x8005, "table");
+        // JMP $8003; two data bytes; NOP; data, labelled partway through
+        let bytes = [0x4c, 0x03, 0x80, 0xea, 0xa9, 0x01, 0x02, 0x03];
+        let log = [1, 1, 1, 1, 2, 2, 0, 0];
+
+        let listing = disassemble_logged(CpuVariant::Nes2A03, &bytes, 0x8000, Some(&symbols), &log);
+        let text: Vec<String> = listing.iter().map(|i| i.to_string()).collect();
+        assert_eq!(text, vec!["JMP $8003", "NOP", ".byte $A9", ".byte $01,$02,$03"]);
+        assert_eq!(listing[3].address, 0x8005);
+    }
+}
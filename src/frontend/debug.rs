@@ -0,0 +1,219 @@
+// Debug windows, opened and closed together with F2: the nametables with the scroll window
+// outlined, the pattern tables, the OAM sprite list, palette RAM and a hex memory editor.
+// They are redrawn after every frame. Closing one only closes that window.
+//
+// Pattern tables: 0-7 pick the palette they are drawn with (4-7 are the sprite palettes),
+// P steps through them.
+// Memory: arrows, Page Up/Down, Home and End move the cursor, two hex digits write a
+// byte, Tab switches between CPU and PPU memory. CPU writes go through the bus like a
+// program's would
+
+use rust_nes_emulator::bus::NesBus;
+use rust_nes_emulator::cpu::{Mem, CPU};
+use rust_nes_emulator::debugger::memview::{AddressSpace, MemoryKey, MemoryView};
+use rust_nes_emulator::render::palette::Palette;
+use rust_nes_emulator::render::viewer::{self, Image};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+// Initial scale of the debug windows
+const SCALE: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViewKind {
+    Nametables,
+    PatternTables,
+    Oam,
+    Palette,
+    Memory,
+}
+
+const VIEWS: [ViewKind; 5] = [
+    ViewKind::Nametables,
+    ViewKind::PatternTables,
+    ViewKind::Oam,
+    ViewKind::Palette,
+    ViewKind::Memory,
+];
+
+impl ViewKind {
+    fn title(self) -> &'static str {
+        match self {
+            ViewKind::Nametables => "Nametables",
+            ViewKind::PatternTables => "Pattern tables",
+            ViewKind::Oam => "OAM",
+            ViewKind::Palette => "Palette",
+            ViewKind::Memory => "Memory",
+        }
+    }
+}
+
+struct View {
+    kind: ViewKind,
+    canvas: Canvas<Window>,
+}
+
+pub struct DebugWindows {
+    video: VideoSubsystem,
+    // empty while hidden
+    views: Vec<View>,
+    // palette the pattern tables are drawn with, 0-7
+    pattern_palette: u8,
+    memory: MemoryView,
+}
+
+impl DebugWindows {
+    pub fn new(video: VideoSubsystem) -> Self {
+        DebugWindows {
+            video,
+            views: Vec::new(),
+            pattern_palette: 0,
+            memory: MemoryView::new(),
+        }
+    }
+
+    // Open all the windows, or close them if any are open
+    pub fn toggle(&mut self, cpu: &CPU<NesBus>, palette: &Palette) -> Result<(), String> {
+        if !self.views.is_empty() {
+            self.views.clear();
+            return Ok(());
+        }
+        for kind in VIEWS {
+            let image = self.image(kind, cpu, palette);
+            let window = self
+                .video
+                .window(kind.title(), image.width as u32 * SCALE, image.height as u32 * SCALE)
+                .resizable()
+                .build()
+                .map_err(|err| err.to_string())?;
+            let canvas = window.into_canvas().build().map_err(|err| err.to_string())?;
+            self.views.push(View { kind, canvas });
+        }
+        self.update_title();
+        Ok(())
+    }
+
+    fn image(&mut self, kind: ViewKind, cpu: &CPU<NesBus>, palette: &Palette) -> Image {
+        let ppu = &cpu.bus.ppu;
+        match kind {
+            ViewKind::Nametables => viewer::nametables(ppu, palette),
+            ViewKind::PatternTables => viewer::pattern_tables(ppu, palette, self.pattern_palette),
+            ViewKind::Oam => viewer::oam(ppu, palette),
+            ViewKind::Palette => viewer::palette_ram(ppu, palette),
+            ViewKind::Memory => {
+                match self.memory.space() {
+                    AddressSpace::Cpu => self.memory.update(|addr| cpu.mem_peek(addr)),
+                    AddressSpace::Ppu => self.memory.update(|addr| ppu.peek(addr)),
+                }
+                self.memory.render()
+            }
+        }
+    }
+
+    // Redraw the open windows from the current machine state
+    pub fn update(&mut self, cpu: &CPU<NesBus>, palette: &Palette) -> Result<(), String> {
+        for i in 0..self.views.len() {
+            let image = self.image(self.views[i].kind, cpu, palette);
+            let canvas = &mut self.views[i].canvas;
+            let creator = canvas.texture_creator();
+            let mut texture = creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, image.width as u32, image.height as u32)
+                .map_err(|err| err.to_string())?;
+            texture.update(None, &image.rgb, image.width * 3).map_err(|err| err.to_string())?;
+            canvas.copy(&texture, None, None)?;
+            canvas.present();
+        }
+        Ok(())
+    }
+
+    fn update_title(&mut self) {
+        let title = format!("Pattern tables - palette {}", self.pattern_palette);
+        for view in &mut self.views {
+            if view.kind == ViewKind::PatternTables {
+                // the title has no NUL bytes
+                let _ = view.canvas.window_mut().set_title(&title);
+            }
+        }
+    }
+
+    // Handle an event aimed at one of the debug windows; returns false for the rest
+    pub fn handle_event(&mut self, event: &Event, cpu: &mut CPU<NesBus>) -> bool {
+        let (window_id, keycode) = match *event {
+            Event::Window { window_id, win_event, .. } => {
+                let index = self.views.iter().position(|view| view.canvas.window().id() == window_id);
+                return match index {
+                    Some(index) => {
+                        if win_event == WindowEvent::Close {
+                            self.views.remove(index);
+                        }
+                        true
+                    }
+                    None => false,
+                };
+            }
+            Event::KeyDown { window_id, keycode, .. } => (window_id, keycode),
+            Event::KeyUp { window_id, .. } => (window_id, None),
+            _ => return false,
+        };
+        let kind = match self.views.iter().find(|view| view.canvas.window().id() == window_id) {
+            Some(view) => view.kind,
+            None => return false,
+        };
+        let keycode = match keycode {
+            Some(keycode) => keycode,
+            None => return true,
+        };
+
+        match kind {
+            ViewKind::PatternTables => {
+                if let Some(digit) = hex_digit(keycode).filter(|&digit| digit < 8) {
+                    self.pattern_palette = digit;
+                } else if keycode == Keycode::P {
+                    self.pattern_palette = (self.pattern_palette + 1) % 8;
+                }
+                self.update_title();
+            }
+            ViewKind::Memory => {
+                if let Some(key) = memory_key(keycode) {
+                    if let Some((addr, value)) = self.memory.key(key) {
+                        match self.memory.space() {
+                            AddressSpace::Cpu => cpu.mem_write(addr, value),
+                            AddressSpace::Ppu => cpu.bus.ppu.poke(addr, value),
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+fn hex_digit(keycode: Keycode) -> Option<u8> {
+    let name = keycode.name();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+fn memory_key(keycode: Keycode) -> Option<MemoryKey> {
+    let key = match keycode {
+        Keycode::Up => MemoryKey::Up,
+        Keycode::Down => MemoryKey::Down,
+        Keycode::Left => MemoryKey::Left,
+        Keycode::Right => MemoryKey::Right,
+        Keycode::PageUp => MemoryKey::PageUp,
+        Keycode::PageDown => MemoryKey::PageDown,
+        Keycode::Home => MemoryKey::Home,
+        Keycode::End => MemoryKey::End,
+        Keycode::Tab => MemoryKey::SwitchSpace,
+        _ => return hex_digit(keycode).map(MemoryKey::Digit),
+    };
+    Some(key)
+}
@@ -0,0 +1,362 @@
+// SDL frontend: plays an NROM cartridge, or the snake demo when no ROM is given.
+//
+// Controls: arrow keys, A = Select, S = Start, Z = B, X = A, Escape quits.
+// F12 saves a screenshot at native resolution, Shift+F12 at the window scale.
+// `--record <file.avi|file.y4m>` dumps every emulated frame and its audio, and
+// `--palette <2c02|2c07|2c03|2c05|file.pal>` selects the colours. `--ntsc <settings>`
+// shows the picture through the NTSC composite filter, settings being "default" or e.g.
+// "hue=-5,saturation=1.2,sharpness=0.3,artifacts=0.5,fringing=0.5,phase_shift=off".
+// Otherwise `--filter <none|scale2x|scale3x|hq2x|hq3x|xbr2x>` picks an upscaler, F9
+// cycles through them. The window is resizable; `--integer` keeps to whole multiples,
+// `--aspect` shows 8:7 pixels, `--overscan [lines]` crops the top and bottom (8 lines by
+// default) and `--fullscreen` or F11 switches to fullscreen. `--region <ntsc|pal|dendy>`
+// overrides the console timing from the NES 2.0 header; the game is paced by the audio
+// queue, so PAL games run at 50 fps whatever the display refresh rate. `--cdl <file>`
+// logs how the session accessed the ROM into an FCEUX .cdl file, adding to it if it exists.
+// F2 opens the PPU and memory viewers, see debug.rs. Cheats are read from `--cheats <file>`
+// or the ROM's .cht file, see cheats.rs; F3 switches them all off and on. `--patch <file>`
+// or a .ips, .bps or .ups file next to the ROM is applied to it before it's loaded, and
+// `--gamedb <file>` adds to the game database that corrects bad headers
+
+mod debug;
+mod snake;
+mod video;
+
+use std::collections::HashMap;
+
+use rust_nes_emulator::apu::DEFAULT_SAMPLE_RATE;
+use rust_nes_emulator::bus::NesBus;
+use rust_nes_emulator::cartridge::Rom;
+use rust_nes_emulator::cdl::CodeDataLog;
+use rust_nes_emulator::cheats::Cheats;
+use rust_nes_emulator::cpu::CPU;
+use rust_nes_emulator::joypad::JoypadButton;
+use rust_nes_emulator::record::Recorder;
+use rust_nes_emulator::region::Region;
+use rust_nes_emulator::render::frame::Frame;
+use rust_nes_emulator::render::palette::Palette;
+use sdl2::audio::AudioSpecDesired;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window};
+
+use debug::DebugWindows;
+use video::{Video, VideoOptions, DEFAULT_OVERSCAN};
+
+// Initial window scale
+const SCALE: u32 = 3;
+
+// Audio kept queued ahead of playback; emulation waits while more than this is queued
+const AUDIO_LATENCY_SECS: f64 = 0.05;
+
+fn key_map() -> HashMap<Keycode, JoypadButton> {
+    let mut key_map = HashMap::new();
+    key_map.insert(Keycode::Down, JoypadButton::DOWN);
+    key_map.insert(Keycode::Up, JoypadButton::UP);
+    key_map.insert(Keycode::Right, JoypadButton::RIGHT);
+    key_map.insert(Keycode::Left, JoypadButton::LEFT);
+    key_map.insert(Keycode::A, JoypadButton::SELECT);
+    key_map.insert(Keycode::S, JoypadButton::START);
+    key_map.insert(Keycode::Z, JoypadButton::BUTTON_B);
+    key_map.insert(Keycode::X, JoypadButton::BUTTON_A);
+    key_map
+}
+
+// First unused "<name>-000.png" style file name in the working directory
+fn screenshot_path(name: &str) -> String {
+    (0..)
+        .map(|n| format!("{}-{:03}.png", name, n))
+        .find(|path| !std::path::Path::new(path).exists())
+        .unwrap()
+}
+
+fn save_screenshot(frame: &Frame, palette: &Palette, name: &str, scale: usize) {
+    let path = screenshot_path(name);
+    match frame.save_png(&path, palette, scale) {
+        Ok(()) => println!("saved {}", path),
+        Err(err) => eprintln!("screenshot failed: {}", err),
+    }
+}
+
+// Options of the player beyond the ROM
+struct Options {
+    palette: Palette,
+    video: VideoOptions,
+    recorder: Option<Recorder>,
+    // file the code/data log is loaded from and saved to
+    cdl: Option<String>,
+    cheats: Cheats,
+}
+
+fn play(rom: Rom, name: &str, options: Options) -> Result<(), String> {
+    let Options { palette, video, mut recorder, cdl, cheats } = options;
+    let mut video = Video::new(video);
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let (width, height) = video.window_size(SCALE);
+    let mut window = video_subsystem.window("Rust NES Emulator", width, height);
+    window.position_centered().resizable();
+    if video.options.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(|err| err.to_string())?;
+
+    let mut canvas = window
+        .into_canvas()
+        .present_vsync()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut debug = DebugWindows::new(video_subsystem.clone());
+
+    // recreated whenever the filter changes the picture size
+    let creator = canvas.texture_creator();
+    let mut texture = None;
+    let mut texture_size = (0, 0);
+
+    let audio_subsystem = sdl_context.audio()?;
+    let audio = audio_subsystem.open_queue::<f32, _>(
+        None,
+        &AudioSpecDesired {
+            freq: Some(DEFAULT_SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: None,
+        },
+    )?;
+    audio.resume();
+
+    let key_map = key_map();
+    let log = match &cdl {
+        Some(file) => Some(CodeDataLog::load_or_new(file, rom.prg_rom.len(), rom.chr_rom.len())?),
+        None => None,
+    };
+    let mut cpu = CPU::with_bus(NesBus::new(rom)?);
+    if let Some(log) = log {
+        cpu.bus.start_code_data_log(log)?;
+    }
+    cpu.cheats = cheats;
+    cpu.reset();
+
+    let result = loop {
+        if let Some(halt) = cpu.run_frame().halt {
+            break Err(format!("CPU halted: {}", halt));
+        }
+
+        let rgb = cpu.bus.ppu.frame.to_rgb_with(&palette);
+        let samples = cpu.bus.apu.take_samples();
+        if let Some(recorder) = &mut recorder {
+            if let Err(err) = recorder.record_frame(&rgb, &samples) {
+                break Err(err);
+            }
+        }
+
+        let window_size = canvas.output_size()?;
+        let dest = video.dest_rect(window_size);
+        let (picture, width, height) = video.picture(&cpu.bus.ppu.frame, &rgb);
+        if texture.is_none() || texture_size != (width, height) {
+            texture = Some(
+                creator
+                    .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+                    .map_err(|err| err.to_string())?,
+            );
+            texture_size = (width, height);
+        }
+        let texture = texture.as_mut().unwrap();
+        if let Err(err) = texture.update(None, &picture, width as usize * 3) {
+            break Err(err.to_string());
+        }
+
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+        if let Err(err) = canvas.copy(texture, video.source_rect(width, height), dest) {
+            break Err(err);
+        }
+        canvas.present();
+        if let Err(err) = debug.update(&cpu, &palette) {
+            break Err(err);
+        }
+
+        audio.queue(&samples);
+        let latency = (AUDIO_LATENCY_SECS * DEFAULT_SAMPLE_RATE as f64) as u32 * 4;
+        while audio.size() > latency {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        let scale = (dest.height() / video.window_size(1).1).max(1) as usize;
+        let mut screen = Screen {
+            canvas: &mut canvas,
+            video: &mut video,
+            palette: &palette,
+            name,
+            scale,
+            debug: &mut debug,
+        };
+        if handle_events(&mut event_pump, &mut cpu, &key_map, &mut screen) {
+            break Ok(());
+        }
+    };
+
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+    if let (Some(file), Some(log)) = (&cdl, cpu.bus.code_data_log()) {
+        log.save(file)?;
+        println!("{}", log.coverage());
+    }
+    result
+}
+
+// Display state the hotkeys act on
+struct Screen<'a> {
+    canvas: &'a mut Canvas<Window>,
+    video: &'a mut Video,
+    palette: &'a Palette,
+    name: &'a str,
+    // current window scale, for screenshots
+    scale: usize,
+    debug: &'a mut DebugWindows,
+}
+
+// Apply keyboard input, returns true when the player quits
+fn handle_events(
+    event_pump: &mut sdl2::EventPump,
+    cpu: &mut CPU<NesBus>,
+    key_map: &HashMap<Keycode, JoypadButton>,
+    screen: &mut Screen,
+) -> bool {
+    for event in event_pump.poll_iter() {
+        if let Event::KeyDown { keycode: Some(Keycode::F2), .. } = event {
+            if let Err(err) = screen.debug.toggle(cpu, screen.palette) {
+                eprintln!("debug windows failed: {}", err);
+            }
+            continue;
+        }
+        if screen.debug.handle_event(&event, cpu) {
+            continue;
+        }
+        match event {
+            // with the debug windows open, closing the main window doesn't send Quit
+            Event::Quit { .. }
+            | Event::Window { win_event: WindowEvent::Close, .. }
+            | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return true,
+            Event::KeyDown { keycode: Some(Keycode::F12), keymod, .. } => {
+                let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                let scale = if shift { screen.scale } else { 1 };
+                save_screenshot(&cpu.bus.ppu.frame, screen.palette, screen.name, scale);
+            }
+            Event::KeyDown { keycode: Some(Keycode::F3), .. } => {
+                let enabled = !cpu.cheats.is_enabled();
+                cpu.cheats.set_all_enabled(enabled);
+                println!("cheats {}", if enabled { "on" } else { "off" });
+            }
+            Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                println!("filter: {}", screen.video.cycle_filter());
+            }
+            Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                let window = screen.canvas.window_mut();
+                let fullscreen = match window.fullscreen_state() {
+                    FullscreenType::Off => FullscreenType::Desktop,
+                    _ => FullscreenType::Off,
+                };
+                if let Err(err) = window.set_fullscreen(fullscreen) {
+                    eprintln!("fullscreen failed: {}", err);
+                }
+            }
+            Event::KeyDown { keycode: Some(keycode), .. } => {
+                if let Some(button) = key_map.get(&keycode) {
+                    cpu.bus.joypad1.set_button_pressed_status(*button, true);
+                }
+            }
+            Event::KeyUp { keycode: Some(keycode), .. } => {
+                if let Some(button) = key_map.get(&keycode) {
+                    cpu.bus.joypad1.set_button_pressed_status(*button, false);
+                }
+            }
+            _ => { /* do nothing */ }
+        }
+    }
+    false
+}
+
+// Entry point for `rust-nes-emulator [rom.nes] [--record <file>] [--palette <name|file>]
+// [--ntsc <settings>] [--filter <name>] [--integer] [--aspect] [--overscan [lines]]
+// [--fullscreen] [--region <region>] [--cdl <file>] [--cheats <file>] [--patch <file>]
+// [--gamedb <file>]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut record = None;
+    let mut palette = Palette::default();
+    let mut video = VideoOptions::default();
+    let mut region = None;
+    let mut cdl = None;
+    let mut cheat_file = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--record" => record = Some(iter.next().ok_or("--record needs a file name")?),
+            "--palette" => {
+                let value = iter.next().ok_or("--palette needs a palette name or file")?;
+                palette = Palette::load(value)?;
+            }
+            "--ntsc" => {
+                let value = iter.next().ok_or("--ntsc needs settings, e.g. \"default\"")?;
+                video.ntsc = Some(value.parse()?);
+            }
+            "--filter" => {
+                let value = iter.next().ok_or("--filter needs a filter name")?;
+                video.filter = value.parse()?;
+            }
+            "--integer" => video.integer = true,
+            "--aspect" => video.aspect = true,
+            "--overscan" => {
+                // the line count is optional
+                video.overscan = match iter.clone().next().map(|value| value.parse()) {
+                    Some(Ok(lines)) => {
+                        iter.next();
+                        lines
+                    }
+                    _ => DEFAULT_OVERSCAN,
+                };
+                if video.overscan >= Frame::HEIGHT as u32 / 2 {
+                    return Err(format!("overscan of {} lines crops the whole picture", video.overscan));
+                }
+            }
+            "--fullscreen" => video.fullscreen = true,
+            "--region" => {
+                let value = iter.next().ok_or("--region needs ntsc, pal or dendy")?;
+                region = Some(value.parse::<Region>()?);
+            }
+            "--cdl" => cdl = Some(iter.next().ok_or("--cdl needs a file name")?.clone()),
+            "--cheats" => cheat_file = Some(iter.next().ok_or("--cheats needs a file name")?.clone()),
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            _ => crate::positional(&mut path, arg)?,
+        }
+    }
+
+    match path {
+        Some(path) => {
+            let mut rom = crate::load_rom(path, patch_file, gamedb)?;
+            let name = std::path::Path::new(path)
+                .file_stem()
+                .map_or("screenshot".into(), |stem| stem.to_string_lossy());
+            if region.is_some() {
+                rom.region = region;
+            }
+            let frame_rate = rom.region.unwrap_or_default().frame_rate();
+            let recorder = match record {
+                Some(file) => Some(Recorder::create(file, frame_rate, DEFAULT_SAMPLE_RATE)?),
+                None => None,
+            };
+            let cheats = Cheats::load_or_new(&cheat_file.unwrap_or_else(|| Cheats::file_for(path)))?;
+            play(rom, &name, Options { palette, video, recorder, cdl, cheats })
+        }
+        None => snake::run(),
+    }
+}
@@ -0,0 +1,103 @@
+// Picture pipeline of the SDL frontend: the frame goes through the NTSC filter or one
+// of the pixel art upscalers, then is cropped and fitted into the window
+
+use std::borrow::Cow;
+
+use rust_nes_emulator::render::frame::Frame;
+use rust_nes_emulator::render::ntsc::{NtscFilter, NtscSettings};
+use rust_nes_emulator::render::scale::{self, ScaleFilter};
+use sdl2::rect::Rect;
+
+// NES pixels are slightly wider than tall on a TV
+const PIXEL_ASPECT: f32 = 8.0 / 7.0;
+// Lines most TVs hid at the top and bottom of the picture
+pub const DEFAULT_OVERSCAN: u32 = 8;
+
+pub struct VideoOptions {
+    pub filter: ScaleFilter,
+    pub ntsc: Option<NtscSettings>,
+    // only scale by whole multiples
+    pub integer: bool,
+    // show pixels at the 8:7 aspect ratio of a TV
+    pub aspect: bool,
+    // lines cropped from the top and bottom
+    pub overscan: u32,
+    pub fullscreen: bool,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            filter: ScaleFilter::None,
+            ntsc: None,
+            integer: false,
+            aspect: false,
+            overscan: 0,
+            fullscreen: false,
+        }
+    }
+}
+
+pub struct Video {
+    pub options: VideoOptions,
+    ntsc: Option<NtscFilter>,
+}
+
+impl Video {
+    pub fn new(options: VideoOptions) -> Self {
+        let ntsc = options.ntsc.map(NtscFilter::new);
+        Video { options, ntsc }
+    }
+
+    // Size of the picture as shown at 1x, after cropping
+    fn visible_size(&self) -> (u32, u32) {
+        (Frame::WIDTH as u32, Frame::HEIGHT as u32 - self.options.overscan * 2)
+    }
+
+    fn pixel_aspect(&self) -> f32 {
+        if self.options.aspect {
+            PIXEL_ASPECT
+        } else {
+            1.0
+        }
+    }
+
+    // Window size for the picture at `scale`
+    pub fn window_size(&self, scale: u32) -> (u32, u32) {
+        let (width, height) = self.visible_size();
+        ((width as f32 * self.pixel_aspect() * scale as f32) as u32, height * scale)
+    }
+
+    pub fn cycle_filter(&mut self) -> ScaleFilter {
+        self.options.filter = self.options.filter.next();
+        self.options.filter
+    }
+
+    // Filtered picture of the frame as RGB24 with its width and height. `rgb` is the
+    // frame through the active palette
+    pub fn picture<'a>(&'a mut self, frame: &Frame, rgb: &'a [u8]) -> (Cow<'a, [u8]>, u32, u32) {
+        if let Some(ntsc) = &mut self.ntsc {
+            return (Cow::Borrowed(ntsc.apply(frame)), NtscFilter::WIDTH as u32, Frame::HEIGHT as u32);
+        }
+        let filter = self.options.filter;
+        let factor = filter.factor() as u32;
+        let (width, height) = (Frame::WIDTH as u32 * factor, Frame::HEIGHT as u32 * factor);
+        match filter {
+            ScaleFilter::None => (Cow::Borrowed(rgb), width, height),
+            _ => (Cow::Owned(filter.apply(rgb, Frame::WIDTH, Frame::HEIGHT)), width, height),
+        }
+    }
+
+    // Part of a picture of `height` lines left after cropping the overscan
+    pub fn source_rect(&self, width: u32, height: u32) -> Rect {
+        let crop = self.options.overscan * height / Frame::HEIGHT as u32;
+        Rect::new(0, crop as i32, width, height - crop * 2)
+    }
+
+    // Where the picture goes in a window of `window` size
+    pub fn dest_rect(&self, window: (u32, u32)) -> Rect {
+        let (width, height) = self.visible_size();
+        let (x, y, width, height) = scale::fit(width, height, window, self.pixel_aspect(), self.options.integer);
+        Rect::new(x, y, width.max(1), height.max(1))
+    }
+}
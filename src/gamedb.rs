@@ -0,0 +1,481 @@
+// Game database in the style of the NES 2.0 XML database (nes20db.xml), for fixing dumps
+// with wrong or legacy iNES headers. Games are keyed by the CRC-32 and/or SHA-1 of the
+// PRG ROM followed by the CHR ROM, so a header with the wrong sizes still matches:
+//
+// <game>
+//   <!-- Some Game (USA) -->
+//   <prgrom size="131072" crc32="..." sha1="..."/>
+//   <chrrom size="131072" crc32="..." sha1="..."/>
+//   <rom size="262144" crc32="1A2B3C4D" sha1="0123456789ABCDEF0123456789ABCDEF01234567"/>
+//   <prgram size="8192"/>
+//   <prgnvram size="8192"/>
+//   <chrram size="8192"/>
+//   <pcb mapper="4" submapper="0" mirroring="V" battery="1"/>
+//   <console type="0" region="0"/>
+//   <expansion type="1"/>
+// </game>
+//
+// Elements and attributes left out keep what the header says, other elements (trainer,
+// miscrom, vs, ...) are ignored. Mirroring is H, V or 4, anything else is left to the
+// mapper. Region is 0 NTSC, 1 PAL, 2 multi-region, 3 Dendy. The built-in games are in
+// gamedb.xml; games from a local file in the same format take precedence over them
+
+use std::str::FromStr;
+
+use crate::cartridge::{Mirroring, Rom};
+use crate::hash;
+use crate::region::Region;
+
+const EMBEDDED: &str = include_str!("gamedb.xml");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Game {
+    // checksums of the PRG ROM followed by the CHR ROM, at least one is set
+    pub crc32: Option<u32>,
+    pub sha1: Option<[u8; 20]>,
+    pub prg_rom_size: Option<usize>,
+    pub chr_rom_size: Option<usize>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    // Some(None) for multi-region games
+    pub region: Option<Option<Region>>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub input_device: Option<u8>,
+}
+
+impl Game {
+    fn matches(&self, crc32: u32, sha1: &[u8; 20]) -> bool {
+        self.crc32.is_none_or(|crc| crc == crc32) && self.sha1.as_ref().is_none_or(|hash| hash == sha1)
+    }
+
+    // Fill in the game from one element inside <game>
+    fn set(&mut self, element: &Element) -> Result<(), String> {
+        match element.name {
+            "rom" => {
+                self.crc32 = element
+                    .attribute("crc32")
+                    .map(|value| u32::from_str_radix(value, 16).map_err(|_| format!("invalid crc32 \"{}\"", value)))
+                    .transpose()?;
+                self.sha1 = element.attribute("sha1").map(parse_sha1).transpose()?;
+            }
+            "prgrom" => self.prg_rom_size = element.number("size")?,
+            "chrrom" => self.chr_rom_size = element.number("size")?,
+            "prgram" => self.prg_ram_size = element.number("size")?,
+            "prgnvram" => self.prg_nvram_size = element.number("size")?,
+            "chrram" => self.chr_ram_size = element.number("size")?,
+            "pcb" => {
+                self.mapper = element.number("mapper")?;
+                self.submapper = element.number("submapper")?;
+                self.mirroring = match element.attribute("mirroring") {
+                    Some("H") => Some(Mirroring::Horizontal),
+                    Some("V") => Some(Mirroring::Vertical),
+                    Some("4") => Some(Mirroring::FourScreen),
+                    _ => None,
+                };
+            }
+            "console" => {
+                self.region = match element.number::<u8>("region")? {
+                    None => None,
+                    Some(0) => Some(Some(Region::Ntsc)),
+                    Some(1) => Some(Some(Region::Pal)),
+                    Some(2) => Some(None),
+                    Some(3) => Some(Some(Region::Dendy)),
+                    Some(region) => return Err(format!("unknown region {}", region)),
+                };
+            }
+            "expansion" => self.input_device = element.number("type")?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Override the cartridge settings with the database's, returning a warning for each
+    // one the header got wrong. Fields an iNES 1.0 header doesn't have are fixed quietly
+    pub fn apply(&self, rom: &mut Rom) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let (Some(prg), Some(chr)) = (self.prg_rom_size, self.chr_rom_size) {
+            if prg != rom.prg_rom.len() && prg + chr == rom.prg_rom.len() + rom.chr_rom.len() {
+                warnings.push(format!(
+                    "ROM sizes: header says {} bytes of PRG and {} of CHR, game database says {} and {}",
+                    rom.prg_rom.len(),
+                    rom.chr_rom.len(),
+                    prg,
+                    chr
+                ));
+                let mut data = std::mem::take(&mut rom.prg_rom);
+                data.append(&mut rom.chr_rom);
+                rom.chr_rom = data.split_off(prg);
+                rom.prg_rom = data;
+            }
+        }
+
+        let nes2 = rom.nes2;
+        fix(&mut warnings, "mapper", &mut rom.mapper, self.mapper, true, |mapper| mapper.to_string());
+        fix(&mut warnings, "submapper", &mut rom.submapper, self.submapper, nes2, |submapper| submapper.to_string());
+        fix(&mut warnings, "mirroring", &mut rom.screen_mirroring, self.mirroring, true, |mirroring| format!("{:?}", mirroring));
+        fix(&mut warnings, "region", &mut rom.region, self.region, nes2, |region| {
+            region.map_or("multi-region".to_string(), |region| region.to_string())
+        });
+        fix(&mut warnings, "PRG RAM", &mut rom.prg_ram_size, self.prg_ram_size, nes2, bytes);
+        fix(&mut warnings, "PRG NVRAM", &mut rom.prg_nvram_size, self.prg_nvram_size, nes2, bytes);
+        fix(&mut warnings, "CHR RAM", &mut rom.chr_ram_size, self.chr_ram_size, nes2, bytes);
+        fix(&mut warnings, "input device", &mut rom.input_device, self.input_device, nes2, |device| device.to_string());
+        warnings
+    }
+}
+
+fn bytes(size: usize) -> String {
+    format!("{} bytes", size)
+}
+
+fn fix<T: PartialEq + Copy>(
+    warnings: &mut Vec<String>,
+    what: &str,
+    header: &mut T,
+    database: Option<T>,
+    warn: bool,
+    show: impl Fn(T) -> String,
+) {
+    if let Some(value) = database.filter(|value| value != header) {
+        if warn {
+            warnings.push(format!("{}: header says {}, game database says {}", what, show(*header), show(value)));
+        }
+        *header = value;
+    }
+}
+
+fn parse_sha1(value: &str) -> Result<[u8; 20], String> {
+    let error = || format!("invalid sha1 \"{}\"", value);
+    if value.len() != 40 || !value.is_ascii() {
+        return Err(error());
+    }
+    let mut sha1 = [0u8; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).map_err(|_| error())?;
+    }
+    Ok(sha1)
+}
+
+pub struct GameDb {
+    games: Vec<Game>,
+}
+
+impl GameDb {
+    // The games compiled into the emulator
+    pub fn embedded() -> GameDb {
+        GameDb::parse(EMBEDDED).expect("built-in game database is valid")
+    }
+
+    pub fn load(path: &str) -> Result<GameDb, String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        GameDb::parse(&text).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn parse(text: &str) -> Result<GameDb, String> {
+        let mut games = Vec::new();
+        // game being read and the line it started on
+        let mut current: Option<(Game, usize)> = None;
+        for element in elements(text)? {
+            let line = element.line;
+            if element.name == "game" {
+                if element.closing {
+                    let (game, start) = current.take().ok_or_else(|| format!("line {}: </game> without <game>", line))?;
+                    if game.crc32.is_none() && game.sha1.is_none() {
+                        return Err(format!("line {}: game has no <rom> CRC-32 or SHA-1", start));
+                    }
+                    games.push(game);
+                } else if !element.empty {
+                    if current.is_some() {
+                        return Err(format!("line {}: <game> inside another game", line));
+                    }
+                    current = Some((Game::default(), line));
+                }
+                continue;
+            }
+            if let Some((game, _)) = current.as_mut() {
+                if !element.closing {
+                    game.set(&element).map_err(|err| format!("line {}: {}", line, err))?;
+                }
+            }
+        }
+        match current {
+            Some((_, start)) => Err(format!("line {}: <game> is never closed", start)),
+            None => Ok(GameDb { games }),
+        }
+    }
+
+    // Add games that take precedence over these ones
+    pub fn add_overrides(&mut self, overrides: GameDb) {
+        let mut games = overrides.games;
+        games.append(&mut self.games);
+        self.games = games;
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    pub fn lookup(&self, rom: &Rom) -> Option<&Game> {
+        let mut data = rom.prg_rom.clone();
+        data.extend_from_slice(&rom.chr_rom);
+        self.find(hash::crc32(&data), &hash::sha1(&data))
+    }
+
+    // The game with these checksums of its PRG ROM followed by its CHR ROM
+    pub fn find(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&Game> {
+        self.games.iter().find(|game| game.matches(crc32, sha1))
+    }
+}
+
+// Just enough XML for the database: tags with quoted attributes. Text, comments,
+// declarations and processing instructions are skipped
+struct Element<'a> {
+    name: &'a str,
+    attributes: Vec<(&'a str, String)>,
+    // </name>
+    closing: bool,
+    // <name/>
+    empty: bool,
+    line: usize,
+}
+
+impl<'a> Element<'a> {
+    fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.iter().find(|(name, _)| *name == key).map(|(_, value)| value.as_str())
+    }
+
+    fn number<T: FromStr>(&self, key: &str) -> Result<Option<T>, String> {
+        self.attribute(key)
+            .map(|value| value.parse().map_err(|_| format!("invalid {} \"{}\"", key, value)))
+            .transpose()
+    }
+}
+
+fn elements(text: &str) -> Result<Vec<Element<'_>>, String> {
+    let mut elements = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = text[pos..].find('<') {
+        pos += offset;
+        let line = text[..pos].matches('\n').count() + 1;
+        let skipped = [("<!--", "-->"), ("<?", "?>"), ("<!", ">")]
+            .iter()
+            .find(|(open, _)| text[pos..].starts_with(open));
+        let close = skipped.map_or(">", |(_, close)| close);
+        let end = pos + text[pos..].find(close).ok_or_else(|| format!("line {}: unterminated tag", line))?;
+        if skipped.is_none() {
+            elements.push(parse_tag(&text[pos + 1..end], line)?);
+        }
+        pos = end + close.len();
+    }
+    Ok(elements)
+}
+
+fn parse_tag(tag: &str, line: usize) -> Result<Element<'_>, String> {
+    let error = || format!("line {}: malformed tag <{}>", line, tag);
+    let (closing, inner) = match tag.strip_prefix('/') {
+        Some(inner) => (true, inner),
+        None => (false, tag),
+    };
+    let (empty, inner) = match inner.strip_suffix('/') {
+        Some(inner) => (true, inner),
+        None => (false, inner),
+    };
+    let inner = inner.trim();
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = &inner[..name_end];
+    if name.is_empty() {
+        return Err(error());
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = inner[name_end..].trim_start();
+    while !rest.is_empty() {
+        let equals = rest.find('=').ok_or_else(error)?;
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'').ok_or_else(error)?;
+        let end = 1 + value[1..].find(quote).ok_or_else(error)?;
+        attributes.push((key, unescape(&value[1..end])));
+        rest = value[end + 1..].trim_start();
+    }
+    Ok(Element {
+        name,
+        attributes,
+        closing,
+        empty,
+        line,
+    })
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_ines;
+    use crate::cartridge::PRG_ROM_PAGE_SIZE;
+
+    // Database entry for `rom` as NROM-256 with vertical mirroring and 8KB PRG RAM
+    fn entry(rom: &Rom, checksums: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?>
+<nes20db>
+  <game>
+    <!-- Test Game (World) -->
+    <prgrom size="32768"/>
+    <chrrom size="8192"/>
+    <rom size="40960" {}/>
+    <pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+    <prgram size="8192"/>
+    <console type="0" region="1"/>
+    <expansion type='1'/>
+  </game>
+</nes20db>
+"#,
+            checksums.replace("{crc32}", &format!("{:08X}", crc32_of(rom)))
+        )
+    }
+
+    fn crc32_of(rom: &Rom) -> u32 {
+        hash::crc32_update(hash::crc32(&rom.prg_rom), &rom.chr_rom)
+    }
+
+    // 32KB PRG and 8KB CHR, with a header claiming 16KB of PRG, 24KB of CHR, mapper 64
+    // and horizontal mirroring
+    fn bad_header() -> Vec<u8> {
+        let mut raw = test_ines(&[0xea]);
+        raw.extend(vec![0x55; 0x8000]);
+        raw[5] = 3;
+        raw[6] = 0x00;
+        raw[7] = 0x40;
+        raw
+    }
+
+    #[test]
+    fn test_fix_header() {
+        let mut rom = Rom::new(&bad_header()).unwrap();
+        let db = GameDb::parse(&entry(&rom, r#"crc32="{crc32}""#)).unwrap();
+        assert_eq!(db.len(), 1);
+        let game = db.lookup(&rom).unwrap();
+        assert_eq!(game.region, Some(Some(Region::Pal)));
+
+        let warnings = game.apply(&mut rom);
+        assert_eq!(
+            warnings,
+            vec![
+                "ROM sizes: header says 16384 bytes of PRG and 24576 of CHR, game database says 32768 and 8192",
+                "mapper: header says 64, game database says 0",
+                "mirroring: header says Horizontal, game database says Vertical",
+            ]
+        );
+        assert_eq!((rom.prg_rom.len(), rom.chr_rom.len()), (0x8000, 0x2000));
+        assert_eq!((rom.prg_rom[0], rom.chr_rom[0]), (0xea, 0x55));
+        assert_eq!((rom.mapper, rom.screen_mirroring), (0, Mirroring::Vertical));
+        // not in an iNES 1.0 header, so fixed without a warning
+        assert_eq!((rom.region, rom.input_device), (Some(Region::Pal), 1));
+
+        // a correct header gets no warnings
+        assert!(game.apply(&mut rom).is_empty());
+    }
+
+    #[test]
+    fn test_fix_diskdude_header() {
+        // mapper 66 with "DiskDude!" over bytes 7-15, so only the low nybble survives
+        let mut raw = test_ines(&[0xea]);
+        raw[6] = 0x20;
+        raw[7..16].copy_from_slice(b"DiskDude!");
+        let mut rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 2);
+
+        let db = GameDb::parse(&format!(
+            r#"<game><rom crc32="{:08X}"/><pcb mapper="66" mirroring="V"/></game>"#,
+            crc32_of(&rom)
+        ))
+        .unwrap();
+        let warnings = db.lookup(&rom).unwrap().apply(&mut rom);
+        assert_eq!(
+            warnings,
+            vec![
+                "mapper: header says 2, game database says 66",
+                "mirroring: header says Horizontal, game database says Vertical",
+            ]
+        );
+        assert_eq!(rom.mapper, 66);
+    }
+
+    #[test]
+    fn test_lookup_by_sha1() {
+        let rom = Rom::new(&bad_header()).unwrap();
+        let mut data = rom.prg_rom.clone();
+        data.extend_from_slice(&rom.chr_rom);
+        let sha1: String = hash::sha1(&data).iter().map(|byte| format!("{:02X}", byte)).collect();
+
+        let db = GameDb::parse(&entry(&rom, &format!(r#"sha1="{}""#, sha1))).unwrap();
+        assert!(db.lookup(&rom).is_some());
+        // both checksums have to match
+        let db = GameDb::parse(&entry(&rom, &format!(r#"crc32="00000000" sha1="{}""#, sha1))).unwrap();
+        assert!(db.lookup(&rom).is_none());
+    }
+
+    #[test]
+    fn test_overrides_win() {
+        let mut rom = Rom::new(&bad_header()).unwrap();
+        let mut db = GameDb::parse(&entry(&rom, r#"crc32="{crc32}""#)).unwrap();
+        let local = entry(&rom, r#"crc32="{crc32}""#).replace(r#"mapper="0""#, r#"mapper="2""#);
+        db.add_overrides(GameDb::parse(&local).unwrap());
+        assert_eq!(db.len(), 2);
+        db.lookup(&rom).unwrap().apply(&mut rom);
+        assert_eq!(rom.mapper, 2);
+
+        assert!(GameDb::embedded().lookup(&rom).is_none());
+    }
+
+    #[test]
+    fn test_embedded() {
+        let db = GameDb::embedded();
+        assert!(!db.is_empty());
+
+        // Super Mario Bros. with a header claiming mapper 64 and horizontal mirroring
+        let mut raw = test_ines(&[]);
+        raw[4] = 2;
+        raw.splice(16..16, vec![0; PRG_ROM_PAGE_SIZE]);
+        raw[6] = 0x00;
+        raw[7] = 0x40;
+        let mut rom = Rom::new(&raw).unwrap();
+        let game = db.find(0x3337_EC46, &[0; 20]).unwrap();
+        assert_eq!(
+            game.apply(&mut rom),
+            vec![
+                "mapper: header says 64, game database says 0",
+                "mirroring: header says Horizontal, game database says Vertical",
+            ]
+        );
+        assert_eq!(rom.region, Some(Region::Ntsc));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |text: &str| GameDb::parse(text).err().unwrap();
+        assert_eq!(err("<game>\n<rom crc32=\"12\"/>"), "line 1: <game> is never closed");
+        assert_eq!(err("<game>\n<pcb mapper=\"x\"/>\n</game>"), "line 2: invalid mapper \"x\"");
+        assert_eq!(err("<game>\n</game>"), "line 1: game has no <rom> CRC-32 or SHA-1");
+        assert_eq!(err("<game>\n<rom sha1=\"12\"/></game>"), "line 2: invalid sha1 \"12\"");
+        assert_eq!(err("<a b=c/>"), "line 1: malformed tag <a b=c/>");
+        assert_eq!(err("\n<!-- open"), "line 2: unterminated tag");
+    }
+}
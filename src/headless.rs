@@ -0,0 +1,237 @@
+// Headless runner: plays a cartridge without a window, feeding controller input from
+// a movie and collecting audio, so CI can compare the final frame, audio and RAM
+
+use std::fmt::Write;
+
+use crate::bus::NesBus;
+use crate::cartridge::Rom;
+use crate::cpu::{Mem, CPU};
+use crate::movie::Movie;
+use crate::profiler::Profiler;
+use crate::record::Recorder;
+use crate::render::palette::Palette;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A byte of CPU memory compared with a value, e.g. "6000<80" for blargg's "test done"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemCondition {
+    pub addr: u16,
+    pub compare: Compare,
+    pub value: u8,
+}
+
+fn parse_hex_u16(value: &str) -> Result<u16, String> {
+    let digits = value.trim().trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex number: {}", value))
+}
+
+impl std::str::FromStr for MemCondition {
+    type Err = String;
+
+    // <addr><op><value> with hex numbers and op one of == != < <= > >=
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // two character operators first so "<=" isn't read as "<"
+        const OPERATORS: [(&str, Compare); 7] = [
+            ("==", Compare::Eq),
+            ("!=", Compare::Ne),
+            ("<=", Compare::Le),
+            (">=", Compare::Ge),
+            ("<", Compare::Lt),
+            (">", Compare::Gt),
+            ("=", Compare::Eq),
+        ];
+
+        let (pos, op, compare) = OPERATORS
+            .iter()
+            .find_map(|&(op, compare)| s.find(op).map(|pos| (pos, op, compare)))
+            .ok_or_else(|| format!("invalid condition: {} (expected e.g. 6000!=80)", s))?;
+
+        let value = parse_hex_u16(&s[pos + op.len()..])?;
+        if value > 0xff {
+            return Err(format!("condition value {:X} doesn't fit in a byte", value));
+        }
+
+        Ok(MemCondition {
+            addr: parse_hex_u16(&s[..pos])?,
+            compare,
+            value: value as u8,
+        })
+    }
+}
+
+impl std::fmt::Display for MemCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = match self.compare {
+            Compare::Eq => "==",
+            Compare::Ne => "!=",
+            Compare::Lt => "<",
+            Compare::Le => "<=",
+            Compare::Gt => ">",
+            Compare::Ge => ">=",
+        };
+        write!(f, "${:04X}{}${:02X}", self.addr, op, self.value)
+    }
+}
+
+impl MemCondition {
+    pub fn holds<M: Mem>(&self, mem: &M) -> bool {
+        let data = mem.mem_peek(self.addr);
+        match self.compare {
+            Compare::Eq => data == self.value,
+            Compare::Ne => data != self.value,
+            Compare::Lt => data < self.value,
+            Compare::Le => data <= self.value,
+            Compare::Gt => data > self.value,
+            Compare::Ge => data >= self.value,
+        }
+    }
+}
+
+pub struct Headless {
+    pub cpu: CPU<NesBus>,
+    pub frame: usize,
+    pub audio: Vec<f32>,
+    // colours for recorded frames
+    pub palette: Palette,
+    // receives every emulated frame when set
+    pub recorder: Option<Recorder>,
+    // profiles every instruction when set
+    pub profiler: Option<Profiler>,
+    movie: Option<Movie>,
+}
+
+impl Headless {
+    pub fn new(rom: Rom, movie: Option<Movie>) -> Result<Self, String> {
+        let mut cpu = CPU::with_bus(NesBus::new(rom)?);
+        cpu.reset();
+        Ok(Headless {
+            cpu,
+            frame: 0,
+            audio: Vec::new(),
+            palette: Palette::default(),
+            recorder: None,
+            profiler: None,
+            movie,
+        })
+    }
+
+    // Emulate one frame with the movie's input for it
+    pub fn run_frame(&mut self) -> Result<(), String> {
+        if let Some(movie) = &self.movie {
+            let input = movie.frame(self.frame);
+            if input.reset {
+                self.cpu.reset();
+            }
+            self.cpu.bus.joypad1.button_status = input.pads[0];
+            self.cpu.bus.joypad2.button_status = input.pads[1];
+        }
+
+        let result = match &mut self.profiler {
+            Some(profiler) => profiler.run_frame(&mut self.cpu),
+            None => self.cpu.run_frame(),
+        };
+        self.frame += 1;
+        let samples = self.cpu.bus.apu.take_samples();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_frame(&self.cpu.bus.ppu.frame.to_rgb_with(&self.palette), &samples)?;
+        }
+        self.audio.extend(samples);
+
+        match result.halt {
+            Some(halt) => Err(format!("CPU halted at frame {}: {}", self.frame, halt)),
+            None => Ok(()),
+        }
+    }
+
+    // Run up to `frames` frames, stopping early once `until` holds after a frame.
+    // Returns whether the condition was met
+    pub fn run(&mut self, frames: usize, until: Option<&MemCondition>) -> Result<bool, String> {
+        for _ in 0..frames {
+            self.run_frame()?;
+            if until.is_some_and(|condition| condition.holds(&self.cpu.bus)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+// Classic 16 bytes per line hex dump with an ASCII column, addresses starting at `base`
+pub fn hex_dump(data: &[u8], base: u16) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:04X}:", base as usize + i * 16);
+        for byte in line {
+            let _ = write!(out, " {:02X}", byte);
+        }
+        out.push_str(&"   ".repeat(16 - line.len()));
+        out.push_str("  ");
+        out.extend(line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::joypad::JoypadButton;
+
+    #[test]
+    fn test_parse_condition() {
+        let condition: MemCondition = "$6000!=80".parse().unwrap();
+        assert_eq!(condition, MemCondition { addr: 0x6000, compare: Compare::Ne, value: 0x80 });
+
+        let condition: MemCondition = "f0<=0x10".parse().unwrap();
+        assert_eq!(condition, MemCondition { addr: 0xf0, compare: Compare::Le, value: 0x10 });
+
+        assert_eq!(condition.to_string(), "$00F0<=$10");
+
+        assert!("6000".parse::<MemCondition>().is_err());
+        assert!("6000==100".parse::<MemCondition>().is_err());
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"NES\x1a", 0x10);
+        assert_eq!(dump, format!("0010: 4E 45 53 1A{}  NES.\n", "   ".repeat(12)));
+    }
+
+    #[test]
+    fn test_run_until_condition() {
+        // count frames in $10 by waiting for vblank in $2002, then read controller 1
+        let rom = test_rom(&[
+            0x2c, 0x02, 0x20, // BIT $2002
+            0x10, 0xfb, //       BPL -5
+            0xe6, 0x10, //       INC $10
+            0xa9, 0x01, //       LDA #1
+            0x8d, 0x16, 0x40, // STA $4016
+            0xa9, 0x00, //       LDA #0
+            0x8d, 0x16, 0x40, // STA $4016
+            0xad, 0x16, 0x40, // LDA $4016
+            0x85, 0x11, //       STA $11
+            0x4c, 0x00, 0x80, // JMP $8000
+        ]);
+        let movie = Movie::parse("|0|.......A|||\n|0|........|||\n").unwrap();
+        let mut headless = Headless::new(rom, Some(movie)).unwrap();
+
+        headless.run_frame().unwrap();
+        assert_eq!(headless.cpu.bus.joypad1.button_status, JoypadButton::BUTTON_A);
+
+        let until: MemCondition = "10>=5".parse().unwrap();
+        assert!(headless.run(100, Some(&until)).unwrap());
+        assert_eq!(headless.cpu.mem_peek(0x10), 5);
+        assert!(headless.frame < 10);
+        assert!(!headless.audio.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+// Standard NES controller at $4016/$4017.
+// Writing 1 to the strobe bit continuously reloads the button state; after writing 0,
+// each read returns the next button in the order A, B, Select, Start, Up, Down, Left, Right
+// and 1 once all eight have been read
+
+bitflags! {
+    pub struct JoypadButton: u8 {
+        const RIGHT    = 0b10000000;
+        const LEFT     = 0b01000000;
+        const DOWN     = 0b00100000;
+        const UP       = 0b00010000;
+        const START    = 0b00001000;
+        const SELECT   = 0b00000100;
+        const BUTTON_B = 0b00000010;
+        const BUTTON_A = 0b00000001;
+    }
+}
+
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    pub button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: JoypadButton::from_bits_truncate(0),
+        }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        let response = self.peek();
+        if !self.strobe && self.button_index <= 7 {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    // Value the next read returns, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.button_status.bits & (1 << self.button_index)) >> self.button_index
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Joypad::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_strobe_mode() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        for _ in 0..10 {
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+
+    #[test]
+    fn test_strobe_mode_on_off() {
+        let mut joypad = Joypad::new();
+
+        joypad.write(0);
+        joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+        joypad.set_button_pressed_status(JoypadButton::LEFT, true);
+        joypad.set_button_pressed_status(JoypadButton::SELECT, true);
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_B, true);
+
+        for _ in 0..=1 {
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 0);
+            assert_eq!(joypad.read(), 1);
+            assert_eq!(joypad.read(), 1);
+
+            for _ in 0..10 {
+                assert_eq!(joypad.read(), 1);
+            }
+            joypad.write(1);
+            joypad.write(0);
+        }
+    }
+}
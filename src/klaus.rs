@@ -0,0 +1,123 @@
+// Harness for Klaus Dormann's 6502 test suite (github.com/Klaus2m5/6502_65C02_functional_tests)
+// running on the bare CPU with a flat 64KB memory.
+//
+// Both tests signal their result by trapping: the CPU ends up on an instruction that
+// jumps or branches to itself. The functional test traps at a known success address
+// (look it up in the .lst file of your build); the decimal test ends and leaves its
+// error flag in zero page.
+
+use crate::cpu::{CpuVariant, HaltReason, Mem, CPU};
+
+// Values for the prebuilt bin/6502_functional_test.bin (a 64KB image loaded at $0000)
+pub const FUNCTIONAL_TEST_START: u16 = 0x0400;
+pub const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+
+// 6502_decimal_test.a65 assembled as a plain binary with its default `org $200`
+pub const DECIMAL_TEST_START: u16 = 0x0200;
+pub const DECIMAL_TEST_ERROR: u16 = 0x000b;
+
+// The functional test needs ~30 million instructions with decimal tests enabled
+const MAX_INSTRUCTIONS: usize = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Trapped(u16),
+    Halted(HaltReason),
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub outcome: Outcome,
+    pub instructions: usize,
+    pub cycles: usize,
+}
+
+// Run until the program counter stops moving, the CPU halts or the budget runs out
+pub fn run_until_trap(cpu: &mut CPU, max_instructions: usize) -> Report {
+    let start_cycles = cpu.cycles;
+    let mut instructions = 0;
+
+    let outcome = loop {
+        if instructions == max_instructions {
+            break Outcome::TimedOut;
+        }
+
+        let result = cpu.step();
+        instructions += 1;
+
+        if let Some(halt) = result.halt {
+            break Outcome::Halted(halt);
+        }
+        if cpu.program_counter == result.address {
+            break Outcome::Trapped(result.address);
+        }
+    };
+
+    Report {
+        outcome,
+        instructions,
+        cycles: cpu.cycles - start_cycles,
+    }
+}
+
+// CPU configured as a stock NMOS 6502: BCD arithmetic and BRK through the IRQ vector
+fn nmos_cpu() -> CPU {
+    let mut cpu = CPU::with_variant(CpuVariant::Nmos6502);
+    cpu.halt_on_brk = false;
+    cpu
+}
+
+// Load a 64KB functional test image at $0000, start it at `start` and check it traps at `success`
+pub fn run_functional_test(image: &[u8], start: u16, success: u16) -> Result<Report, String> {
+    let mut cpu = nmos_cpu();
+    cpu.load_at(0x0000, image)?;
+    cpu.reset();
+    cpu.program_counter = start;
+
+    let report = run_until_trap(&mut cpu, MAX_INSTRUCTIONS);
+    match report.outcome {
+        Outcome::Trapped(addr) if addr == success => Ok(report),
+        Outcome::Trapped(addr) => Err(format!(
+            "trapped at ${:04X} after {} instructions, success is at ${:04X}",
+            addr, report.instructions, success
+        )),
+        Outcome::Halted(halt) => Err(format!(
+            "CPU halted after {} instructions: {}",
+            report.instructions, halt
+        )),
+        Outcome::TimedOut => Err(format!(
+            "no trap after {} instructions, PC=${:04X}",
+            report.instructions, cpu.program_counter
+        )),
+    }
+}
+
+// Load the decimal test at `load`, start it at `start` and check its error flag once it ends.
+// Depending on the build it ends in a self loop, a BRK or a 65C02 STP ($DB), which the
+// NMOS core reports as an illegal opcode; all of them count as the end of the test
+pub fn run_decimal_test(image: &[u8], load: u16, start: u16) -> Result<Report, String> {
+    let mut cpu = nmos_cpu();
+    cpu.halt_on_brk = true;
+    cpu.load_at(load, image)?;
+    cpu.reset();
+    cpu.program_counter = start;
+
+    let report = run_until_trap(&mut cpu, MAX_INSTRUCTIONS);
+    if report.outcome == Outcome::TimedOut {
+        return Err(format!(
+            "test did not finish after {} instructions, PC=${:04X}",
+            report.instructions, cpu.program_counter
+        ));
+    }
+
+    match cpu.mem_peek(DECIMAL_TEST_ERROR) {
+        0 => Ok(report),
+        _ => Err(format!(
+            "ERROR flag set ({:?}): N1=${:02X} N2=${:02X}",
+            report.outcome,
+            cpu.mem_peek(0x0000),
+            cpu.mem_peek(0x0001)
+        )),
+    }
+}
@@ -0,0 +1,35 @@
+// NES emulator core: a 6502 family CPU wired through a Bus to the NES picture and audio
+// units, controllers and NROM cartridges, plus tooling (disassembler, tracer and test
+// harnesses). The core has no native dependencies; the SDL frontend lives in the binary
+// behind the `sdl` cargo feature.
+
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate bitflags;
+
+pub mod apu;
+pub mod asm;
+pub mod bus;
+pub mod cartridge;
+pub mod cdl;
+pub mod cheats;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gamedb;
+pub mod hash;
+pub mod headless;
+pub mod joypad;
+pub mod klaus;
+pub mod movie;
+pub mod opcodes;
+pub mod patch;
+pub mod ppu;
+pub mod profiler;
+pub mod record;
+pub mod region;
+pub mod render;
+pub mod symbols;
+pub mod trace;
@@ -1,140 +1,552 @@
-pub mod cpu;
-pub mod opcodes;
-
-use cpu::Mem;
-use cpu::CPU;
-
-use rand::Rng;
-use sdl2::event::Event;
-use sdl2::render::Texture;
-use sdl2::EventPump;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-use std::time::Duration;
-
-#[macro_use]
-extern crate lazy_static;
-
-#[macro_use]
-extern crate bitflags;
-
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
+// Command line entry point: tooling subcommands, and the SDL frontend when built with
+// the `sdl` feature. Commands that load a ROM first apply the IPS, BPS or UPS patch given
+// with --patch, or else one with the ROM's name and a .ips, .bps or .ups extension. Games
+// found in the built-in game database or the --gamedb file get their header corrected,
+// with a warning for each setting the header got wrong
+
+use rust_nes_emulator::asm::Assembler;
+use rust_nes_emulator::bus::{Bus, NesBus};
+use rust_nes_emulator::cartridge::{self, Rom};
+use rust_nes_emulator::cdl::CodeDataLog;
+use rust_nes_emulator::cheats::Cheats;
+use rust_nes_emulator::cpu::{CpuVariant, CPU};
+use rust_nes_emulator::debugger::gdb::GdbStub;
+use rust_nes_emulator::debugger::Debugger;
+use rust_nes_emulator::gamedb::GameDb;
+use rust_nes_emulator::headless::{self, Headless, MemCondition};
+use rust_nes_emulator::movie::Movie;
+use rust_nes_emulator::profiler::Profiler;
+use rust_nes_emulator::record::{wav, Recorder};
+use rust_nes_emulator::region::Region;
+use rust_nes_emulator::render::palette::Palette;
+use rust_nes_emulator::{disasm, hash, patch, symbols, trace};
+
+#[cfg(feature = "sdl")]
+mod frontend;
+
+// Hottest instructions listed by `run --profile`
+const PROFILE_INSTRUCTIONS: usize = 20;
+
+// Parse an address given as "$8000", "0x8000" or "8000"
+fn parse_hex(value: &str) -> Result<u16, String> {
+    let digits = value
+        .trim_start_matches('$')
+        .trim_start_matches("0x")
+        .trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address: {}", value))
+}
+
+// Take a command's file argument, rejecting unknown options and a second file
+fn positional<'a>(path: &mut Option<&'a String>, arg: &'a String) -> Result<(), String> {
+    if arg.starts_with("--") {
+        return Err(format!("unknown option: {}", arg));
+    }
+    if let Some(first) = path {
+        return Err(format!("unexpected argument {} after {}", arg, first));
     }
+    *path = Some(arg);
+    Ok(())
 }
 
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200 .. 0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
-        } 
-        frame_idx += 3;
-    }
-    return update;
+// Read a ROM with the --patch file, or a patch next to it, applied (see patch.rs)
+fn read_rom(path: &str, patch: Option<&str>) -> Result<Vec<u8>, String> {
+    let (raw, applied) = patch::read_rom(path, patch)?;
+    if let Some(file) = applied {
+        eprintln!("applied patch {}", file);
+    }
+    Ok(raw)
+}
+
+// Read and parse a cartridge, fixing its header from the game database
+fn load_rom(path: &str, patch: Option<&str>, gamedb: Option<&str>) -> Result<Rom, String> {
+    parse_rom(&read_rom(path, patch)?, gamedb)
 }
 
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                std::process::exit(0)
-            },
-            Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-                cpu.mem_write(0xff, 0x77);
-            },
-            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                cpu.mem_write(0xff, 0x73);
-            },
-            Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                cpu.mem_write(0xff, 0x61);
-            },
-            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                cpu.mem_write(0xff, 0x64);
+// Parse an already read cartridge, fixing its header from the game database with the
+// --gamedb file's games taking precedence (see gamedb.rs)
+fn parse_rom(raw: &[u8], gamedb: Option<&str>) -> Result<Rom, String> {
+    let mut rom = Rom::new(raw)?;
+    let mut db = GameDb::embedded();
+    if let Some(file) = gamedb {
+        db.add_overrides(GameDb::load(file)?);
+    }
+    if let Some(game) = db.lookup(&rom) {
+        for warning in game.apply(&mut rom) {
+            eprintln!("warning: {}", warning);
+        }
+    }
+    Ok(rom)
+}
+
+// disasm <file> [--org <addr>] [--bank <n>] [--cpu <2a03|6502|65c02>] [--symbols <file>]
+//        [--cdl <file>] [--patch <file>] [--gamedb <file>]
+// Raw binaries are placed at --org (default $0000). For .nes files the selected 16KB
+// PRG bank (default 0) is placed at --org. By default the last bank goes at $C000, where
+// a lone 16KB bank is mirrored and most mappers fix the last one, and the rest at $8000.
+// --symbols loads labels from a ca65 .dbg, NESASM .fns or Mesen .mlb file. --cdl takes
+// a code/data log of a .nes file and only decodes the bytes it has seen executed
+fn disasm_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+    let mut org = None;
+    let mut bank = 0;
+    let mut variant = CpuVariant::Nes2A03;
+    let mut symbol_file = None;
+    let mut cdl = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--org" => {
+                let value = iter.next().ok_or("--org needs an address")?;
+                org = Some(parse_hex(value)?);
+            }
+            "--bank" => {
+                let value = iter.next().ok_or("--bank needs a bank number")?;
+                bank = value.parse().map_err(|_| format!("invalid bank: {}", value))?;
+            }
+            "--cpu" => {
+                let value = iter.next().ok_or("--cpu needs a CPU variant")?;
+                variant = value.parse()?;
             }
-            _ => { /* do nothing */ }
+            "--symbols" => symbol_file = Some(iter.next().ok_or("--symbols needs a file name")?),
+            "--cdl" => cdl = Some(iter.next().ok_or("--cdl needs a file name")?),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            _ => positional(&mut path, arg)?,
         }
     }
+
+    let path = path.ok_or("usage: disasm <file> [--org <addr>] [--bank <n>] [--cpu <variant>] [--symbols <file>] [--cdl <file>] [--patch <file>] [--gamedb <file>]")?;
+    let raw = read_rom(path, patch_file)?;
+
+    let mut log = None;
+    let (bytes, org, prg_size) = if Rom::is_ines(&raw) {
+        let rom = parse_rom(&raw, gamedb)?;
+        if bank >= rom.prg_banks() {
+            return Err(format!("bank {} out of range, ROM has {} PRG banks", bank, rom.prg_banks()));
+        }
+        let start = bank * cartridge::PRG_ROM_PAGE_SIZE;
+        let end = start + cartridge::PRG_ROM_PAGE_SIZE;
+        if let Some(file) = cdl {
+            let data = std::fs::read(file).map_err(|err| format!("{}: {}", file, err))?;
+            let cdl = CodeDataLog::from_bytes(&data, rom.prg_rom.len(), rom.chr_rom.len())
+                .map_err(|err| format!("{}: {}", file, err))?;
+            log = Some(cdl.prg[start..end].to_vec());
+        }
+        let default_org = if bank == rom.prg_banks() - 1 { 0xc000 } else { 0x8000 };
+        (rom.prg_rom[start..end].to_vec(), org.unwrap_or(default_org), rom.prg_rom.len())
+    } else if cdl.is_some() {
+        return Err("--cdl needs a .nes file".to_string());
+    } else {
+        let len = raw.len();
+        (raw, org.unwrap_or(0x0000), len)
+    };
+    let symbols = symbol_file.map(|file| symbols::load(file, prg_size)).transpose()?;
+
+    let instructions = match log {
+        Some(log) => disasm::disassemble_logged(variant, &bytes, org, symbols.as_ref(), &log),
+        None => disasm::disassemble_variant(variant, &bytes, org, symbols.as_ref()),
+    };
+    print!("{}", disasm::format_listing(&instructions, symbols.as_ref()));
+    Ok(())
 }
 
-fn main() {
-    println!("Rust NES Emulator!");    
-
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("Snake Game", {32.0 * 10.0} as u32, (32.0 * 10.0) as u32)
-        .position_centered()
-        .build().unwrap();
-
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
-
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
-
-    let game_code = vec![
-        0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9, 0x02, 0x85,
-        0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85, 0x12, 0xa9, 0x0f, 0x85,
-        0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60, 0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe,
-        0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60, 0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3,
-        0x06, 0x20, 0x19, 0x07, 0x20, 0x20, 0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9,
-        0x77, 0xf0, 0x0d, 0xc9, 0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60,
-        0xa9, 0x04, 0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
-        0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04, 0x85, 0x02,
-        0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60, 0x60, 0x20, 0x94, 0x06,
-        0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d, 0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07,
-        0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60, 0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06,
-        0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09, 0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c,
-        0x35, 0x07, 0x60, 0xa6, 0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02,
-        0x4a, 0xb0, 0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
-        0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28, 0x60, 0xe6,
-        0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69, 0x20, 0x85, 0x10, 0xb0,
-        0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c, 0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29,
-        0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35, 0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60,
-        0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10, 0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa2, 0x00, 0xea,
-        0xea, 0xca, 0xd0, 0xfb, 0x60
+// trace <rom.nes> [--pc <addr>] [--steps <n>] [--out <file>] [--symbols <file>]
+//       [--patch <file>] [--gamedb <file>]
+// Logs every executed instruction in nestest.log format to --out (default stdout).
+// --pc overrides the reset vector, e.g. `--pc C000` runs nestest in automation mode.
+// With --symbols, operands are shown as labels
+fn trace_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+    let mut pc = None;
+    let mut steps = None;
+    let mut out = None;
+    let mut symbol_file = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pc" => {
+                let value = iter.next().ok_or("--pc needs an address")?;
+                pc = Some(parse_hex(value)?);
+            }
+            "--steps" => {
+                let value = iter.next().ok_or("--steps needs a number")?;
+                steps = Some(value.parse::<usize>().map_err(|_| format!("invalid step count: {}", value))?);
+            }
+            "--out" => out = Some(iter.next().ok_or("--out needs a file name")?),
+            "--symbols" => symbol_file = Some(iter.next().ok_or("--symbols needs a file name")?),
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            _ => positional(&mut path, arg)?,
+        }
+    }
+
+    let path = path.ok_or("usage: trace <rom.nes> [--pc <addr>] [--steps <n>] [--out <file>] [--symbols <file>] [--patch <file>] [--gamedb <file>]")?;
+    let rom = load_rom(path, patch_file, gamedb)?;
+    let symbols = symbol_file.map(|file| symbols::load(file, rom.prg_rom.len())).transpose()?;
+
+    let mut cpu = CPU::with_bus(NesBus::new(rom)?);
+    cpu.reset();
+    if let Some(pc) = pc {
+        cpu.program_counter = pc;
+    }
+
+    let mut tracer = match out {
+        Some(file) => trace::Tracer::to_file(file).map_err(|err| format!("{}: {}", file, err))?,
+        None => trace::Tracer::stdout(),
+    };
+    if let Some(symbols) = symbols {
+        tracer = tracer.with_symbols(symbols);
+    }
+
+    let mut executed = 0;
+    while steps.is_none_or(|steps| executed < steps) {
+        let result = tracer.step(&mut cpu).map_err(|err| err.to_string())?;
+        executed += 1;
+        if let Some(halt) = result.halt {
+            eprintln!("CPU halted: {}", halt);
+            break;
+        }
+    }
+
+    tracer.flush().map_err(|err| err.to_string())
+}
+
+// run <rom.nes> [--frames <n>] [--until <cond>] [--movie <file.fm2>]
+//     [--png <file>] [--wav <file>] [--ram <file>] [--record <file.avi|file.y4m>]
+//     [--palette <name|file.pal>] [--region ntsc|pal|dendy] [--cdl <file>]
+//     [--profile] [--flamegraph <file>] [--symbols <file>] [--cheats <file>]
+//     [--patch <file>] [--gamedb <file>]
+// Runs without a window for --frames frames (default 600), or until a memory condition
+// like `6000<80` holds, which fails if it doesn't within --frames. The final frame, audio
+// and 2KB RAM hex dump are written to the given files, and their CRC-32s to stdout.
+// --record dumps every frame and its audio as it runs. --palette takes a built-in
+// palette (2c02, 2c07, 2c03, 2c05) or a .pal file. --region overrides the console the
+// NES 2.0 header asks for. --cdl logs how the ROM was accessed into an FCEUX .cdl file,
+// adding to it if it exists, and prints the coverage. --profile prints the cycles each
+// routine took per frame and the hottest instructions, --flamegraph writes the call
+// stacks in the collapsed format of flamegraph.pl; --symbols names the routines.
+// Cheats come from --cheats, or the ROM's .cht file if there is one
+fn run_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+    let mut frames = 600;
+    let mut until = None;
+    let mut movie = None;
+    let mut png_out = None;
+    let mut wav_out = None;
+    let mut ram_out = None;
+    let mut record = None;
+    let mut palette = None;
+    let mut region = None;
+    let mut cdl = None;
+    let mut profile = false;
+    let mut flamegraph = None;
+    let mut symbol_file = None;
+    let mut cheat_file = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--frames" => {
+                let value = iter.next().ok_or("--frames needs a number")?;
+                frames = value.parse().map_err(|_| format!("invalid frame count: {}", value))?;
+            }
+            "--until" => {
+                let value = iter.next().ok_or("--until needs a condition")?;
+                until = Some(value.parse::<MemCondition>()?);
+            }
+            "--movie" => {
+                let file = iter.next().ok_or("--movie needs a file name")?;
+                let text = std::fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+                movie = Some(Movie::parse(&text).map_err(|err| format!("{}: {}", file, err))?);
+            }
+            "--png" => png_out = Some(iter.next().ok_or("--png needs a file name")?),
+            "--wav" => wav_out = Some(iter.next().ok_or("--wav needs a file name")?),
+            "--ram" => ram_out = Some(iter.next().ok_or("--ram needs a file name")?),
+            "--record" => record = Some(iter.next().ok_or("--record needs a file name")?),
+            "--palette" => {
+                let value = iter.next().ok_or("--palette needs a palette name or file")?;
+                palette = Some(Palette::load(value)?);
+            }
+            "--region" => {
+                let value = iter.next().ok_or("--region needs ntsc, pal or dendy")?;
+                region = Some(value.parse::<Region>()?);
+            }
+            "--cdl" => cdl = Some(iter.next().ok_or("--cdl needs a file name")?),
+            "--profile" => profile = true,
+            "--flamegraph" => flamegraph = Some(iter.next().ok_or("--flamegraph needs a file name")?),
+            "--symbols" => symbol_file = Some(iter.next().ok_or("--symbols needs a file name")?),
+            "--cheats" => cheat_file = Some(iter.next().ok_or("--cheats needs a file name")?.clone()),
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            _ => positional(&mut path, arg)?,
+        }
+    }
+
+    let path = path.ok_or(
+        "usage: run <rom.nes> [--frames <n>] [--until <cond>] [--movie <file>] [--png <file>] [--wav <file>] [--ram <file>] [--record <file>] [--palette <name|file>] [--region <region>] [--cdl <file>] [--profile] [--flamegraph <file>] [--symbols <file>] [--cheats <file>] [--patch <file>] [--gamedb <file>]",
+    )?;
+    let mut rom = load_rom(path, patch_file, gamedb)?;
+    if region.is_some() {
+        rom.region = region;
+    }
+    let log = match cdl {
+        Some(file) => Some(CodeDataLog::load_or_new(file, rom.prg_rom.len(), rom.chr_rom.len())?),
+        None => None,
+    };
+    let symbols = symbol_file.map(|file| symbols::load(file, rom.prg_rom.len())).transpose()?;
+    let mut headless = Headless::new(rom, movie)?;
+    headless.cpu.cheats = Cheats::load_or_new(&cheat_file.unwrap_or_else(|| Cheats::file_for(path)))?;
+    if profile || flamegraph.is_some() {
+        headless.profiler = Some(Profiler::new());
+    }
+    if let Some(log) = log {
+        headless.cpu.bus.start_code_data_log(log)?;
+    }
+
+    if let Some(palette) = palette {
+        headless.palette = palette;
+    }
+    if let Some(file) = record {
+        let frame_rate = headless.cpu.bus.region().frame_rate();
+        headless.recorder = Some(Recorder::create(file, frame_rate, headless.cpu.bus.apu.sample_rate)?);
+    }
+
+    let result = headless.run(frames, until.as_ref());
+    if let Some(recorder) = headless.recorder.take() {
+        recorder.finish()?;
+    }
+    if let (Some(file), Some(log)) = (cdl, headless.cpu.bus.code_data_log()) {
+        log.save(file)?;
+        println!("{}", log.coverage());
+    }
+    if let Some(profiler) = &headless.profiler {
+        if let Some(file) = flamegraph {
+            let stacks = profiler.collapsed_stacks(symbols.as_ref());
+            std::fs::write(file, stacks).map_err(|err| format!("{}: {}", file, err))?;
+        }
+        if profile {
+            print!("{}", profiler.report(symbols.as_ref(), PROFILE_INSTRUCTIONS));
+        }
+    }
+    let met = result?;
+    println!("frames {}", headless.frame);
+
+    let outputs = [
+        ("frame", png_out, headless.cpu.bus.ppu.frame.to_png(&headless.palette, 1)),
+        ("audio", wav_out, wav::encode(&headless.audio, headless.cpu.bus.apu.sample_rate)),
+        ("ram", ram_out, headless::hex_dump(headless.cpu.bus.ram(), 0).into_bytes()),
     ];
+    for (name, file, data) in outputs.iter() {
+        if let Some(file) = file {
+            std::fs::write(file, data).map_err(|err| format!("{}: {}", file, err))?;
+        }
+        println!("{} crc32 {:08x}", name, hash::crc32(data));
+    }
+
+    match until {
+        Some(condition) if !met => Err(format!("{} not met after {} frames", condition, frames)),
+        _ => Ok(()),
+    }
+}
+
+// debug <rom.nes> [--pc <addr>] [--symbols <file>] [--cheats <file>] [--patch <file>]
+//       [--gamedb <file>]
+// Interactive monitor on stdin, see `help` for the commands. An empty line repeats the
+// previous command, e.g. to keep stepping. --symbols allows labels in place of addresses
+// and shows the source line when stopping (ca65 .dbg files). Cheats are loaded from and
+// saved to --cheats, by default the ROM's .cht file
+fn debug_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+    let mut pc = None;
+    let mut symbol_file = None;
+    let mut cheat_file = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pc" => {
+                let value = iter.next().ok_or("--pc needs an address")?;
+                pc = Some(parse_hex(value)?);
+            }
+            "--symbols" => symbol_file = Some(iter.next().ok_or("--symbols needs a file name")?),
+            "--cheats" => cheat_file = Some(iter.next().ok_or("--cheats needs a file name")?.clone()),
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            _ => positional(&mut path, arg)?,
+        }
+    }
+
+    let path = path.ok_or("usage: debug <rom.nes> [--pc <addr>] [--symbols <file>] [--cheats <file>] [--patch <file>] [--gamedb <file>]")?;
+    let rom = load_rom(path, patch_file, gamedb)?;
+    let symbols = symbol_file.map(|file| symbols::load(file, rom.prg_rom.len())).transpose()?;
+    let mut cpu = CPU::with_bus(NesBus::new(rom)?);
+    cpu.reset();
+    if let Some(pc) = pc {
+        cpu.program_counter = pc;
+    }
+    let cheat_file = cheat_file.unwrap_or_else(|| Cheats::file_for(path));
+    cpu.cheats = Cheats::load_or_new(&cheat_file)?;
+    let mut debugger = Debugger::new(cpu);
+    debugger.cheat_file = Some(cheat_file);
+    if let Some(symbols) = symbols {
+        debugger.symbols = symbols;
+    }
+    println!("{}", trace::trace_with_symbols(&debugger.cpu, Some(&debugger.symbols)));
+
+    let stdin = std::io::stdin();
+    let mut last = String::new();
+    loop {
+        print!("> ");
+        std::io::Write::flush(&mut std::io::stdout()).map_err(|err| err.to_string())?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).map_err(|err| err.to_string())? == 0 {
+            return Ok(());
+        }
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        if line == "q" || line == "quit" {
+            return Ok(());
+        }
+
+        match debugger.execute(&line) {
+            Ok(output) => print!("{}", output),
+            Err(err) => eprintln!("{}", err),
+        }
+        last = line;
+    }
+}
+
+// gdb <rom.nes> [--port <n>] [--patch <file>] [--gamedb <file>]
+// Waits for GDB remote protocol clients on localhost (port 6502 by default), one at a
+// time, until one kills the target
+fn gdb_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut patch_file = None;
+    let mut gamedb = None;
+    let mut port: u16 = 6502;
 
-    let mut cpu = CPU::new();
-    cpu.load(game_code);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--port" => {
+                let value = iter.next().ok_or("--port needs a port number")?;
+                port = value.parse().map_err(|_| format!("invalid port: {}", value))?;
+            }
+            "--gamedb" => gamedb = Some(iter.next().ok_or("--gamedb needs a file name")?.as_str()),
+            "--patch" => patch_file = Some(iter.next().ok_or("--patch needs a file name")?.as_str()),
+            _ => positional(&mut path, arg)?,
+        }
+    }
+
+    let path = path.ok_or("usage: gdb <rom.nes> [--port <n>] [--patch <file>] [--gamedb <file>]")?;
+    let mut cpu = CPU::with_bus(NesBus::new(load_rom(path, patch_file, gamedb)?)?);
     cpu.reset();
+    let mut stub = GdbStub::new(Debugger::new(cpu));
+
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port)).map_err(|err| format!("port {}: {}", port, err))?;
+    println!("listening on 127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|err| err.to_string())?;
+        match stub.serve(stream) {
+            Ok(true) => return Ok(()),
+            Ok(false) => println!("client detached"),
+            Err(err) => eprintln!("connection lost: {}", err),
+        }
+    }
+    Ok(())
+}
 
-    let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
+// asm <source.s> --out <file> [--org <addr>] [--cpu <2a03|6502|65c02>] [--chr <file>]
+// Assembles to a raw binary of the bytes written, or to an NROM iNES image when --out
+// ends in .nes, with the CHR ROM from --chr (blank 8KB by default). Code before the
+// first .org goes at --org (default $8000)
+fn asm_command(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut out = None;
+    let mut org = 0x8000;
+    let mut variant = CpuVariant::Nes2A03;
+    let mut chr = None;
 
-    cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--out" => out = Some(iter.next().ok_or("--out needs a file name")?),
+            "--org" => {
+                let value = iter.next().ok_or("--org needs an address")?;
+                org = parse_hex(value)?;
+            }
+            "--cpu" => {
+                let value = iter.next().ok_or("--cpu needs a CPU variant")?;
+                variant = value.parse()?;
+            }
+            "--chr" => chr = Some(iter.next().ok_or("--chr needs a file name")?),
+            _ => positional(&mut path, arg)?,
+        }
+    }
 
-        cpu.mem_write(0xfe, rng.gen_range(1, 16));
+    let usage = "usage: asm <source.s> --out <file> [--org <addr>] [--cpu <variant>] [--chr <file>]";
+    let (path, out) = path.zip(out).ok_or(usage)?;
+    let output = Assembler::new(variant).assemble_file(path, org)?;
+    println!("{} bytes at ${:04X}", output.bytes.len(), output.origin);
 
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
+    let data = if out.to_ascii_lowercase().ends_with(".nes") {
+        let chr = match chr {
+            Some(file) => std::fs::read(file).map_err(|err| format!("{}: {}", file, err))?,
+            None => Vec::new(),
+        };
+        output.to_ines(&chr)?
+    } else {
+        output.bytes
+    };
+    std::fs::write(out, data).map_err(|err| format!("{}: {}", out, err))
+}
 
-            canvas.copy(&texture, None, None).unwrap();
+type Command = fn(&[String]) -> Result<(), String>;
 
-            canvas.present();
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    let command: Option<Command> = match args.get(1).map(String::as_str) {
+        Some("disasm") => Some(disasm_command),
+        Some("trace") => Some(trace_command),
+        Some("run") => Some(run_command),
+        Some("debug") => Some(debug_command),
+        Some("gdb") => Some(gdb_command),
+        Some("asm") => Some(asm_command),
+        _ => None,
+    };
+
+    if let Some(command) = command {
+        if let Err(err) = command(&args[2..]) {
+            eprintln!("{}: {}", args[1], err);
+            std::process::exit(1);
         }
+        return;
+    }
 
-        ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    });
+    #[cfg(feature = "sdl")]
+    if let Err(err) = frontend::run(&args[1..]) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 
+    #[cfg(not(feature = "sdl"))]
+    {
+        eprintln!("usage: {} disasm|trace|run|debug|gdb|asm <args>", args[0]);
+        eprintln!("build with `--features sdl` to play ROMs");
+        std::process::exit(1);
+    }
 }
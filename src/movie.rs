@@ -0,0 +1,121 @@
+// Input movies in FCEUX's FM2 text format. Header lines ("key value") are ignored;
+// every line starting with '|' is one frame of input:
+//
+//   |commands|port0|port1|port2|
+//   |0|R...T..A|........||
+//
+// Gamepad fields list the buttons RLDUTSBA, where '.' or ' ' means released. Bit 0 of
+// the commands field is a soft reset
+
+use crate::joypad::JoypadButton;
+
+const BUTTON_ORDER: [JoypadButton; 8] = [
+    JoypadButton::RIGHT,
+    JoypadButton::LEFT,
+    JoypadButton::DOWN,
+    JoypadButton::UP,
+    JoypadButton::START,
+    JoypadButton::SELECT,
+    JoypadButton::BUTTON_B,
+    JoypadButton::BUTTON_A,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub reset: bool,
+    pub pads: [JoypadButton; 2],
+}
+
+impl MovieFrame {
+    // No buttons pressed, played after the end of a movie
+    pub fn idle() -> Self {
+        MovieFrame {
+            reset: false,
+            pads: [JoypadButton::empty(); 2],
+        }
+    }
+}
+
+pub struct Movie {
+    pub frames: Vec<MovieFrame>,
+}
+
+fn parse_gamepad(field: &str) -> Result<JoypadButton, String> {
+    if field.is_empty() {
+        return Ok(JoypadButton::empty());
+    }
+    if field.len() != BUTTON_ORDER.len() {
+        return Err(format!("gamepad field {:?} should have 8 buttons", field));
+    }
+    let mut buttons = JoypadButton::empty();
+    for (c, button) in field.chars().zip(BUTTON_ORDER.iter()) {
+        if c != '.' && c != ' ' {
+            buttons.insert(*button);
+        }
+    }
+    Ok(buttons)
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut frames = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if !line.starts_with('|') {
+                continue;
+            }
+            let error = |msg: String| format!("line {}: {}", number + 1, msg);
+
+            let fields: Vec<&str> = line[1..].split('|').collect();
+            if fields.len() < 3 {
+                return Err(error("expected |commands|port0|port1|".to_string()));
+            }
+            let commands: u8 = fields[0]
+                .parse()
+                .map_err(|_| error(format!("invalid commands field {:?}", fields[0])))?;
+
+            frames.push(MovieFrame {
+                reset: commands & 1 != 0,
+                pads: [
+                    parse_gamepad(fields[1]).map_err(error)?,
+                    parse_gamepad(fields[2]).map_err(error)?,
+                ],
+            });
+        }
+
+        Ok(Movie { frames })
+    }
+
+    // Input for `frame`, counted from 0
+    pub fn frame(&self, frame: usize) -> MovieFrame {
+        self.frames.get(frame).copied().unwrap_or_else(MovieFrame::idle)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fm2() {
+        let movie = Movie::parse("version 3\nport0 1\n|0|........|||\n|0|R..UT..A|||\n|1|.......A|.L......||\n")
+            .unwrap();
+
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frame(0), MovieFrame::idle());
+        assert_eq!(
+            movie.frame(1).pads[0],
+            JoypadButton::RIGHT | JoypadButton::UP | JoypadButton::START | JoypadButton::BUTTON_A
+        );
+        assert!(movie.frame(2).reset);
+        assert_eq!(movie.frame(2).pads[1], JoypadButton::LEFT);
+        assert_eq!(movie.frame(3), MovieFrame::idle());
+    }
+
+    #[test]
+    fn test_bad_gamepad_field() {
+        assert!(Movie::parse("|0|RL|||\n").is_err());
+        assert!(Movie::parse("|x|........|||\n").is_err());
+    }
+}
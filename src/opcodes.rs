@@ -1,4 +1,4 @@
-use crate::cpu::AddressingMode;
+use crate::cpu::{AddressingMode, CpuVariant};
 use std::collections::HashMap;
 
 // Struct packed with information of given OpCode 
@@ -15,11 +15,11 @@ impl OpCode {
     // Defininf a new OpCode
     fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
         OpCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode,
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
         }
     }
 }
@@ -78,25 +78,25 @@ lazy_static! {
         OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
 
         /* Shifts */
-        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
 
-        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x4a, "LSR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::Absolute_X),
 
-        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::Absolute_X),
 
-        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::Accumulator),
         OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
@@ -137,22 +137,22 @@ lazy_static! {
 
 
         /* Branching */
-        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::NoneAddressing), //AddressingMode that acts as Immidiate
-        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::NoneAddressing), //AddressingMode:Indirect with 6502 bug
+        OpCode::new(0x4c, "JMP", 3, 3, AddressingMode::Absolute), //AddressingMode that acts as Immidiate
+        OpCode::new(0x6c, "JMP", 3, 5, AddressingMode::Indirect), //AddressingMode:Indirect with 6502 bug
 
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::NoneAddressing),
+        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
 
         OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
 
-        OpCode::new(0xd0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xf0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xb0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+        OpCode::new(0xd0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0xf0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0xb0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
+        OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::Relative),
 
         OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
@@ -228,4 +228,123 @@ lazy_static! {
         }
         map
     }; 
+
+    // 65C02 additions and changes on top of the NMOS instruction set
+    pub static ref CMOS_OPS_CODES: Vec<OpCode> = {
+        let mut ops = vec![
+            OpCode::new(0x6c, "JMP", 3, 6, AddressingMode::Indirect), //page wrap bug fixed, one extra cycle
+            OpCode::new(0x7c, "JMP", 3, 6, AddressingMode::Indirect_Absolute_X),
+            OpCode::new(0x80, "BRA", 2, 2 /*(+1 always, +2 if to a new page)*/, AddressingMode::Relative),
+
+            /* (zp) addressing */
+            OpCode::new(0x12, "ORA", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0x32, "AND", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0x52, "EOR", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0x72, "ADC", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0x92, "STA", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0xb2, "LDA", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0xd2, "CMP", 2, 5, AddressingMode::ZeroPage_Indirect),
+            OpCode::new(0xf2, "SBC", 2, 5, AddressingMode::ZeroPage_Indirect),
+
+            OpCode::new(0x89, "BIT", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x34, "BIT", 2, 4, AddressingMode::ZeroPage_X),
+            OpCode::new(0x3c, "BIT", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+            OpCode::new(0x1a, "INC", 1, 2, AddressingMode::Accumulator),
+            OpCode::new(0x3a, "DEC", 1, 2, AddressingMode::Accumulator),
+
+            /* Stack */
+            OpCode::new(0xda, "PHX", 1, 3, AddressingMode::NoneAddressing),
+            OpCode::new(0x5a, "PHY", 1, 3, AddressingMode::NoneAddressing),
+            OpCode::new(0xfa, "PLX", 1, 4, AddressingMode::NoneAddressing),
+            OpCode::new(0x7a, "PLY", 1, 4, AddressingMode::NoneAddressing),
+
+            /* Stores */
+            OpCode::new(0x64, "STZ", 2, 3, AddressingMode::ZeroPage),
+            OpCode::new(0x74, "STZ", 2, 4, AddressingMode::ZeroPage_X),
+            OpCode::new(0x9c, "STZ", 3, 4, AddressingMode::Absolute),
+            OpCode::new(0x9e, "STZ", 3, 5, AddressingMode::Absolute_X),
+
+            /* Bit test and set/reset */
+            OpCode::new(0x04, "TSB", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x0c, "TSB", 3, 6, AddressingMode::Absolute),
+            OpCode::new(0x14, "TRB", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x1c, "TRB", 3, 6, AddressingMode::Absolute),
+
+            /* Rockwell/WDC bit manipulation */
+            OpCode::new(0x07, "RMB0", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x17, "RMB1", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x27, "RMB2", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x37, "RMB3", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x47, "RMB4", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x57, "RMB5", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x67, "RMB6", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x77, "RMB7", 2, 5, AddressingMode::ZeroPage),
+
+            OpCode::new(0x87, "SMB0", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0x97, "SMB1", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xa7, "SMB2", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xb7, "SMB3", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xc7, "SMB4", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xd7, "SMB5", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xe7, "SMB6", 2, 5, AddressingMode::ZeroPage),
+            OpCode::new(0xf7, "SMB7", 2, 5, AddressingMode::ZeroPage),
+
+            OpCode::new(0x0f, "BBR0", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x1f, "BBR1", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x2f, "BBR2", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x3f, "BBR3", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x4f, "BBR4", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x5f, "BBR5", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x6f, "BBR6", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x7f, "BBR7", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+
+            OpCode::new(0x8f, "BBS0", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0x9f, "BBS1", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xaf, "BBS2", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xbf, "BBS3", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xcf, "BBS4", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xdf, "BBS5", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xef, "BBS6", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+            OpCode::new(0xff, "BBS7", 3, 5 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::ZeroPage_Relative),
+
+            /* Undefined opcodes are NOPs of a fixed length and timing */
+            OpCode::new(0x02, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x22, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x42, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x62, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0xc2, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0xe2, "NOP", 2, 2, AddressingMode::Immediate),
+            OpCode::new(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+            OpCode::new(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+            OpCode::new(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+            OpCode::new(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+            OpCode::new(0x5c, "NOP", 3, 8, AddressingMode::Absolute),
+            OpCode::new(0xdc, "NOP", 3, 4, AddressingMode::Absolute),
+            OpCode::new(0xfc, "NOP", 3, 4, AddressingMode::Absolute),
+        ];
+        // the $x3 and $xB columns take one byte and one cycle ($CB and $DB too, as on the
+        // Rockwell parts rather than WDC's WAI and STP)
+        for code in (0x03..=0xf3).step_by(0x10).chain((0x0b..=0xfb).step_by(0x10)) {
+            ops.push(OpCode::new(code, "NOP", 1, 1, AddressingMode::NoneAddressing));
+        }
+        ops
+    };
+
+    pub static ref CMOS_OPCODES_MAP: HashMap<u8, &'static OpCode> = {
+        let mut map = OPCODES_MAP.clone();
+        for cpuop in &*CMOS_OPS_CODES {
+            map.insert(cpuop.code, cpuop);
+        }
+        map
+    };
+}
+
+// Find the opcode for `code` in the instruction set of the given CPU variant
+pub fn lookup(variant: CpuVariant, code: u8) -> Option<&'static OpCode> {
+    match variant {
+        CpuVariant::Cmos65C02 => CMOS_OPCODES_MAP.get(&code).copied(),
+        CpuVariant::Nes2A03 | CpuVariant::Nmos6502 => OPCODES_MAP.get(&code).copied(),
+    }
 }
@@ -0,0 +1,428 @@
+// ROM patches in the IPS, BPS and UPS formats, told apart by their magic bytes. They
+// apply to the whole file, header included, so they run before the iNES header is parsed.
+//
+// IPS: "PATCH", records of a 24-bit offset and 16-bit length followed by that many bytes
+//      (length 0: a 16-bit count and one byte repeated), "EOF", then optionally the
+//      24-bit size to truncate the result to.
+// UPS: "UPS1", source and target sizes, then runs of a skip and bytes XORed into the
+//      source up to a 0 byte.
+// BPS: "BPS1", source, target and metadata sizes, the metadata, then actions that copy
+//      from the source, the patch or the output so far.
+// UPS and BPS sizes and offsets are variable length numbers, and both end with the
+// CRC-32s of the source, the target and the patch itself, which are all checked.
+
+use std::fmt;
+
+use crate::hash;
+
+// Extensions looked for next to a ROM, in order
+pub const EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+// Largest result a UPS or BPS patch may ask for, well past any NES image, so a corrupt
+// size can't make it allocate gigabytes
+pub const MAX_TARGET_SIZE: usize = 32 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ips,
+    Bps,
+    Ups,
+}
+
+impl Format {
+    pub fn detect(patch: &[u8]) -> Option<Format> {
+        if patch.starts_with(b"PATCH") {
+            Some(Format::Ips)
+        } else if patch.starts_with(b"BPS1") {
+            Some(Format::Bps)
+        } else if patch.starts_with(b"UPS1") {
+            Some(Format::Ups)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Ips => f.write_str("IPS"),
+            Format::Bps => f.write_str("BPS"),
+            Format::Ups => f.write_str("UPS"),
+        }
+    }
+}
+
+// Read a ROM file and apply the `patch` file to it, or else the first .ips, .bps or .ups
+// file next to it with the same name. Returns the patch applied along with the data
+pub fn read_rom(path: &str, patch: Option<&str>) -> Result<(Vec<u8>, Option<String>), String> {
+    let raw = std::fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let patch = match patch {
+        Some(patch) => Some(patch.to_string()),
+        None => EXTENSIONS
+            .iter()
+            .map(|extension| std::path::Path::new(path).with_extension(extension))
+            .find(|file| file.is_file())
+            .map(|file| file.to_string_lossy().into_owned()),
+    };
+    match patch {
+        Some(patch) => {
+            let data = std::fs::read(&patch).map_err(|err| format!("{}: {}", patch, err))?;
+            let patched = apply(&data, &raw).map_err(|err| format!("{}: {}", patch, err))?;
+            Ok((patched, Some(patch)))
+        }
+        None => Ok((raw, None)),
+    }
+}
+
+// Patch `source` with the IPS, BPS or UPS `patch`
+pub fn apply(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, String> {
+    match Format::detect(patch) {
+        Some(Format::Ips) => apply_ips(patch, source),
+        Some(Format::Bps) => apply_bps(patch, source),
+        Some(Format::Ups) => apply_ups(patch, source),
+        None => Err("not an IPS, BPS or UPS patch".to_string()),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.data.get(self.pos).ok_or("patch is truncated")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or("patch is truncated")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn big_endian(&mut self, len: usize) -> Result<usize, String> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, &byte| value << 8 | byte as usize))
+    }
+
+    // UPS/BPS number: 7 bits a byte, low bits first, the last byte flagged with bit 7.
+    // Each continuation also adds one, so every number has a single encoding
+    fn number(&mut self) -> Result<usize, String> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = (byte as usize & 0x7f)
+                .checked_mul(shift)
+                .and_then(|add| value.checked_add(add))
+                .ok_or("number in patch is too large")?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).filter(|&shift| shift < 1 << 56).ok_or("number in patch is too large")?;
+            value += shift;
+        }
+    }
+}
+
+fn apply_ips(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = source.to_vec();
+    let mut reader = Reader { data: patch, pos: 5 };
+    loop {
+        if reader.data.get(reader.pos..reader.pos + 3) == Some(b"EOF") {
+            reader.pos += 3;
+            if reader.data.len() - reader.pos >= 3 {
+                let size = reader.big_endian(3)?;
+                output.truncate(size);
+            }
+            return Ok(output);
+        }
+        let offset = reader.big_endian(3)?;
+        let (data, len) = match reader.big_endian(2)? {
+            0 => {
+                let count = reader.big_endian(2)?;
+                (None, count)
+            }
+            len => (Some(reader.bytes(len)?), len),
+        };
+        if output.len() < offset + len {
+            output.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => output[offset..offset + len].copy_from_slice(data),
+            None => {
+                let value = reader.byte()?;
+                output[offset..offset + len].fill(value);
+            }
+        }
+    }
+}
+
+// The CRC-32s of source, target and patch at the end of UPS and BPS patches
+struct Footer {
+    source: u32,
+    target: u32,
+}
+
+fn check_footer(format: Format, patch: &[u8], source: &[u8], source_size: usize) -> Result<Footer, String> {
+    if patch.len() < 16 {
+        return Err(format!("{} patch is truncated", format));
+    }
+    let crc_at = |pos: usize| u32::from_le_bytes([patch[pos], patch[pos + 1], patch[pos + 2], patch[pos + 3]]);
+    let end = patch.len() - 12;
+    let footer = Footer {
+        source: crc_at(end),
+        target: crc_at(end + 4),
+    };
+
+    let expected = crc_at(end + 8);
+    let actual = hash::crc32(&patch[..end + 8]);
+    if actual != expected {
+        return Err(format!("{} patch is corrupt: CRC32 {:08x}, expected {:08x}", format, actual, expected));
+    }
+    let actual = hash::crc32(source);
+    if actual != footer.source || source.len() != source_size {
+        return Err(format!(
+            "{} patch doesn't match this ROM: it was made for {} bytes with CRC32 {:08x}, this ROM is {} bytes with CRC32 {:08x}",
+            format,
+            source_size,
+            footer.source,
+            source.len(),
+            actual
+        ));
+    }
+    Ok(footer)
+}
+
+fn check_target_size(format: Format, target_size: usize) -> Result<(), String> {
+    if target_size > MAX_TARGET_SIZE {
+        return Err(format!(
+            "{} patch asks for a {} byte ROM, more than the {} byte limit",
+            format, target_size, MAX_TARGET_SIZE
+        ));
+    }
+    Ok(())
+}
+
+fn check_target(format: Format, output: &[u8], target_size: usize, footer: &Footer) -> Result<(), String> {
+    let actual = hash::crc32(output);
+    if output.len() != target_size || actual != footer.target {
+        return Err(format!(
+            "{} patch produced {} bytes with CRC32 {:08x}, expected {} bytes with CRC32 {:08x}",
+            format,
+            output.len(),
+            actual,
+            target_size,
+            footer.target
+        ));
+    }
+    Ok(())
+}
+
+fn apply_ups(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = Reader { data: patch, pos: 4 };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(Format::Ups, target_size)?;
+    let footer = check_footer(Format::Ups, patch, source, source_size)?;
+
+    let mut output = source.to_vec();
+    output.resize(target_size, 0);
+    let end = patch.len() - 12;
+    let mut pos = 0usize;
+    while reader.pos < end {
+        pos = pos.checked_add(reader.number()?).ok_or("UPS patch offset is too large")?;
+        loop {
+            let byte = reader.byte()?;
+            if let Some(out) = output.get_mut(pos) {
+                *out ^= byte;
+            }
+            pos += 1;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    check_target(Format::Ups, &output, target_size, &footer)?;
+    Ok(output)
+}
+
+// Move a BPS copy offset by a number whose low bit is the sign
+fn relative_offset(offset: usize, delta: usize) -> Result<usize, String> {
+    let moved = if delta & 1 != 0 { offset.checked_sub(delta >> 1) } else { offset.checked_add(delta >> 1) };
+    moved.ok_or_else(|| "BPS patch copies from before the start".to_string())
+}
+
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, String> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let mut reader = Reader { data: patch, pos: 4 };
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(Format::Bps, target_size)?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    let footer = check_footer(Format::Bps, patch, source, source_size)?;
+
+    let outside = || "BPS patch copies from outside the data".to_string();
+    let mut output = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    let end = patch.len() - 12;
+    while reader.pos < end {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        if output.len() + len > target_size {
+            return Err(format!("BPS patch writes past the {} byte target", target_size));
+        }
+        match action & 3 {
+            SOURCE_READ => {
+                let start = output.len();
+                output.extend_from_slice(source.get(start..start + len).ok_or_else(outside)?);
+            }
+            TARGET_READ => output.extend_from_slice(reader.bytes(len)?),
+            SOURCE_COPY => {
+                source_offset = relative_offset(source_offset, reader.number()?)?;
+                output.extend_from_slice(source.get(source_offset..source_offset + len).ok_or_else(outside)?);
+                source_offset += len;
+            }
+            // target copy, byte by byte as the run may overlap what it writes
+            _ => {
+                target_offset = relative_offset(target_offset, reader.number()?)?;
+                for _ in 0..len {
+                    let byte = *output.get(target_offset).ok_or_else(outside)?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    check_target(Format::Bps, &output, target_size, &footer)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(0x80 | byte);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn finish(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&hash::crc32(source).to_le_bytes());
+        patch.extend_from_slice(&hash::crc32(target).to_le_bytes());
+        let crc = hash::crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn test_numbers() {
+        for value in [0, 1, 127, 128, 255, 16511, 16512, 1 << 30] {
+            let mut encoded = Vec::new();
+            number(value, &mut encoded);
+            assert_eq!(Reader { data: &encoded, pos: 0 }.number(), Ok(value));
+        }
+        assert!(Reader { data: &[0; 12], pos: 0 }.number().is_err());
+    }
+
+    #[test]
+    fn test_ips() {
+        let mut patch = b"PATCH".to_vec();
+        // 2 bytes at 1, then 3 x $FF at 6 growing the file
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 3, 0xff]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(apply(&patch, &[0; 4]).unwrap(), vec![0, 0xaa, 0xbb, 0, 0, 0, 0xff, 0xff, 0xff]);
+
+        // truncated to 2 bytes
+        patch.extend_from_slice(&[0, 0, 2]);
+        assert_eq!(apply(&patch, &[0; 4]).unwrap(), vec![0, 0xaa]);
+
+        assert!(apply(b"PATCH\0\0\x01\0\x05\x01", &[0; 4]).unwrap_err().contains("truncated"));
+    }
+
+    #[test]
+    fn test_ups() {
+        let source = b"Hello, world".to_vec();
+        let target = b"Hello, World!".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(target.len(), &mut patch);
+        // skip 7, XOR 'w' ^ 'W', then the end of the run
+        number(7, &mut patch);
+        patch.extend_from_slice(&[b'w' ^ b'W', 0]);
+        // skip 3 to offset 12, '!' past the end of the source
+        number(3, &mut patch);
+        patch.extend_from_slice(&[b'!', 0]);
+        let patch = finish(patch, &source, &target);
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+
+        let err = apply(&patch, b"Hello, there").unwrap_err();
+        assert!(err.starts_with("UPS patch doesn't match this ROM"), "{}", err);
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+        assert!(apply(&corrupt, &source).unwrap_err().starts_with("UPS patch is corrupt"));
+
+        // a well formed patch asking for 4GB
+        let mut huge = b"UPS1".to_vec();
+        number(source.len(), &mut huge);
+        number(1 << 32, &mut huge);
+        let huge = finish(huge, &source, &target);
+        assert!(apply(&huge, &source).unwrap_err().contains("more than the 33554432 byte limit"));
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcXYXYXYgh".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        number(source.len(), &mut patch);
+        number(target.len(), &mut patch);
+        number(0, &mut patch);
+        // source read 3: "abc"
+        number((3 - 1) << 2, &mut patch);
+        // target read 2: "XY"
+        number((2 - 1) << 2 | 1, &mut patch);
+        patch.extend_from_slice(b"XY");
+        // target copy 4 from offset 3, overlapping itself: "XYXY"
+        number((4 - 1) << 2 | 3, &mut patch);
+        number(3 << 1, &mut patch);
+        // source copy 2 from offset 6: "gh"
+        number((2 - 1) << 2 | 2, &mut patch);
+        number(6 << 1, &mut patch);
+        let patch = finish(patch, &source, &target);
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+
+        // right source, but the patch claims a different result
+        let mut wrong = patch[..patch.len() - 12].to_vec();
+        wrong = finish(wrong, &source, b"something else");
+        assert!(apply(&wrong, &source).unwrap_err().starts_with("BPS patch produced 11 bytes"));
+
+        let mut huge = b"BPS1".to_vec();
+        number(source.len(), &mut huge);
+        number(MAX_TARGET_SIZE + 1, &mut huge);
+        number(0, &mut huge);
+        let huge = finish(huge, &source, &target);
+        assert!(apply(&huge, &source).unwrap_err().starts_with("BPS patch asks for a 33554433 byte ROM"));
+
+        assert!(apply(b"garbage", &source).is_err());
+    }
+}
@@ -0,0 +1,601 @@
+// 2C02 picture processing unit.
+//
+// PPU memory map:
+// $0000-$1FFF  pattern tables (CHR ROM/RAM on the cartridge)
+// $2000-$2FFF  4 nametables backed by 2KB of VRAM, arranged by the cartridge mirroring
+// $3000-$3EFF  mirror of $2000-$2EFF
+// $3F00-$3F1F  palette RAM, mirrored up to $3FFF
+//
+// Scrolling uses the internal "loopy" registers: v (current VRAM address), t (temporary
+// address), fine x and the shared $2005/$2006 write toggle w
+
+pub mod registers;
+mod scanline;
+
+use crate::cartridge::Mirroring;
+use crate::cdl::ChrFlags;
+use crate::region::Region;
+use crate::render::frame::Frame;
+use registers::control::ControlRegister;
+use registers::mask::MaskRegister;
+use registers::status::StatusRegister;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+
+pub struct NesPPU {
+    pub chr_rom: Vec<u8>,
+    chr_is_ram: bool,
+    pub mirroring: Mirroring,
+    pub region: Region,
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 4096],
+    pub oam_addr: u8,
+    pub oam_data: [u8; 256],
+
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    w: bool,
+    internal_data_buf: u8,
+    // Last value written to a PPU register, read back from write-only registers
+    io_latch: u8,
+
+    pub scanline: u16,
+    pub cycle: u16,
+    pub frame_count: u64,
+    pub frame: Frame,
+    nmi_interrupt: bool,
+    frame_complete: bool,
+    sprite_zero_hit_dot: Option<u16>,
+    // CHR half of the code/data log, see cdl.rs
+    pub chr_log: Option<Vec<u8>>,
+}
+
+impl NesPPU {
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        NesPPU::with_region(chr_rom, mirroring, Region::Ntsc)
+    }
+
+    // Cartridges without CHR ROM get 8KB of CHR RAM
+    pub fn with_region(chr_rom: Vec<u8>, mirroring: Mirroring, region: Region) -> Self {
+        let chr_is_ram = chr_rom.is_empty();
+        let chr_rom = if chr_is_ram { vec![0; 0x2000] } else { chr_rom };
+
+        NesPPU {
+            chr_rom,
+            chr_is_ram,
+            mirroring,
+            region,
+            palette_table: [0; 32],
+            vram: [0; 4096],
+            oam_addr: 0,
+            oam_data: [0; 256],
+            ctrl: ControlRegister::new(),
+            mask: MaskRegister::new(),
+            status: StatusRegister::new(),
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            internal_data_buf: 0,
+            io_latch: 0,
+            scanline: 0,
+            cycle: 0,
+            frame_count: 0,
+            frame: Frame::new(),
+            nmi_interrupt: false,
+            frame_complete: false,
+            sprite_zero_hit_dot: None,
+            chr_log: None,
+        }
+    }
+
+    // Bytes of CHR ROM, 0 for CHR RAM
+    pub fn chr_rom_size(&self) -> usize {
+        if self.chr_is_ram {
+            0
+        } else {
+            self.chr_rom.len()
+        }
+    }
+
+    // Record an access to the pattern tables in the code/data log
+    fn log_chr(&mut self, addr: u16, flags: ChrFlags) {
+        if let Some(log) = &mut self.chr_log {
+            if let Some(entry) = log.get_mut(addr as usize) {
+                *entry |= flags.bits();
+            }
+        }
+    }
+
+    pub fn new_empty_rom() -> Self {
+        NesPPU::new(vec![0; 2048], Mirroring::Horizontal)
+    }
+
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        let mirrored_vram = addr & 0b10111111111111; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
+        let vram_index = mirrored_vram - 0x2000; // to vram vector
+        let name_table = vram_index / 0x400; // to the name table index
+        match (&self.mirroring, name_table) {
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+            (Mirroring::Horizontal, 2) => vram_index - 0x400,
+            (Mirroring::Horizontal, 1) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            _ => vram_index,
+        }
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+    fn mirror_palette_addr(addr: u16) -> usize {
+        let index = (addr & 0x1f) as usize;
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1c => index - 0x10,
+            _ => index,
+        }
+    }
+
+    // Read PPU address space without side effects
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => self.chr_rom[addr as usize],
+            0x2000..=0x3eff => self.vram[self.mirror_vram_addr(addr) as usize],
+            _ => self.palette_table[NesPPU::mirror_palette_addr(addr)],
+        }
+    }
+
+    // Write PPU address space without moving v, e.g. from a memory editor; CHR ROM stays
+    // read-only
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.write(addr, data);
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3fff;
+        match addr {
+            0..=0x1fff => {
+                if self.chr_is_ram {
+                    self.chr_rom[addr as usize] = data;
+                }
+            }
+            0x2000..=0x3eff => {
+                let index = self.mirror_vram_addr(addr) as usize;
+                self.vram[index] = data;
+            }
+            _ => self.palette_table[NesPPU::mirror_palette_addr(addr)] = data & 0x3f,
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment() as u16) & 0x7fff;
+    }
+
+    // Current VRAM address (loopy v), e.g. for showing the scroll position in a debugger
+    pub fn vram_addr(&self) -> u16 {
+        self.v
+    }
+
+    // Scroll position of the top left pixel within the 512x480 nametable space
+    pub fn scroll(&self) -> (usize, usize) {
+        let x = ((self.t & 0x001f) << 3) | self.fine_x as u16 | ((self.t & 0x0400) >> 2);
+        let y = ((((self.t >> 5) & 0x1f) << 3) | ((self.t >> 12) & 0x7)) + ((self.t & 0x0800) >> 11) * 240;
+        (x as usize, y as usize)
+    }
+
+    fn write_to_ctrl(&mut self, value: u8) {
+        let before_nmi_status = self.ctrl.generate_vblank_nmi();
+        self.ctrl.update(value);
+        self.t = (self.t & !0x0c00) | ((value as u16 & 0b11) << 10);
+
+        // enabling NMI during vblank raises it immediately
+        if !before_nmi_status && self.ctrl.generate_vblank_nmi() && self.status.is_in_vblank() {
+            self.nmi_interrupt = true;
+        }
+    }
+
+    fn write_to_scroll(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001f) | (value as u16 >> 3);
+            self.fine_x = value & 0b111;
+        } else {
+            self.t = (self.t & !0x73e0) | ((value as u16 & 0b111) << 12) | ((value as u16 >> 3) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    fn write_to_ppu_addr(&mut self, value: u8) {
+        if !self.w {
+            self.t = (self.t & 0x00ff) | ((value as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | value as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    fn write_to_data(&mut self, value: u8) {
+        self.write(self.v, value);
+        self.increment_vram_addr();
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let addr = self.v & 0x3fff;
+        self.increment_vram_addr();
+
+        match addr {
+            // reads below the palette go through the internal buffer and lag one read behind
+            0..=0x3eff => {
+                let result = self.internal_data_buf;
+                self.internal_data_buf = self.peek(addr);
+                if addr < 0x2000 {
+                    self.log_chr(addr, ChrFlags::READ);
+                }
+                result
+            }
+            // palette reads are immediate; the buffer gets the nametable byte underneath
+            _ => {
+                self.internal_data_buf = self.peek(addr - 0x1000);
+                self.peek(addr) | (self.io_latch & 0b1100_0000)
+            }
+        }
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let data = self.peek_register(0x2002);
+        self.status.reset_vblank_status();
+        self.w = false;
+        data
+    }
+
+    // CPU read of $2000-$2007
+    pub fn read_register(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            0x2002 => self.read_status(),
+            0x2004 => self.oam_data[self.oam_addr as usize],
+            0x2007 => self.read_data(),
+            // write-only registers
+            _ => self.io_latch,
+        };
+        self.io_latch = data;
+        data
+    }
+
+    // Value a CPU read of $2000-$2007 would return, without side effects
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        match addr {
+            0x2002 => (self.status.snapshot() & 0b1110_0000) | (self.io_latch & 0b0001_1111),
+            0x2004 => self.oam_data[self.oam_addr as usize],
+            0x2007 if self.v & 0x3fff >= 0x3f00 => self.peek(self.v),
+            0x2007 => self.internal_data_buf,
+            _ => self.io_latch,
+        }
+    }
+
+    // CPU write of $2000-$2007
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        self.io_latch = data;
+        match addr {
+            0x2000 => self.write_to_ctrl(data),
+            0x2001 => self.mask.update(data),
+            0x2003 => self.oam_addr = data,
+            0x2004 => {
+                self.oam_data[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            0x2005 => self.write_to_scroll(data),
+            0x2006 => self.write_to_ppu_addr(data),
+            0x2007 => self.write_to_data(data),
+            // $2002 is read-only
+            _ => {}
+        }
+    }
+
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+        for x in data.iter() {
+            self.oam_data[self.oam_addr as usize] = *x;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    // Coarse Y / fine Y increment at dot 256, wrapping into the next nametable
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03e0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03e0) | (coarse_y << 5);
+    }
+
+    fn copy_horizontal_bits(&mut self) {
+        self.v = (self.v & !0x041f) | (self.t & 0x041f);
+    }
+
+    fn copy_vertical_bits(&mut self) {
+        self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+    }
+
+    fn update_scroll_counters(&mut self) {
+        match self.cycle {
+            256 => self.increment_y(),
+            257 => self.copy_horizontal_bits(),
+            _ => {}
+        }
+    }
+
+    fn tick_dot(&mut self) {
+        let rendering = self.mask.rendering_enabled();
+        let scanlines = self.region.scanlines_per_frame();
+        let pre_render = scanlines - 1;
+
+        match self.scanline {
+            0..=239 => {
+                if self.cycle == 1 {
+                    self.render_scanline();
+                }
+                if Some(self.cycle) == self.sprite_zero_hit_dot {
+                    self.status.set_sprite_zero_hit(true);
+                }
+                if rendering {
+                    self.update_scroll_counters();
+                }
+            }
+            line if line == self.region.vblank_scanline() && self.cycle == 1 => {
+                self.status.set_vblank_status(true);
+                self.frame_complete = true;
+                if self.ctrl.generate_vblank_nmi() {
+                    self.nmi_interrupt = true;
+                }
+            }
+            line if line == pre_render => {
+                if self.cycle == 1 {
+                    self.status.reset_vblank_status();
+                    self.status.set_sprite_zero_hit(false);
+                    self.status.set_sprite_overflow(false);
+                }
+                if rendering {
+                    self.update_scroll_counters();
+                    if (280..=304).contains(&self.cycle) {
+                        self.copy_vertical_bits();
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.cycle += 1;
+        // the NTSC pre-render line of odd frames is one dot shorter while rendering
+        if self.scanline == pre_render
+            && self.region.skips_odd_frame_dot()
+            && self.cycle == DOTS_PER_SCANLINE - 1
+            && self.frame_count % 2 == 1
+            && rendering
+        {
+            self.cycle = DOTS_PER_SCANLINE;
+        }
+
+        if self.cycle >= DOTS_PER_SCANLINE {
+            self.cycle = 0;
+            self.scanline += 1;
+            if self.scanline >= scanlines {
+                self.scanline = 0;
+                self.frame_count += 1;
+            }
+        }
+    }
+
+    // Advance by `dots` PPU cycles (3 per CPU cycle on NTSC, 3.2 on PAL)
+    pub fn tick(&mut self, dots: usize) {
+        for _ in 0..dots {
+            self.tick_dot();
+        }
+    }
+
+    // True once for every vblank NMI raised since the last poll
+    pub fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_interrupt)
+    }
+
+    // True once `frame` holds a completed picture, at the start of vblank
+    pub fn poll_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_complete)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+        ppu.write_register(0x2007, 0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+
+        ppu.read_register(0x2007); // load into buffer
+        assert_eq!(ppu.vram_addr(), 0x2306);
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0xff);
+
+        ppu.read_register(0x2007); // load into buffer
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+        assert_eq!(ppu.read_register(0x2007), 0x77);
+        assert_eq!(ppu.read_register(0x2007), 0x88);
+    }
+
+    // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 B ] [0x2C00 b ]
+    #[test]
+    fn test_vram_horizontal_mirror() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_register(0x2006, 0x24);
+        ppu.write_register(0x2006, 0x05);
+        ppu.write_register(0x2007, 0x66); // write to a
+
+        ppu.write_register(0x2006, 0x28);
+        ppu.write_register(0x2006, 0x05);
+        ppu.write_register(0x2007, 0x77); // write to B
+
+        ppu.write_register(0x2006, 0x20);
+        ppu.write_register(0x2006, 0x05);
+        ppu.read_register(0x2007); // load into buffer
+        assert_eq!(ppu.read_register(0x2007), 0x66); // read from A
+
+        ppu.write_register(0x2006, 0x2C);
+        ppu.write_register(0x2006, 0x05);
+        ppu.read_register(0x2007); // load into buffer
+        assert_eq!(ppu.read_register(0x2007), 0x77); // read from b
+    }
+
+    #[test]
+    fn test_palette_mirrors_and_immediate_reads() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x10);
+        ppu.write_register(0x2007, 0x21);
+
+        assert_eq!(ppu.palette_table[0], 0x21);
+
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x00);
+        assert_eq!(ppu.read_register(0x2007) & 0x3f, 0x21);
+    }
+
+    #[test]
+    fn test_read_status_resets_latch_and_vblank() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_register(0x2006, 0x21);
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+
+        ppu.read_register(0x2007); // load into buffer
+        assert_ne!(ppu.read_register(0x2007), 0x66);
+
+        ppu.status.set_vblank_status(true);
+        ppu.read_register(0x2002);
+        assert!(!ppu.status.is_in_vblank());
+
+        ppu.write_register(0x2006, 0x23);
+        ppu.write_register(0x2006, 0x05);
+
+        ppu.read_register(0x2007); // load into buffer
+        assert_eq!(ppu.read_register(0x2007), 0x66);
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_register(0x2003, 0x10);
+        ppu.write_register(0x2004, 0x66);
+        ppu.write_register(0x2004, 0x77);
+
+        ppu.write_register(0x2003, 0x10);
+        assert_eq!(ppu.read_register(0x2004), 0x66);
+
+        ppu.write_register(0x2003, 0x11);
+        assert_eq!(ppu.read_register(0x2004), 0x77);
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi_once_per_frame() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_register(0x2000, 0b1000_0000);
+
+        let vblank = Region::Ntsc.vblank_scanline() as usize;
+        ppu.tick(vblank * DOTS_PER_SCANLINE as usize + 1);
+        assert!(!ppu.poll_nmi());
+        ppu.tick(1);
+        assert!(ppu.status.is_in_vblank());
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+        assert!(ppu.poll_frame());
+
+        ppu.tick((261 - vblank) * DOTS_PER_SCANLINE as usize);
+        assert!(!ppu.status.is_in_vblank());
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let mut chr = vec![0; 0x2000];
+        // tile 1: solid colour 1
+        for row in 0..8 {
+            chr[16 + row] = 0xff;
+        }
+        let mut ppu = NesPPU::new(chr, Mirroring::Vertical);
+        // nametable filled with tile 1, sprite 0 at (16, 16)
+        for i in 0..960 {
+            ppu.vram[i] = 1;
+        }
+        ppu.oam_data[0..4].copy_from_slice(&[15, 1, 0, 16]);
+        ppu.write_register(0x2001, 0b0001_1110);
+
+        ppu.tick(16 * DOTS_PER_SCANLINE as usize);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        ppu.tick(DOTS_PER_SCANLINE as usize);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+    }
+
+    #[test]
+    fn test_frame_length_per_region() {
+        let regions = [(Region::Ntsc, 262, 241), (Region::Pal, 312, 241), (Region::Dendy, 312, 291)];
+        for (region, scanlines, vblank) in regions {
+            let mut ppu = NesPPU::with_region(vec![0; 0x2000], Mirroring::Horizontal, region);
+            ppu.tick(vblank * DOTS_PER_SCANLINE as usize + 1);
+            assert!(!ppu.status.is_in_vblank(), "{}", region);
+            ppu.tick(1);
+            assert!(ppu.status.is_in_vblank(), "{}", region);
+
+            ppu.tick((scanlines - vblank - 1) * DOTS_PER_SCANLINE as usize);
+            assert!(!ppu.status.is_in_vblank(), "{}", region);
+            assert_eq!(ppu.frame_count, 0);
+            ppu.tick(DOTS_PER_SCANLINE as usize - 2);
+            assert_eq!((ppu.scanline, ppu.cycle, ppu.frame_count), (0, 0, 1));
+        }
+    }
+}
@@ -0,0 +1,69 @@
+bitflags! {
+    // 7  bit  0
+    // ---- ----
+    // BGRs bMmG
+    // |||| ||||
+    // |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+    // |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+    // |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+    // |||| +---- 1: Show background
+    // |||+------ 1: Show sprites
+    // ||+------- Emphasize red
+    // |+-------- Emphasize green
+    // +--------- Emphasize blue
+    pub struct MaskRegister: u8 {
+        const GREYSCALE               = 0b00000001;
+        const LEFTMOST_8PXL_BACKGROUND = 0b00000010;
+        const LEFTMOST_8PXL_SPRITE    = 0b00000100;
+        const SHOW_BACKGROUND         = 0b00001000;
+        const SHOW_SPRITES            = 0b00010000;
+        const EMPHASISE_RED           = 0b00100000;
+        const EMPHASISE_GREEN         = 0b01000000;
+        const EMPHASISE_BLUE          = 0b10000000;
+    }
+}
+
+impl MaskRegister {
+    pub fn new() -> Self {
+        MaskRegister::from_bits_truncate(0b00000000)
+    }
+
+    pub fn is_grayscale(&self) -> bool {
+        self.contains(MaskRegister::GREYSCALE)
+    }
+
+    pub fn leftmost_8pxl_background(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND)
+    }
+
+    pub fn leftmost_8pxl_sprite(&self) -> bool {
+        self.contains(MaskRegister::LEFTMOST_8PXL_SPRITE)
+    }
+
+    pub fn show_background(&self) -> bool {
+        self.contains(MaskRegister::SHOW_BACKGROUND)
+    }
+
+    pub fn show_sprites(&self) -> bool {
+        self.contains(MaskRegister::SHOW_SPRITES)
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.show_background() || self.show_sprites()
+    }
+
+    // Emphasis bits R, G, B as bits 0-2
+    pub fn emphasis(&self) -> u8 {
+        self.bits >> 5
+    }
+
+    pub fn update(&mut self, data: u8) {
+        self.bits = data;
+    }
+}
+
+impl Default for MaskRegister {
+    fn default() -> Self {
+        MaskRegister::new()
+    }
+}
@@ -0,0 +1,3 @@
+pub mod control;
+pub mod mask;
+pub mod status;
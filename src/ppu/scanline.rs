@@ -0,0 +1,175 @@
+// Scanline renderer: composes one line of background and sprites into the frame at
+// the start of each visible scanline, using the scroll position held in loopy v
+
+use crate::cdl::ChrFlags;
+use crate::ppu::NesPPU;
+use crate::render::frame::Frame;
+
+const MAX_SPRITES_PER_LINE: usize = 8;
+
+// Pixels of one line: 0 is transparent, otherwise palette (0-3) << 2 | colour (1-3)
+struct SpriteLine {
+    pixels: [u8; Frame::WIDTH],
+    behind_background: [bool; Frame::WIDTH],
+    sprite_zero: [bool; Frame::WIDTH],
+}
+
+impl NesPPU {
+    // Pattern table byte fetched for rendering
+    fn fetch_pattern(&mut self, addr: u16) -> u8 {
+        if self.chr_log.is_some() {
+            self.log_chr(addr, ChrFlags::DRAWN);
+        }
+        self.peek(addr)
+    }
+
+    fn background_line(&mut self, line: &mut [u8; Frame::WIDTH]) {
+        let mut v = self.v;
+        let fine_y = (v >> 12) & 0b111;
+        let bank = self.ctrl.bknd_pattern_addr();
+        let fine_x = self.fine_x as usize;
+
+        // 33 tiles cover the line when it is scrolled by fine x
+        for tile in 0..33 {
+            let tile_idx = self.peek(0x2000 | (v & 0x0fff)) as u16;
+            let attr = self.peek(0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+            let shift = ((v >> 4) & 0b100) | (v & 0b10);
+            let palette = (attr >> shift) & 0b11;
+
+            let lo = self.fetch_pattern(bank + tile_idx * 16 + fine_y);
+            let hi = self.fetch_pattern(bank + tile_idx * 16 + fine_y + 8);
+
+            for bit in 0..8 {
+                let px = tile * 8 + bit;
+                if px < fine_x || px - fine_x >= Frame::WIDTH {
+                    continue;
+                }
+                let value = ((lo >> (7 - bit)) & 1) | (((hi >> (7 - bit)) & 1) << 1);
+                if value != 0 {
+                    line[px - fine_x] = palette << 2 | value;
+                }
+            }
+
+            // coarse X increment, wrapping into the horizontally adjacent nametable
+            if v & 0x001f == 31 {
+                v &= !0x001f;
+                v ^= 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+    }
+
+    // Sprites are drawn one line below their OAM Y coordinate; lower OAM indexes win
+    fn sprite_line(&mut self, line: &mut SpriteLine) {
+        let height = self.ctrl.sprite_size() as i32;
+        let y = self.scanline as i32;
+        let mut count = 0;
+
+        for i in 0..64 {
+            let oam = &self.oam_data[i * 4..i * 4 + 4];
+            let mut row = y - (oam[0] as i32 + 1);
+            if row < 0 || row >= height {
+                continue;
+            }
+
+            count += 1;
+            if count > MAX_SPRITES_PER_LINE {
+                self.status.set_sprite_overflow(true);
+                break;
+            }
+
+            let (tile, attr, x) = (oam[1] as u16, oam[2], oam[3] as usize);
+            let flip_vertical = attr & 0x80 != 0;
+            let flip_horizontal = attr & 0x40 != 0;
+            let behind_background = attr & 0x20 != 0;
+            let palette = attr & 0b11;
+
+            if flip_vertical {
+                row = height - 1 - row;
+            }
+            let tile_addr = if height == 16 {
+                let bank = (tile & 1) * 0x1000;
+                let top = tile & 0xfe;
+                if row >= 8 {
+                    row -= 8;
+                    bank + (top + 1) * 16
+                } else {
+                    bank + top * 16
+                }
+            } else {
+                self.ctrl.sprt_pattern_addr() + tile * 16
+            };
+
+            let lo = self.fetch_pattern(tile_addr + row as u16);
+            let hi = self.fetch_pattern(tile_addr + row as u16 + 8);
+
+            for bit in 0..8 {
+                let px = x + bit;
+                if px >= Frame::WIDTH {
+                    break;
+                }
+                let shift = if flip_horizontal { bit } else { 7 - bit };
+                let value = ((lo >> shift) & 1) | (((hi >> shift) & 1) << 1);
+                if value == 0 || line.pixels[px] != 0 {
+                    continue;
+                }
+                line.pixels[px] = palette << 2 | value;
+                line.behind_background[px] = behind_background;
+                line.sprite_zero[px] = i == 0;
+            }
+        }
+    }
+
+    pub(super) fn render_scanline(&mut self) {
+        let y = self.scanline as usize;
+        let emphasis = (self.mask.emphasis() as u16) << 6;
+        let grey_mask = if self.mask.is_grayscale() { 0x30 } else { 0x3f };
+        self.sprite_zero_hit_dot = None;
+
+        if !self.mask.rendering_enabled() {
+            // with rendering off the PPU outputs the backdrop, or the palette entry v points at
+            let addr = if self.v & 0x3f00 == 0x3f00 { self.v } else { 0x3f00 };
+            let color = (self.peek(addr) & grey_mask) as u16 | emphasis;
+            for x in 0..Frame::WIDTH {
+                self.frame.set_pixel(x, y, color);
+            }
+            return;
+        }
+
+        let mut background = [0u8; Frame::WIDTH];
+        if self.mask.show_background() {
+            self.background_line(&mut background);
+        }
+
+        let mut sprites = SpriteLine {
+            pixels: [0; Frame::WIDTH],
+            behind_background: [false; Frame::WIDTH],
+            sprite_zero: [false; Frame::WIDTH],
+        };
+        if self.mask.show_sprites() {
+            self.sprite_line(&mut sprites);
+        }
+
+        for (x, &bg) in background.iter().enumerate() {
+            let left_edge = x < 8;
+            let bg = if left_edge && !self.mask.leftmost_8pxl_background() { 0 } else { bg };
+            let sprite = if left_edge && !self.mask.leftmost_8pxl_sprite() { 0 } else { sprites.pixels[x] };
+
+            let palette_addr = match (bg != 0, sprite != 0) {
+                (false, false) => 0,
+                (false, true) => 0x10 | sprite,
+                (true, false) => bg,
+                (true, true) => {
+                    if sprites.sprite_zero[x] && x != 255 && self.sprite_zero_hit_dot.is_none() {
+                        self.sprite_zero_hit_dot = Some(x as u16 + 1);
+                    }
+                    if sprites.behind_background[x] { bg } else { 0x10 | sprite }
+                }
+            };
+
+            let color = self.palette_table[NesPPU::mirror_palette_addr(palette_addr as u16)] & grey_mask;
+            self.frame.set_pixel(x, y, color as u16 | emphasis);
+        }
+    }
+}
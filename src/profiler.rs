@@ -0,0 +1,353 @@
+// Execution profiler: attributes CPU cycles to subroutines by following JSR/RTS and
+// interrupt entry and exit.
+//
+// Every instruction's cycles, with the interrupt entry and DMA stall it caused, count as
+// exclusive to the routine on top of the call stack and inclusive to each routine on it
+// (once, however deep it recurses). A call ends when RTS/RTI brings the stack pointer
+// back to where it was before the call, so pushing an address and returning through it,
+// as jump tables do, stays inside the routine. The bottom of the stack is wherever the
+// profiler started, usually the reset handler.
+//
+// report() prints per-frame averages as a text table and collapsed_stacks() writes the
+// "outer;inner;leaf cycles" lines flamegraph.pl and inferno read
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::cpu::{Interrupt, RunResult, StepResult, CPU};
+use crate::disasm::SymbolTable;
+use crate::opcodes;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+// Cycles the CPU takes to enter an interrupt handler
+const INTERRUPT_CYCLES: u64 = 7;
+
+// An entry of the call stack; its routine is in Profiler::path
+struct Call {
+    // stack pointer before the call pushed anything, None for the bottom of the stack
+    sp: Option<u8>,
+    interrupt: Option<Interrupt>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstructionStats {
+    pub mnemonic: &'static str,
+    pub count: u64,
+    pub cycles: u64,
+}
+
+pub struct Profiler {
+    stack: Vec<Call>,
+    // routines of `stack`, the key into `stacks`
+    path: Vec<u16>,
+    pub routines: HashMap<u16, RoutineStats>,
+    pub instructions: HashMap<u16, InstructionStats>,
+    // cycles per distinct call stack
+    stacks: HashMap<Vec<u16>, u64>,
+    pub frames: u64,
+    pub cycles: u64,
+    // cycles spent with an NMI handler on the stack, and in vblank
+    pub nmi_cycles: u64,
+    pub vblank_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            stack: Vec::new(),
+            path: Vec::new(),
+            routines: HashMap::new(),
+            instructions: HashMap::new(),
+            stacks: HashMap::new(),
+            frames: 0,
+            cycles: 0,
+            nmi_cycles: 0,
+            vblank_cycles: 0,
+        }
+    }
+
+    fn enter(&mut self, routine: u16, sp: Option<u8>, interrupt: Option<Interrupt>) {
+        self.stack.push(Call { sp, interrupt });
+        self.path.push(routine);
+        self.routines.entry(routine).or_default().calls += 1;
+    }
+
+    fn leave(&mut self, sp: u8) {
+        while let Some(Call { sp: Some(call_sp), .. }) = self.stack.last() {
+            if *call_sp > sp {
+                break;
+            }
+            self.stack.pop();
+            self.path.pop();
+        }
+    }
+
+    // Execute one instruction, recording where its cycles went
+    pub fn step<B: Bus>(&mut self, cpu: &mut CPU<B>) -> StepResult {
+        if self.stack.is_empty() {
+            self.enter(cpu.program_counter, None, None);
+        }
+        let sp = cpu.stack_pointer;
+        let in_vblank = cpu.bus.in_vblank();
+
+        let result = cpu.step();
+        if let Some(interrupt) = result.interrupt {
+            self.enter(result.address, Some(sp), Some(interrupt));
+        }
+
+        let cycles = result.cycles as u64;
+        self.cycles += cycles;
+        if in_vblank {
+            self.vblank_cycles += cycles;
+        }
+        if self.stack.iter().any(|call| call.interrupt == Some(Interrupt::Nmi)) {
+            self.nmi_cycles += cycles;
+        }
+        self.routines.entry(*self.path.last().unwrap()).or_default().exclusive += cycles;
+        for (i, &routine) in self.path.iter().enumerate() {
+            if !self.path[..i].contains(&routine) {
+                self.routines.entry(routine).or_default().inclusive += cycles;
+            }
+        }
+        match self.stacks.get_mut(self.path.as_slice()) {
+            Some(total) => *total += cycles,
+            None => {
+                self.stacks.insert(self.path.clone(), cycles);
+            }
+        }
+
+        if let Some(opcode) = opcodes::lookup(cpu.variant, result.opcode) {
+            let stats = self.instructions.entry(result.address).or_insert(InstructionStats {
+                mnemonic: opcode.mnemonic,
+                count: 0,
+                cycles: 0,
+            });
+            stats.count += 1;
+            stats.cycles += if result.interrupt.is_some() { cycles.saturating_sub(INTERRUPT_CYCLES) } else { cycles };
+        }
+
+        match result.opcode {
+            // the return address is on the stack now
+            JSR => self.enter(cpu.program_counter, Some(cpu.stack_pointer.wrapping_add(2)), None),
+            RTS | RTI => self.leave(cpu.stack_pointer),
+            _ => {}
+        }
+        result
+    }
+
+    // CPU::run_frame() with every instruction profiled
+    pub fn run_frame<B: Bus>(&mut self, cpu: &mut CPU<B>) -> RunResult {
+        cpu.write_freezes();
+        let start = cpu.cycles;
+        let halt = loop {
+            let halt = self.step(cpu).halt;
+            if halt.is_some() || cpu.bus.poll_frame() {
+                break halt;
+            }
+        };
+        self.frames += 1;
+        RunResult {
+            cycles: cpu.cycles - start,
+            halt,
+        }
+    }
+
+    // Routines by exclusive cycles, then the `top` hottest instructions
+    pub fn report(&self, symbols: Option<&SymbolTable>, top: usize) -> String {
+        let frames = self.frames.max(1) as f64;
+        let per_frame = |cycles: u64| cycles as f64 / frames;
+        let percent = |cycles: u64| if self.cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / self.cycles as f64 };
+
+        let mut out = String::new();
+        let _ = writeln!(out, "{} frames, {:.1} cycles per frame", self.frames, per_frame(self.cycles));
+        let _ = writeln!(
+            out,
+            "NMI handlers {:.1} cycles per frame ({:.1}%), vblank {:.1} ({:.1}%)",
+            per_frame(self.nmi_cycles),
+            percent(self.nmi_cycles),
+            per_frame(self.vblank_cycles),
+            percent(self.vblank_cycles)
+        );
+
+        let mut routines: Vec<_> = self.routines.iter().collect();
+        routines.sort_by_key(|&(&addr, stats)| (std::cmp::Reverse(stats.exclusive), addr));
+        let _ = writeln!(
+            out,
+            "\n{:<24} {:>11} {:>15} {:>6} {:>15} {:>6}",
+            "routine", "calls/frame", "inclusive/frame", "%", "exclusive/frame", "%"
+        );
+        for (&addr, stats) in routines {
+            let _ = writeln!(
+                out,
+                "{:<24} {:>11.2} {:>15.1} {:>6.1} {:>15.1} {:>6.1}",
+                routine_name(addr, symbols),
+                stats.calls as f64 / frames,
+                per_frame(stats.inclusive),
+                percent(stats.inclusive),
+                per_frame(stats.exclusive),
+                percent(stats.exclusive)
+            );
+        }
+
+        let mut instructions: Vec<_> = self.instructions.iter().collect();
+        instructions.sort_by_key(|&(&addr, stats)| (std::cmp::Reverse(stats.cycles), addr));
+        let _ = writeln!(
+            out,
+            "\n{:<24} {:<4} {:>11} {:>12} {:>6}",
+            "instruction", "", "count/frame", "cycles/frame", "%"
+        );
+        for (&addr, stats) in instructions.into_iter().take(top) {
+            let location = match symbols.and_then(|symbols| symbols.label(addr)) {
+                Some(label) => format!("${:04X} {}", addr, label),
+                None => format!("${:04X}", addr),
+            };
+            let _ = writeln!(
+                out,
+                "{:<24} {:<4} {:>11.2} {:>12.1} {:>6.1}",
+                location,
+                stats.mnemonic,
+                stats.count as f64 / frames,
+                per_frame(stats.cycles),
+                percent(stats.cycles)
+            );
+        }
+        out
+    }
+
+    // One "outer;inner;leaf cycles" line per distinct call stack, for flame graphs
+    pub fn collapsed_stacks(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(path, cycles)| {
+                let names: Vec<String> = path.iter().map(|&addr| routine_name(addr, symbols)).collect();
+                format!("{} {}", names.join(";"), cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+fn routine_name(addr: u16, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|symbols| symbols.label(addr)) {
+        Some(label) => label.to_string(),
+        None => format!("${:04X}", addr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::{HaltReason, Mem};
+
+    fn run(cpu: &mut CPU, profiler: &mut Profiler) -> HaltReason {
+        loop {
+            if let Some(halt) = profiler.step(cpu).halt {
+                return halt;
+            }
+        }
+    }
+
+    #[test]
+    fn test_calls_and_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(crate::asm::program(
+            "
+            main:   jsr outer   ; $0600
+                    jsr leaf
+                    brk
+            outer:  jsr leaf    ; $0607
+                    ldx #3
+            @loop:  dex
+                    bne @loop
+                    rts
+            leaf:   nop         ; $0610
+                    rts
+            ",
+        ));
+        cpu.reset();
+        let mut profiler = Profiler::new();
+        assert_eq!(run(&mut cpu, &mut profiler), HaltReason::Brk);
+
+        let leaf = profiler.routines[&0x0610];
+        assert_eq!(leaf, RoutineStats { calls: 2, inclusive: 16, exclusive: 16 });
+        // JSR 6, LDX 2, 3 DEX 6, BNE taken twice 3 + 3, not taken 2, RTS 6
+        let outer = profiler.routines[&0x0607];
+        assert_eq!(outer, RoutineStats { calls: 1, inclusive: 36, exclusive: 28 });
+        let main = profiler.routines[&0x0600];
+        assert_eq!(main.inclusive, profiler.cycles);
+        assert_eq!(main.exclusive, profiler.cycles - 36 - 8);
+
+        assert_eq!(profiler.instructions[&0x060c].count, 3);
+        assert_eq!(profiler.instructions[&0x060c].mnemonic, "DEX");
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0600, "main");
+        symbols.insert(0x0607, "outer");
+        symbols.insert(0x0610, "leaf");
+        let stacks = profiler.collapsed_stacks(Some(&symbols));
+        let lines: Vec<&str> = stacks.lines().collect();
+        assert_eq!(lines[1..], ["main;leaf 8", "main;outer 28", "main;outer;leaf 8"]);
+        assert!(lines[0].starts_with("main "));
+
+        let report = profiler.report(Some(&symbols), 20);
+        assert!(report.contains("\nouter "));
+        assert!(report.contains("$060C") && report.contains("DEX"));
+    }
+
+    #[test]
+    fn test_interrupts_and_jump_tables() {
+        let mut cpu = CPU::new();
+        cpu.load(crate::asm::program(
+            "
+            main:   jsr table   ; $0600
+                    brk
+            table:  lda #>(target - 1)  ; $0604
+                    pha
+                    lda #<(target - 1)
+                    pha
+                    rts
+            target: nop         ; $060B
+                    rts
+            nmi:    inc $10     ; $060D
+                    rti
+            ",
+        ));
+        cpu.mem_write_u16(0xfffa, 0x060d);
+        cpu.reset();
+        let mut profiler = Profiler::new();
+        profiler.step(&mut cpu);
+        cpu.trigger_nmi();
+        let result = profiler.step(&mut cpu);
+        assert_eq!(result.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(run(&mut cpu, &mut profiler), HaltReason::Brk);
+
+        // the RTS into `target` stays inside `table`
+        assert!(!profiler.routines.contains_key(&0x060b));
+        let table = profiler.routines[&0x0604];
+        assert_eq!(table.exclusive, 2 + 3 + 2 + 3 + 6 + 2 + 6);
+
+        // INC zp 5 + RTI 6, plus the 7 cycles of entering the handler
+        let nmi = profiler.routines[&0x060d];
+        assert_eq!((nmi.calls, nmi.inclusive), (1, 18));
+        assert_eq!(profiler.nmi_cycles, 18);
+        assert_eq!(profiler.instructions[&0x060d].cycles, 5);
+    }
+}
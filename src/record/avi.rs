@@ -0,0 +1,309 @@
+// Uncompressed AVI 1.0 with a 24-bit RGB video stream and a 16-bit mono PCM audio
+// stream. Frame and sample counts and chunk sizes are patched in by finish(), so the
+// writer needs a seekable output. AVI 1.0 is limited to 4GB (about 6 hours of video at
+// 256x240), older players may stop at 1GB
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::record::wav;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+struct IndexEntry {
+    id: [u8; 4],
+    offset: u32,
+    size: u32,
+}
+
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    width: usize,
+    height: usize,
+    frames: u32,
+    samples: u32,
+    // file positions of the fields patched in by finish()
+    total_frames_pos: u64,
+    video_length_pos: u64,
+    audio_length_pos: u64,
+    movi_pos: u64,
+    // offset of the next chunk from the "movi" fourcc, as idx1 counts them
+    movi_offset: u32,
+    index: Vec<IndexEntry>,
+}
+
+fn u16le(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u32le(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+// Start a chunk and return the position of its size field
+fn begin_chunk(out: &mut Vec<u8>, id: &[u8; 4]) -> usize {
+    out.extend_from_slice(id);
+    u32le(out, 0);
+    out.len() - 4
+}
+
+fn begin_list(out: &mut Vec<u8>, kind: &[u8; 4]) -> usize {
+    let pos = begin_chunk(out, b"LIST");
+    out.extend_from_slice(kind);
+    pos
+}
+
+fn end_chunk(out: &mut [u8], size_pos: usize) {
+    let size = (out.len() - size_pos - 4) as u32;
+    out[size_pos..size_pos + 4].copy_from_slice(&size.to_le_bytes());
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    // `frame_rate` is a fraction (numerator, denominator) of frames per second
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        frame_rate: (u32, u32),
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        let frame_size = (width * height * 3) as u32;
+        let (rate, scale) = frame_rate;
+        let mut h = Vec::new();
+
+        h.extend_from_slice(b"RIFF");
+        u32le(&mut h, 0);
+        h.extend_from_slice(b"AVI ");
+
+        let hdrl = begin_list(&mut h, b"hdrl");
+        let avih = begin_chunk(&mut h, b"avih");
+        u32le(&mut h, (1_000_000u64 * scale as u64 / rate as u64) as u32); // microseconds per frame
+        u32le(&mut h, (frame_size as u64 * rate as u64 / scale as u64) as u32 + sample_rate * 2);
+        u32le(&mut h, 0); // padding granularity
+        u32le(&mut h, AVIF_HASINDEX);
+        let total_frames_pos = h.len();
+        u32le(&mut h, 0); // total frames
+        u32le(&mut h, 0); // initial frames
+        u32le(&mut h, 2); // streams
+        u32le(&mut h, frame_size); // suggested buffer size
+        u32le(&mut h, width as u32);
+        u32le(&mut h, height as u32);
+        h.extend_from_slice(&[0; 16]);
+        end_chunk(&mut h, avih);
+
+        let strl = begin_list(&mut h, b"strl");
+        let strh = begin_chunk(&mut h, b"strh");
+        h.extend_from_slice(b"vidsDIB ");
+        u32le(&mut h, 0); // flags
+        u16le(&mut h, 0); // priority
+        u16le(&mut h, 0); // language
+        u32le(&mut h, 0); // initial frames
+        u32le(&mut h, scale);
+        u32le(&mut h, rate);
+        u32le(&mut h, 0); // start
+        let video_length_pos = h.len();
+        u32le(&mut h, 0); // length in frames
+        u32le(&mut h, frame_size);
+        u32le(&mut h, u32::MAX); // default quality
+        u32le(&mut h, 0); // sample size, 0 = varies per chunk
+        for value in [0, 0, width as u16, height as u16] {
+            u16le(&mut h, value);
+        }
+        end_chunk(&mut h, strh);
+        // BITMAPINFOHEADER, a positive height means rows are stored bottom up
+        let strf = begin_chunk(&mut h, b"strf");
+        u32le(&mut h, 40);
+        u32le(&mut h, width as u32);
+        u32le(&mut h, height as u32);
+        u16le(&mut h, 1); // planes
+        u16le(&mut h, 24); // bits per pixel
+        u32le(&mut h, 0); // BI_RGB
+        u32le(&mut h, frame_size);
+        h.extend_from_slice(&[0; 16]);
+        end_chunk(&mut h, strf);
+        end_chunk(&mut h, strl);
+
+        let strl = begin_list(&mut h, b"strl");
+        let strh = begin_chunk(&mut h, b"strh");
+        h.extend_from_slice(b"auds");
+        u32le(&mut h, 0); // handler
+        u32le(&mut h, 0); // flags
+        u16le(&mut h, 0); // priority
+        u16le(&mut h, 0); // language
+        u32le(&mut h, 0); // initial frames
+        u32le(&mut h, 1); // scale
+        u32le(&mut h, sample_rate); // rate
+        u32le(&mut h, 0); // start
+        let audio_length_pos = h.len();
+        u32le(&mut h, 0); // length in samples
+        u32le(&mut h, sample_rate * 2); // suggested buffer size
+        u32le(&mut h, u32::MAX); // default quality
+        u32le(&mut h, 2); // sample size
+        h.extend_from_slice(&[0; 8]);
+        end_chunk(&mut h, strh);
+        // WAVEFORMATEX for 16-bit mono PCM
+        let strf = begin_chunk(&mut h, b"strf");
+        u16le(&mut h, 1);
+        u16le(&mut h, 1);
+        u32le(&mut h, sample_rate);
+        u32le(&mut h, sample_rate * 2);
+        u16le(&mut h, 2);
+        u16le(&mut h, 16);
+        u16le(&mut h, 0);
+        end_chunk(&mut h, strf);
+        end_chunk(&mut h, strl);
+        end_chunk(&mut h, hdrl);
+
+        let movi_pos = begin_list(&mut h, b"movi");
+        out.write_all(&h)?;
+
+        Ok(AviWriter {
+            out,
+            width,
+            height,
+            frames: 0,
+            samples: 0,
+            total_frames_pos: total_frames_pos as u64,
+            video_length_pos: video_length_pos as u64,
+            audio_length_pos: audio_length_pos as u64,
+            movi_pos: movi_pos as u64,
+            movi_offset: 4,
+            index: Vec::new(),
+        })
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], data: &[u8]) -> io::Result<()> {
+        let size = data.len() as u32;
+        let padded = size + (size & 1);
+        if (self.movi_pos + self.movi_offset as u64 + 8 + padded as u64 + (self.index.len() as u64 + 1) * 16)
+            >= u32::MAX as u64
+        {
+            return Err(io::Error::other("AVI file size limit reached"));
+        }
+
+        self.out.write_all(&id)?;
+        self.out.write_all(&size.to_le_bytes())?;
+        self.out.write_all(data)?;
+        if size & 1 == 1 {
+            self.out.write_all(&[0])?;
+        }
+
+        self.index.push(IndexEntry {
+            id,
+            offset: self.movi_offset,
+            size,
+        });
+        self.movi_offset += 8 + padded;
+        Ok(())
+    }
+
+    // `rgb` holds 3 bytes per pixel, rows top to bottom
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), self.width * self.height * 3, "RGB data doesn't match the video size");
+
+        // DIBs are BGR, bottom row first. 24-bit rows must be padded to 4 bytes
+        let stride = (self.width * 3 + 3) & !3;
+        let mut data = Vec::with_capacity(stride * self.height);
+        for row in rgb.chunks(self.width * 3).rev() {
+            for pixel in row.chunks(3) {
+                data.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+            data.resize(data.len() + stride - self.width * 3, 0);
+        }
+
+        self.write_chunk(*b"00db", &data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            data.extend_from_slice(&wav::to_pcm(sample).to_le_bytes());
+        }
+        self.write_chunk(*b"01wb", &data)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut idx1 = Vec::with_capacity(8 + self.index.len() * 16);
+        idx1.extend_from_slice(b"idx1");
+        u32le(&mut idx1, (self.index.len() * 16) as u32);
+        for entry in &self.index {
+            idx1.extend_from_slice(&entry.id);
+            u32le(&mut idx1, AVIIF_KEYFRAME);
+            u32le(&mut idx1, entry.offset);
+            u32le(&mut idx1, entry.size);
+        }
+        self.out.write_all(&idx1)?;
+
+        let movi_end = self.movi_pos + 4 + self.movi_offset as u64;
+        let file_end = movi_end + idx1.len() as u64;
+        let patches = [
+            (4, (file_end - 8) as u32),
+            (self.total_frames_pos, self.frames),
+            (self.video_length_pos, self.frames),
+            (self.audio_length_pos, self.samples),
+            (self.movi_pos, (movi_end - self.movi_pos - 4) as u32),
+        ];
+        for (pos, value) in patches {
+            self.out.seek(SeekFrom::Start(pos))?;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::Start(file_end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_u32(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    }
+
+    fn find(data: &[u8], id: &[u8]) -> usize {
+        data.windows(id.len()).position(|window| window == id).unwrap()
+    }
+
+    #[test]
+    fn test_chunk_layout() {
+        let mut writer = AviWriter::new(Cursor::new(Vec::new()), 2, 2, (60, 1), 44_100).unwrap();
+        writer.write_frame(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]).unwrap();
+        writer.write_samples(&[0.0; 3]).unwrap();
+        writer.write_frame(&[0; 12]).unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        assert_eq!(data[..4], *b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+        assert_eq!(data[8..12], *b"AVI ");
+        assert_eq!(read_u32(&data, find(&data, b"avih") + 8 + 16), 2);
+
+        // movi list size covers every chunk up to idx1
+        let movi = find(&data, b"movi") - 8;
+        let idx1 = find(&data, b"idx1");
+        assert_eq!(read_u32(&data, movi + 4) as usize, idx1 - movi - 8);
+
+        // first frame: bottom row first, BGR, rows padded to 8 bytes
+        let frame = movi + 12;
+        assert_eq!(data[frame..frame + 8], *b"00db\x10\0\0\0");
+        assert_eq!(data[frame + 8..frame + 24], [9, 8, 7, 12, 11, 10, 0, 0, 3, 2, 1, 6, 5, 4, 0, 0]);
+
+        // audio follows in its own chunk
+        let audio = frame + 24;
+        assert_eq!(data[audio..audio + 8], *b"01wb\x06\0\0\0");
+        assert_eq!(data[audio + 14..audio + 18], *b"00db");
+
+        // index offsets count from the "movi" fourcc
+        assert_eq!(read_u32(&data, idx1 + 4), 3 * 16);
+        assert_eq!(read_u32(&data, idx1 + 16), 4);
+        assert_eq!(read_u32(&data, idx1 + 16 + 16), 4 + 24);
+    }
+}
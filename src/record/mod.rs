@@ -0,0 +1,74 @@
+// Encoders for captured emulator output, and a Recorder that dumps every emulated
+// frame with its audio to an AVI, or a Y4M plus WAV pair
+
+pub mod avi;
+pub mod wav;
+pub mod y4m;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::render::frame::Frame;
+
+enum Output {
+    Avi(avi::AviWriter<BufWriter<File>>),
+    Y4m(y4m::Y4mWriter<BufWriter<File>>, wav::WavWriter<BufWriter<File>>),
+}
+
+pub struct Recorder {
+    output: Output,
+    pub frames: usize,
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, String> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+impl Recorder {
+    // The format follows the extension: .avi, or .y4m with the audio written next to it
+    // as .wav. `frame_rate` is frames per second as a fraction, see Region::frame_rate
+    pub fn create(path: &str, frame_rate: (u32, u32), sample_rate: u32) -> Result<Recorder, String> {
+        let path = Path::new(path);
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        let error = |err: std::io::Error| format!("{}: {}", path.display(), err);
+
+        let output = match extension.as_deref() {
+            Some("avi") => Output::Avi(
+                avi::AviWriter::new(create(path)?, Frame::WIDTH, Frame::HEIGHT, frame_rate, sample_rate)
+                    .map_err(error)?,
+            ),
+            Some("y4m") => {
+                let wav_path = path.with_extension("wav");
+                let video = y4m::Y4mWriter::new(create(path)?, Frame::WIDTH, Frame::HEIGHT, frame_rate)
+                    .map_err(error)?;
+                let audio = wav::WavWriter::new(create(&wav_path)?, sample_rate)
+                    .map_err(|err| format!("{}: {}", wav_path.display(), err))?;
+                Output::Y4m(video, audio)
+            }
+            _ => return Err(format!("{}: expected a .avi or .y4m file name", path.display())),
+        };
+
+        Ok(Recorder { output, frames: 0 })
+    }
+
+    // `rgb` is the frame after palette conversion, `samples` the audio generated with it
+    pub fn record_frame(&mut self, rgb: &[u8], samples: &[f32]) -> Result<(), String> {
+        let result = match &mut self.output {
+            Output::Avi(avi) => avi.write_frame(rgb).and_then(|_| avi.write_samples(samples)),
+            Output::Y4m(video, audio) => video.write_frame(rgb).and_then(|_| audio.write_samples(samples)),
+        };
+        self.frames += 1;
+        result.map_err(|err| format!("recording failed: {}", err))
+    }
+
+    pub fn finish(self) -> Result<(), String> {
+        let result = match self.output {
+            Output::Avi(avi) => avi.finish().map(|_| ()),
+            Output::Y4m(video, audio) => video.finish().and(audio.finish()).map(|_| ()),
+        };
+        result.map_err(|err| format!("recording failed: {}", err))
+    }
+}
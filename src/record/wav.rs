@@ -0,0 +1,104 @@
+// WAV writer for APU output: 16-bit signed PCM, mono
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: usize = 44;
+
+// Convert APU samples (roughly 0.0 to 1.0) to 16-bit PCM
+pub fn to_pcm(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn header(sample_rate: u32, data_len: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_SIZE);
+
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // channels
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // bytes per second
+    out.extend_from_slice(&2u16.to_le_bytes()); // bytes per frame
+    out.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    out
+}
+
+// Complete WAV file holding `samples`
+pub fn encode(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let mut out = header(sample_rate, (samples.len() * 2) as u32);
+    for &sample in samples {
+        out.extend_from_slice(&to_pcm(sample).to_le_bytes());
+    }
+    out
+}
+
+// Streams samples to `out`; the header sizes are filled in by finish()
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        out.write_all(&header(sample_rate, 0))?;
+        Ok(WavWriter {
+            out,
+            sample_rate,
+            samples: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            data.extend_from_slice(&to_pcm(sample).to_le_bytes());
+        }
+        self.out.write_all(&data)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header(self.sample_rate, self.samples * 2))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_encode() {
+        let wav = encode(&[0.0, 1.0, 2.0], 44_100);
+
+        assert_eq!(wav.len(), HEADER_SIZE + 6);
+        assert_eq!(wav[..4], *b"RIFF");
+        assert_eq!(u32::from_le_bytes([wav[4], wav[5], wav[6], wav[7]]), 36 + 6);
+        assert_eq!(u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]), 44_100);
+        assert_eq!(wav[36..40], *b"data");
+        assert_eq!(wav[44..], [0, 0, 0xff, 0x7f, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn test_streaming_matches_encode() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        writer.write_samples(&[0.25, 0.5]).unwrap();
+        writer.write_samples(&[0.75]).unwrap();
+        let streamed = writer.finish().unwrap().into_inner();
+
+        assert_eq!(streamed, encode(&[0.25, 0.5, 0.75], 48_000));
+    }
+}
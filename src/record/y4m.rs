@@ -0,0 +1,69 @@
+// YUV4MPEG2 video stream: a text header followed by uncompressed frames. Frames are
+// stored as full resolution 4:4:4 so single NES pixels don't bleed colour
+
+use std::io::{self, Write};
+
+// BT.601 studio range YCbCr
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    // `frame_rate` is a fraction (numerator, denominator) of frames per second
+    pub fn new(mut out: W, width: usize, height: usize, frame_rate: (u32, u32)) -> io::Result<Self> {
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+            width, height, frame_rate.0, frame_rate.1
+        )?;
+        Ok(Y4mWriter { out, width, height })
+    }
+
+    // `rgb` holds 3 bytes per pixel, rows top to bottom
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), self.width * self.height * 3, "RGB data doesn't match the video size");
+
+        let pixels = self.width * self.height;
+        let mut planes = vec![0u8; pixels * 3];
+        for (i, pixel) in rgb.chunks(3).enumerate() {
+            let (y, u, v) = rgb_to_yuv(pixel[0], pixel[1], pixel[2]);
+            planes[i] = y;
+            planes[pixels + i] = u;
+            planes[pixels * 2 + i] = v;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_header_and_frame() {
+        let mut writer = Y4mWriter::new(Vec::new(), 2, 1, (39_375_000, 655_171)).unwrap();
+        writer.write_frame(&[0, 0, 0, 255, 255, 255]).unwrap();
+        let data = writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F39375000:655171 Ip A1:1 C444\n";
+        assert_eq!(data[..header.len()], header[..]);
+        assert_eq!(data[header.len()..], *b"FRAME\n\x10\xeb\x80\x80\x80\x80");
+    }
+}
@@ -0,0 +1,106 @@
+// TV system timing. NTSC consoles run the CPU at the 21.477 MHz master clock / 12 with
+// 3 PPU dots per CPU cycle; PAL ones at 26.602 MHz / 16 with 3.2 dots per cycle and 312
+// scanlines per frame. The Dendy, a Famicom clone sold in Russia, pairs the PAL clock and
+// scanline count (divided by 15 for the CPU, so 3 dots per cycle) with the NTSC APU
+// and a vblank that starts 50 lines late, to stay compatible with NTSC games
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    // CPU cycles per second
+    pub fn cpu_clock(self) -> f64 {
+        match self {
+            Region::Ntsc => 1_789_773.0,
+            Region::Pal => 1_662_607.0,
+            Region::Dendy => 1_773_448.0,
+        }
+    }
+
+    // PPU dots per CPU cycle as a fraction (numerator, denominator)
+    pub fn dots_per_cpu_cycle(self) -> (usize, usize) {
+        match self {
+            Region::Pal => (16, 5),
+            Region::Ntsc | Region::Dendy => (3, 1),
+        }
+    }
+
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // Scanline at the start of which vblank and the NMI begin. Vblank lasts until the
+    // pre-render line, the last of the frame: 20 lines on NTSC and Dendy, 70 on PAL
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // Only the NTSC PPU shortens odd frames by a dot while rendering
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::Ntsc
+    }
+
+    // Frames per second as a fraction: 60.0988 Hz on NTSC, 50.0070 Hz otherwise
+    pub fn frame_rate(self) -> (u32, u32) {
+        match self {
+            // 21.477272 MHz / 4 per dot / 89341.5 dots per frame
+            Region::Ntsc => (39_375_000, 655_171),
+            // 26.601712 MHz / 5 per dot / 106392 dots per frame
+            Region::Pal | Region::Dendy => (10_640_685, 212_784),
+        }
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Region::Ntsc => "NTSC",
+            Region::Pal => "PAL",
+            Region::Dendy => "Dendy",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("unknown region: {} (expected ntsc, pal or dendy)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_rates_match_clocks() {
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let (num, den) = region.dots_per_cpu_cycle();
+            let dots_per_second = region.cpu_clock() * num as f64 / den as f64;
+            let dots_per_frame = 341.0 * region.scanlines_per_frame() as f64;
+            let (rate, scale) = region.frame_rate();
+            let fps = rate as f64 / scale as f64;
+            // NTSC frames average half a dot less because of the odd frame skip
+            let skip = if region.skips_odd_frame_dot() { 0.5 } else { 0.0 };
+            assert!((dots_per_second / (dots_per_frame - skip) - fps).abs() < 0.001, "{}", region);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::render::palette::Palette;
+use crate::render::png;
+
+lazy_static! {
+    static ref DEFAULT_PALETTE: Palette = Palette::default();
+}
+
+// A rendered 256x240 picture. Pixels are kept as the PPU outputs them: a 6-bit palette
+// colour in bits 0-5 and the PPUMASK colour emphasis bits (R, G, B) in bits 6-8, so
+// the RGB conversion can be done later with any palette or video filter
+pub struct Frame {
+    pub pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub const WIDTH: usize = 256;
+    pub const HEIGHT: usize = 240;
+
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; Frame::WIDTH * Frame::HEIGHT],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u16) {
+        let index = y * Frame::WIDTH + x;
+        if index < self.pixels.len() {
+            self.pixels[index] = color;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * Frame::WIDTH + x]
+    }
+
+    // RGB24 data through the 2C02 palette, e.g. for an SDL texture
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.to_rgb_with(&DEFAULT_PALETTE)
+    }
+
+    pub fn to_rgb_with(&self, palette: &Palette) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for &pixel in &self.pixels {
+            let (r, g, b) = palette.rgb(pixel);
+            data.extend_from_slice(&[r, g, b]);
+        }
+        data
+    }
+
+    // PNG of the frame through `palette`, each pixel blown up to scale x scale
+    pub fn to_png(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let scale = scale.max(1);
+        let rgb = self.to_rgb_with(palette);
+        if scale == 1 {
+            return png::encode_rgb(Frame::WIDTH, Frame::HEIGHT, &rgb);
+        }
+
+        let width = Frame::WIDTH * scale;
+        let mut scaled = Vec::with_capacity(rgb.len() * scale * scale);
+        for row in rgb.chunks(Frame::WIDTH * 3) {
+            let start = scaled.len();
+            for pixel in row.chunks(3) {
+                for _ in 0..scale {
+                    scaled.extend_from_slice(pixel);
+                }
+            }
+            for _ in 1..scale {
+                scaled.extend_from_within(start..start + width * 3);
+            }
+        }
+        png::encode_rgb(width, Frame::HEIGHT * scale, &scaled)
+    }
+
+    pub fn save_png(&self, path: &str, palette: &Palette, scale: usize) -> Result<(), String> {
+        std::fs::write(path, self.to_png(palette, scale)).map_err(|err| format!("{}: {}", path, err))
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::render::palette::SYSTEM_PALLETE;
+
+    #[test]
+    fn test_rgb_goes_through_palette() {
+        let mut frame = Frame::new();
+        frame.set_pixel(1, 0, 0x21);
+        frame.set_pixel(2, 0, 0x21 | 0b111 << 6);
+
+        let mut base = SYSTEM_PALLETE;
+        base[0x21] = (100, 100, 100);
+        let rgb = frame.to_rgb_with(&Palette::from_base(&base));
+        assert_eq!(rgb[3..6], [100, 100, 100]);
+        assert_eq!(rgb[6..9], [67, 67, 67]);
+    }
+
+    #[test]
+    fn test_scaled_png_size() {
+        let frame = Frame::new();
+        let native = frame.to_png(&Palette::default(), 1);
+        let scaled = frame.to_png(&Palette::default(), 3);
+
+        // IHDR width and height
+        assert_eq!(native[16..24], [0, 0, 1, 0, 0, 0, 0, 240]);
+        assert_eq!(scaled[16..24], [0, 0, 3, 0, 0, 0, 2, 208]);
+        assert!(scaled.len() > native.len() * 8);
+    }
+}
@@ -0,0 +1,7 @@
+pub mod font;
+pub mod frame;
+pub mod ntsc;
+pub mod palette;
+pub mod png;
+pub mod scale;
+pub mod viewer;
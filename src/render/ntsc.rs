@@ -0,0 +1,320 @@
+// NTSC composite video filter. Every PPU dot is turned into 8 samples of the square
+// wave the 2C02 puts out for its colour (12 samples per colour subcarrier cycle), then
+// the line is decoded back to YIQ by averaging over windows of the signal, as described
+// on the nesdev wiki "NTSC video" page. Artefact colours and fringing come out of the
+// decoding the same way they do on a TV.
+//
+// Window sums come from prefix sums over the line, so the cost per output pixel is
+// constant whatever the window sizes
+
+use std::f32::consts::PI;
+
+use crate::render::frame::Frame;
+
+// Voltage levels relative to sync, for levels 0-3 of the colour's low and high half
+const LEVELS_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const LEVELS_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+const SAMPLES_PER_DOT: usize = 8;
+const SAMPLES_PER_LINE: usize = Frame::WIDTH * SAMPLES_PER_DOT;
+const PHASES: usize = 12;
+// 341 dots * 8 samples per scanline is 4 phases more than a whole number of cycles
+const LINE_PHASE_STEP: usize = 341 * SAMPLES_PER_DOT % PHASES;
+// Decoder phase that lines the colours up with the usual NES palettes
+const HUE_TWEAK: f32 = 3.9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    // Hue rotation in degrees
+    pub hue: f32,
+    // Chroma gain, 1.0 is neutral and 0.0 greyscale
+    pub saturation: f32,
+    // -1.0 blurs, 1.0 sharpens luma edges
+    pub sharpness: f32,
+    // 0.0 to 1.0: how much of the colour subcarrier is left in luma, showing up as dot
+    // crawl and checkerboard patterns on colour edges
+    pub artifacts: f32,
+    // 0.0 to 1.0: widens the chroma window, spreading colour fringes around luma edges
+    pub fringing: f32,
+    // Move the subcarrier phase every frame like the PPU does (it skips a dot on odd
+    // frames). Turning it off gives a steady picture without the dot crawl
+    pub phase_shift: bool,
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            sharpness: 0.0,
+            artifacts: 0.0,
+            fringing: 0.0,
+            phase_shift: true,
+        }
+    }
+}
+
+impl std::str::FromStr for NtscSettings {
+    type Err = String;
+
+    // "default", or comma separated settings like "hue=-5,sharpness=0.3,phase_shift=off"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut settings = NtscSettings::default();
+        if s == "default" || s == "on" {
+            return Ok(settings);
+        }
+
+        for setting in s.split(',') {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("invalid NTSC setting: {} (expected key=value)", setting))?;
+            let number = || value.parse::<f32>().map_err(|_| format!("invalid value for {}: {}", key, value));
+            match key {
+                "hue" => settings.hue = number()?,
+                "saturation" => settings.saturation = number()?,
+                "sharpness" => settings.sharpness = number()?.clamp(-1.0, 1.0),
+                "artifacts" => settings.artifacts = number()?.clamp(0.0, 1.0),
+                "fringing" => settings.fringing = number()?.clamp(0.0, 1.0),
+                "phase_shift" => {
+                    settings.phase_shift = match value {
+                        "on" | "true" | "1" => true,
+                        "off" | "false" | "0" => false,
+                        _ => return Err(format!("invalid value for phase_shift: {}", value)),
+                    }
+                }
+                _ => return Err(format!("unknown NTSC setting: {}", key)),
+            }
+        }
+        Ok(settings)
+    }
+}
+
+// Normalised signal level of a frame pixel (colour | emphasis << 6) at a subcarrier phase
+fn signal(pixel: u16, phase: usize) -> f32 {
+    let color = (pixel & 0x0f) as usize;
+    let emphasis = (pixel >> 6) & 0b111;
+    // colours $xE and $xF are output as level 1 black
+    let level = if color > 13 { 1 } else { ((pixel >> 4) & 3) as usize };
+
+    let mut low = LEVELS_LOW[level];
+    let mut high = LEVELS_HIGH[level];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let in_color_phase = |color: usize| (color + phase) % PHASES < 6;
+    let mut level = if in_color_phase(color) { high } else { low };
+
+    if (emphasis & 1 != 0 && in_color_phase(0))
+        || (emphasis & 2 != 0 && in_color_phase(4))
+        || (emphasis & 4 != 0 && in_color_phase(8))
+    {
+        level *= EMPHASIS_ATTENUATION;
+    }
+
+    (level - BLACK) / (WHITE - BLACK)
+}
+
+pub struct NtscFilter {
+    pub settings: NtscSettings,
+    // signal level of every pixel value at every phase, repeated twice so a line's
+    // phase offset can be added without wrapping
+    levels: Vec<[f32; PHASES * 2]>,
+    frame_phase: usize,
+    // prefix sums of the line's signal, and of the signal times cos/sin of the carrier
+    sum_y: Vec<f32>,
+    sum_i: Vec<f32>,
+    sum_q: Vec<f32>,
+    luma: Vec<f32>,
+    rgb: Vec<u8>,
+}
+
+impl NtscFilter {
+    // Output width: 256 pixels stretched to roughly the 8:7 pixel aspect ratio
+    pub const WIDTH: usize = 602;
+
+    pub fn new(settings: NtscSettings) -> Self {
+        let levels = (0..512u16)
+            .map(|pixel| {
+                let mut levels = [0.0; PHASES * 2];
+                for (phase, level) in levels.iter_mut().enumerate() {
+                    *level = signal(pixel, phase % PHASES);
+                }
+                levels
+            })
+            .collect();
+
+        NtscFilter {
+            settings,
+            levels,
+            frame_phase: 0,
+            sum_y: vec![0.0; SAMPLES_PER_LINE + 1],
+            sum_i: vec![0.0; SAMPLES_PER_LINE + 1],
+            sum_q: vec![0.0; SAMPLES_PER_LINE + 1],
+            luma: vec![0.0; NtscFilter::WIDTH],
+            rgb: vec![0; NtscFilter::WIDTH * Frame::HEIGHT * 3],
+        }
+    }
+
+    // Filter a frame to WIDTH x 240 RGB24. Each call is one emulated frame
+    pub fn apply(&mut self, frame: &Frame) -> &[u8] {
+        let settings = self.settings;
+        let decode_phase = HUE_TWEAK + settings.hue / 30.0;
+
+        // sample ranges averaged for each output pixel: a full carrier cycle for luma
+        // and chroma, fringing widens the chroma window and artifacts blend in a luma
+        // window shorter than a cycle
+        let windows = |half: f32| -> Vec<(usize, usize, f32)> {
+            (0..NtscFilter::WIDTH)
+                .map(|x| {
+                    let center = (x * SAMPLES_PER_LINE) as f32 / NtscFilter::WIDTH as f32;
+                    let begin = (center - half).max(0.0) as usize;
+                    let end = ((center + half) as usize).min(SAMPLES_PER_LINE);
+                    (begin, end, 1.0 / (half * 2.0))
+                })
+                .collect()
+        };
+        let luma_windows = windows(6.0);
+        let narrow_windows = windows(2.0);
+        let chroma_windows = windows(6.0 + settings.fringing * 6.0);
+        let window = |sum: &[f32], (begin, end, scale): (usize, usize, f32)| (sum[end] - sum[begin]) * scale;
+
+        for y in 0..Frame::HEIGHT {
+            let line_phase = (self.frame_phase + y * LINE_PHASE_STEP) % PHASES;
+
+            let mut carrier = [(0.0, 0.0); PHASES];
+            for (p, carrier) in carrier.iter_mut().enumerate() {
+                let angle = PI * ((line_phase + p) as f32 + decode_phase) / 6.0;
+                *carrier = (angle.cos(), angle.sin());
+            }
+
+            let pixels = &frame.pixels[y * Frame::WIDTH..(y + 1) * Frame::WIDTH];
+            let (mut sy, mut si, mut sq) = (0.0, 0.0, 0.0);
+            let mut p = 0;
+            // phase of sample p relative to the line start, kept in step to avoid divisions
+            let mut phase = 0;
+            for &pixel in pixels {
+                let levels = &self.levels[(pixel & 0x1ff) as usize][line_phase..line_phase + PHASES];
+                for _ in 0..SAMPLES_PER_DOT {
+                    let level = levels[phase];
+                    let (cos, sin) = carrier[phase];
+                    sy += level;
+                    si += level * cos;
+                    sq += level * sin;
+                    p += 1;
+                    self.sum_y[p] = sy;
+                    self.sum_i[p] = si;
+                    self.sum_q[p] = sq;
+                    phase = if phase == PHASES - 1 { 0 } else { phase + 1 };
+                }
+            }
+
+            let row = &mut self.rgb[y * NtscFilter::WIDTH * 3..(y + 1) * NtscFilter::WIDTH * 3];
+            for (x, luma) in self.luma.iter_mut().enumerate() {
+                let clean = window(&self.sum_y, luma_windows[x]);
+                let narrow = window(&self.sum_y, narrow_windows[x]);
+                *luma = clean + settings.artifacts * (narrow - clean);
+            }
+
+            for x in 0..NtscFilter::WIDTH {
+                let mut luma = self.luma[x];
+                if settings.sharpness != 0.0 && x > 0 && x + 1 < NtscFilter::WIDTH {
+                    let neighbours = (self.luma[x - 1] + self.luma[x + 1]) / 2.0;
+                    luma += settings.sharpness * (luma - neighbours);
+                }
+                let i = window(&self.sum_i, chroma_windows[x]) * settings.saturation;
+                let q = window(&self.sum_q, chroma_windows[x]) * settings.saturation;
+
+                // float to int casts saturate, so this also clamps to 0-255
+                let to_byte = |value: f32| (value * 255.0 + 0.5) as u8;
+                row[x * 3] = to_byte(luma + 0.946882 * i + 0.623557 * q);
+                row[x * 3 + 1] = to_byte(luma - 0.274788 * i - 0.635691 * q);
+                row[x * 3 + 2] = to_byte(luma - 1.108545 * i + 1.709007 * q);
+            }
+        }
+
+        if settings.phase_shift {
+            // an even frame is one dot longer than an odd one with the skipped dot,
+            // so the starting phase toggles between 0 and one line's step
+            self.frame_phase = if self.frame_phase == 0 { LINE_PHASE_STEP } else { 0 };
+        }
+        &self.rgb
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn solid(color: u16) -> Frame {
+        let mut frame = Frame::new();
+        frame.pixels.iter_mut().for_each(|pixel| *pixel = color);
+        frame
+    }
+
+    fn pixel(rgb: &[u8], x: usize, y: usize) -> (u8, u8, u8) {
+        let i = (y * NtscFilter::WIDTH + x) * 3;
+        (rgb[i], rgb[i + 1], rgb[i + 2])
+    }
+
+    #[test]
+    fn test_greys_have_no_chroma() {
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        let rgb = filter.apply(&solid(0x30)).to_vec();
+        let (r, g, b) = pixel(&rgb, 300, 100);
+        assert_eq!((r, g, b), (255, 255, 255));
+
+        let rgb = filter.apply(&solid(0x0f)).to_vec();
+        assert_eq!(pixel(&rgb, 300, 100), (0, 0, 0));
+
+        let (r, g, b) = pixel(filter.apply(&solid(0x10)), 300, 100);
+        assert!(r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1, "{:?}", (r, g, b));
+    }
+
+    #[test]
+    fn test_colours_and_saturation() {
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        // $16 is a red
+        let (r, g, b) = pixel(filter.apply(&solid(0x16)), 300, 100);
+        assert!(r > g + 60 && r > b + 60, "{:?}", (r, g, b));
+
+        filter.settings.saturation = 0.0;
+        let (r, g, b) = pixel(filter.apply(&solid(0x16)), 300, 100);
+        assert_eq!((r, g), (g, b));
+    }
+
+    #[test]
+    fn test_phase_alternates_between_frames() {
+        let mut frame = Frame::new();
+        for (i, pixel) in frame.pixels.iter_mut().enumerate() {
+            *pixel = if i % 2 == 0 { 0x30 } else { 0x0f };
+        }
+
+        let mut filter = NtscFilter::new(NtscSettings::default());
+        let first = filter.apply(&frame).to_vec();
+        let second = filter.apply(&frame).to_vec();
+        let third = filter.apply(&frame).to_vec();
+        assert_ne!(first, second);
+        assert_eq!(first, third);
+
+        filter.settings.phase_shift = false;
+        let steady = filter.apply(&frame).to_vec();
+        assert_eq!(steady, filter.apply(&frame));
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        let settings: NtscSettings = "hue=-5,sharpness=2,phase_shift=off".parse().unwrap();
+        assert_eq!(settings.hue, -5.0);
+        assert_eq!(settings.sharpness, 1.0);
+        assert!(!settings.phase_shift);
+        assert_eq!("default".parse::<NtscSettings>().unwrap(), NtscSettings::default());
+        assert!("tint=3".parse::<NtscSettings>().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+// Palettes map the PPU's output (a 6-bit colour plus the three PPUMASK emphasis bits)
+// to RGB. A full palette has 512 entries: 8 emphasis combinations of the 64 colours,
+// laid out like a 1536 byte .pal file, so a frame pixel indexes it directly
+
+const COLORS: usize = 64;
+const ENTRIES: usize = COLORS * 8;
+
+// Emphasis darkens the two other channels on composite video PPUs
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// 2C02 NTSC master palette
+pub static SYSTEM_PALLETE: [(u8, u8, u8); 64] = [
+    (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+    (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+    (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+    (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+    (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+    (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+    (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+    (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+    (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+    (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+    (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+    (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+    (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11),
+];
+
+// RGB PPUs (2C03, 2C05 and friends) use a 3-bit DAC per channel. Digits are R, G, B
+static RGB_PPU_PALLETE: [u16; COLORS] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteKind {
+    // NTSC composite PPU
+    Ntsc2C02,
+    // PAL composite PPU: the 2C02 colours, with the red and green emphasis bits swapped
+    Pal2C07,
+    // Arcade RGB PPUs, where emphasis drives a channel to full brightness
+    Rgb2C03,
+    // Same colours as the 2C03, the 2C05 only differs in its register layout
+    Rgb2C05,
+}
+
+impl std::str::FromStr for PaletteKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "2c02" | "ntsc" => Ok(PaletteKind::Ntsc2C02),
+            "2c07" | "pal" => Ok(PaletteKind::Pal2C07),
+            "2c03" | "rgb" => Ok(PaletteKind::Rgb2C03),
+            "2c05" => Ok(PaletteKind::Rgb2C05),
+            _ => Err(format!("unknown palette: {} (expected 2c02, 2c07, 2c03, 2c05 or a .pal file)", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Palette {
+    colors: Vec<(u8, u8, u8)>,
+}
+
+fn attenuate(value: u8) -> u8 {
+    (value as f32 * EMPHASIS_ATTENUATION).round() as u8
+}
+
+impl Palette {
+    pub fn builtin(kind: PaletteKind) -> Palette {
+        match kind {
+            PaletteKind::Ntsc2C02 => Palette::from_base(&SYSTEM_PALLETE),
+            PaletteKind::Pal2C07 => {
+                let mut palette = Palette::from_base(&SYSTEM_PALLETE);
+                // emphasis bit 0 is green and bit 1 red on the 2C07
+                let ntsc = palette.colors.clone();
+                for emphasis in 0..8 {
+                    let swapped = (emphasis & 0b100) | (emphasis & 1) << 1 | (emphasis & 2) >> 1;
+                    palette.colors[emphasis * COLORS..(emphasis + 1) * COLORS]
+                        .copy_from_slice(&ntsc[swapped * COLORS..(swapped + 1) * COLORS]);
+                }
+                palette
+            }
+            PaletteKind::Rgb2C03 | PaletteKind::Rgb2C05 => {
+                let level = |value: u16| ((value & 7) * 255 / 7) as u8;
+                let mut colors = Vec::with_capacity(ENTRIES);
+                for emphasis in 0..8 {
+                    for &rgb in RGB_PPU_PALLETE.iter() {
+                        let (mut r, mut g, mut b) = (level(rgb >> 6), level(rgb >> 3), level(rgb));
+                        if emphasis & 1 != 0 {
+                            r = 0xff;
+                        }
+                        if emphasis & 2 != 0 {
+                            g = 0xff;
+                        }
+                        if emphasis & 4 != 0 {
+                            b = 0xff;
+                        }
+                        colors.push((r, g, b));
+                    }
+                }
+                Palette { colors }
+            }
+        }
+    }
+
+    // Expand 64 colours to all emphasis combinations the way a composite PPU does.
+    // Colours $xE and $xF are forced black and aren't affected
+    pub fn from_base(base: &[(u8, u8, u8); COLORS]) -> Palette {
+        let mut colors = Vec::with_capacity(ENTRIES);
+        for emphasis in 0..8 {
+            for (i, &(r, g, b)) in base.iter().enumerate() {
+                if i & 0x0e == 0x0e {
+                    colors.push((r, g, b));
+                    continue;
+                }
+                // each emphasised channel darkens the other two
+                let (mut r, mut g, mut b) = (r, g, b);
+                if emphasis & 1 != 0 {
+                    g = attenuate(g);
+                    b = attenuate(b);
+                }
+                if emphasis & 2 != 0 {
+                    r = attenuate(r);
+                    b = attenuate(b);
+                }
+                if emphasis & 4 != 0 {
+                    r = attenuate(r);
+                    g = attenuate(g);
+                }
+                colors.push((r, g, b));
+            }
+        }
+        Palette { colors }
+    }
+
+    // A .pal file: 64 RGB triples (192 bytes), or all 512 emphasis variants (1536 bytes)
+    pub fn from_pal(data: &[u8]) -> Result<Palette, String> {
+        let triples = || data.chunks(3).map(|rgb| (rgb[0], rgb[1], rgb[2]));
+        match data.len() {
+            192 => {
+                let mut base = [(0, 0, 0); COLORS];
+                for (color, rgb) in base.iter_mut().zip(triples()) {
+                    *color = rgb;
+                }
+                Ok(Palette::from_base(&base))
+            }
+            1536 => Ok(Palette { colors: triples().collect() }),
+            len => Err(format!("palette file of {} bytes, expected 192 or 1536", len)),
+        }
+    }
+
+    // A built-in palette name, or the path of a .pal file
+    pub fn load(name: &str) -> Result<Palette, String> {
+        if let Ok(kind) = name.parse() {
+            return Ok(Palette::builtin(kind));
+        }
+        if !name.to_ascii_lowercase().ends_with(".pal") {
+            return Err(name.parse::<PaletteKind>().unwrap_err());
+        }
+        let data = std::fs::read(name).map_err(|err| format!("{}: {}", name, err))?;
+        Palette::from_pal(&data).map_err(|err| format!("{}: {}", name, err))
+    }
+
+    // RGB of a frame pixel: colour in bits 0-5, emphasis in bits 6-8
+    pub fn rgb(&self, pixel: u16) -> (u8, u8, u8) {
+        self.colors[(pixel as usize) & (ENTRIES - 1)]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::builtin(PaletteKind::Ntsc2C02)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_emphasis_darkens_other_channels() {
+        let palette = Palette::default();
+        let white = palette.rgb(0x30);
+        assert_eq!(white, SYSTEM_PALLETE[0x30]);
+
+        // red emphasis
+        let (r, g, b) = palette.rgb(0x30 | 0b001 << 6);
+        assert_eq!(r, white.0);
+        assert!(g < white.1 && b < white.2);
+
+        // black columns are left alone
+        assert_eq!(palette.rgb(0x0f | 0b111 << 6), SYSTEM_PALLETE[0x0f]);
+    }
+
+    #[test]
+    fn test_pal_swaps_red_and_green_emphasis() {
+        let ntsc = Palette::builtin(PaletteKind::Ntsc2C02);
+        let pal = Palette::builtin(PaletteKind::Pal2C07);
+        assert_eq!(pal.rgb(0x21 | 0b001 << 6), ntsc.rgb(0x21 | 0b010 << 6));
+        assert_eq!(pal.rgb(0x21 | 0b100 << 6), ntsc.rgb(0x21 | 0b100 << 6));
+    }
+
+    #[test]
+    fn test_rgb_ppu() {
+        let palette = Palette::builtin(PaletteKind::Rgb2C03);
+        assert_eq!(palette.rgb(0x20), (0xff, 0xff, 0xff));
+        assert_eq!(palette.rgb(0x16), (0xff, 0, 0));
+        assert_eq!(palette.rgb(0x0f | 0b100 << 6), (0, 0, 0xff));
+    }
+
+    #[test]
+    fn test_pal_files() {
+        let mut data = vec![0; 192];
+        data[3..6].copy_from_slice(&[1, 2, 3]);
+        let palette = Palette::from_pal(&data).unwrap();
+        assert_eq!(palette.rgb(0x01), (1, 2, 3));
+
+        let full: Vec<u8> = (0..1536).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::from_pal(&full).unwrap();
+        assert_eq!(palette.rgb(0x1ff), (0xff, 0xff, 0xff));
+        assert_eq!(palette.rgb(0x41), (0x41, 0x41, 0x41));
+
+        assert!(Palette::from_pal(&[0; 100]).is_err());
+        assert!(Palette::load("sepia").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+// Minimal PNG encoder for RGB24 images. The image data is stored in uncompressed
+// deflate blocks, which keeps the encoder tiny at the cost of file size
+
+use crate::hash;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+// Largest payload of a stored deflate block
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = hash::crc32_update(hash::crc32(kind), data);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+// zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Encode `rgb` (3 bytes per pixel, rows top to bottom) as an 8-bit truecolour PNG
+pub fn encode_rgb(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "RGB data doesn't match the image size");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth 8, colour type 2 (RGB), deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with its filter type, 0 = none
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_encode_layout() {
+        let png = encode_rgb(2, 1, &[255, 0, 0, 0, 255, 0]);
+
+        assert_eq!(png[..8], SIGNATURE);
+        // IHDR: length 13, then width and height
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        let crc = u32::from_be_bytes([png[29], png[30], png[31], png[32]]);
+        assert_eq!(crc, hash::crc32(&png[12..29]));
+
+        // a single final stored block holding the filtered row
+        let idat = &png[33..];
+        assert_eq!(idat[4..8], *b"IDAT");
+        assert_eq!(idat[8..16], [0x78, 0x01, 1, 7, 0, 0xf8, 0xff, 0]);
+        assert_eq!(png[png.len() - 12..png.len() - 4], [0, 0, 0, 0, b'I', b'E', b'N', b'D']);
+    }
+
+    #[test]
+    fn test_large_images_are_split_into_blocks() {
+        let stream = zlib_stored(&vec![0; MAX_STORED_BLOCK + 1]);
+        assert_eq!(stream[2], 0);
+        assert_eq!(stream[2 + 5 + MAX_STORED_BLOCK], 1);
+        assert_eq!(stream.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 1 + 4);
+    }
+}
@@ -0,0 +1,438 @@
+// Pixel art upscalers working on RGB24 images, and the viewport maths for fitting a
+// picture into a window. Filters run on the CPU before the picture is uploaded
+
+// Filter applied to the frame before display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    None,
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Hq3x,
+    Xbr2x,
+}
+
+impl ScaleFilter {
+    pub const ALL: [ScaleFilter; 6] = [
+        ScaleFilter::None,
+        ScaleFilter::Scale2x,
+        ScaleFilter::Scale3x,
+        ScaleFilter::Hq2x,
+        ScaleFilter::Hq3x,
+        ScaleFilter::Xbr2x,
+    ];
+
+    pub fn factor(self) -> usize {
+        match self {
+            ScaleFilter::None => 1,
+            ScaleFilter::Scale2x | ScaleFilter::Hq2x | ScaleFilter::Xbr2x => 2,
+            ScaleFilter::Scale3x | ScaleFilter::Hq3x => 3,
+        }
+    }
+
+    // The filter after this one, for cycling through them at runtime
+    pub fn next(self) -> ScaleFilter {
+        let index = ScaleFilter::ALL.iter().position(|&filter| filter == self).unwrap();
+        ScaleFilter::ALL[(index + 1) % ScaleFilter::ALL.len()]
+    }
+
+    // Upscale `rgb` (width x height, 3 bytes per pixel) by factor()
+    pub fn apply(self, rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+        let image = Image::new(rgb, width, height);
+        match self {
+            ScaleFilter::None => rgb.to_vec(),
+            ScaleFilter::Scale2x => image.scale2x(),
+            ScaleFilter::Scale3x => image.scale3x(),
+            ScaleFilter::Hq2x => image.hqx(2),
+            ScaleFilter::Hq3x => image.hqx(3),
+            ScaleFilter::Xbr2x => image.xbr2x(),
+        }
+    }
+}
+
+impl std::fmt::Display for ScaleFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ScaleFilter::None => "none",
+            ScaleFilter::Scale2x => "scale2x",
+            ScaleFilter::Scale3x => "scale3x",
+            ScaleFilter::Hq2x => "hq2x",
+            ScaleFilter::Hq3x => "hq3x",
+            ScaleFilter::Xbr2x => "xbr2x",
+        };
+        f.write_str(name)
+    }
+}
+
+impl std::str::FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ScaleFilter::ALL
+            .iter()
+            .find(|filter| filter.to_string() == s.to_ascii_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown filter: {} (expected none, scale2x, scale3x, hq2x, hq3x or xbr2x)", s))
+    }
+}
+
+type Rgb = [u8; 3];
+
+// xBR works on the bottom right corner of a pixel; these map the other corners onto
+// it. Each rotation gives the unit steps of its x and y axes and the output cells
+// (corner, next along x, next along y) of a 2x2 block
+type Rotation = ((isize, isize), (isize, isize), [usize; 3]);
+const XBR_ROTATIONS: [Rotation; 4] = [
+    ((1, 0), (0, 1), [3, 2, 1]),
+    ((0, -1), (1, 0), [1, 3, 0]),
+    ((-1, 0), (0, -1), [0, 1, 2]),
+    ((0, 1), (-1, 0), [2, 3, 0]),
+];
+
+// A source pixel with its YUV values worked out once for the similarity tests
+#[derive(Clone, Copy)]
+struct Pixel {
+    rgb: Rgb,
+    yuv: [i32; 3],
+}
+
+impl PartialEq for Pixel {
+    fn eq(&self, other: &Self) -> bool {
+        self.rgb == other.rgb
+    }
+}
+
+// Source image, with reads past the edges clamped
+struct Image {
+    pixels: Vec<Pixel>,
+    width: usize,
+    height: usize,
+}
+
+// Weighted average of pixels, weights summing to a power of two `1 << shift`
+fn blend(colors: &[(Rgb, u32)], shift: u32) -> Rgb {
+    let mut out = [0; 3];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let sum: u32 = colors.iter().map(|(color, weight)| color[channel] as u32 * weight).sum();
+        *value = (sum >> shift) as u8;
+    }
+    out
+}
+
+fn to_yuv(color: Rgb) -> [i32; 3] {
+    let (r, g, b) = (color[0] as i32, color[1] as i32, color[2] as i32);
+    let y = (299 * r + 587 * g + 114 * b) / 1000;
+    let u = (-169 * r - 331 * g + 500 * b) / 1000 + 128;
+    let v = (500 * r - 419 * g - 81 * b) / 1000 + 128;
+    [y, u, v]
+}
+
+// hqx's similarity test: colours are "the same" within these YUV thresholds
+fn similar(a: Pixel, b: Pixel) -> bool {
+    let [ya, ua, va] = a.yuv;
+    let [yb, ub, vb] = b.yuv;
+    (ya - yb).abs() <= 48 && (ua - ub).abs() <= 7 && (va - vb).abs() <= 6
+}
+
+// xBR's weighted YUV distance
+fn distance(a: Pixel, b: Pixel) -> i32 {
+    let [ya, ua, va] = a.yuv;
+    let [yb, ub, vb] = b.yuv;
+    48 * (ya - yb).abs() + 7 * (ua - ub).abs() + 6 * (va - vb).abs()
+}
+
+impl Image {
+    fn new(rgb: &[u8], width: usize, height: usize) -> Self {
+        assert_eq!(rgb.len(), width * height * 3, "RGB data doesn't match the image size");
+        Image {
+            pixels: rgb
+                .chunks(3)
+                .map(|p| {
+                    let rgb = [p[0], p[1], p[2]];
+                    Pixel { rgb, yuv: to_yuv(rgb) }
+                })
+                .collect(),
+            width,
+            height,
+        }
+    }
+
+    fn at(&self, x: isize, y: isize) -> Pixel {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // Run `block` for every source pixel; it fills a factor x factor block of output
+    fn upscale<F>(&self, factor: usize, mut block: F) -> Vec<u8>
+    where
+        F: FnMut(isize, isize, &mut [Rgb]),
+    {
+        let out_width = self.width * factor;
+        let mut out = vec![0; out_width * self.height * factor * 3];
+        let mut cells = vec![[0; 3]; factor * factor];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                block(x as isize, y as isize, &mut cells);
+                for (i, cell) in cells.iter().enumerate() {
+                    let (ox, oy) = (x * factor + i % factor, y * factor + i / factor);
+                    let offset = (oy * out_width + ox) * 3;
+                    out[offset..offset + 3].copy_from_slice(cell);
+                }
+            }
+        }
+        out
+    }
+
+    // Scale2x (AdvanceMAME2x / EPX)
+    fn scale2x(&self) -> Vec<u8> {
+        self.upscale(2, |x, y, out| {
+            let rgb = |x, y| self.at(x, y).rgb;
+            let (b, d, e, f, h) = (rgb(x, y - 1), rgb(x - 1, y), rgb(x, y), rgb(x + 1, y), rgb(x, y + 1));
+            if b != h && d != f {
+                out[0] = if d == b { d } else { e };
+                out[1] = if b == f { f } else { e };
+                out[2] = if d == h { d } else { e };
+                out[3] = if h == f { f } else { e };
+            } else {
+                out.fill(e);
+            }
+        })
+    }
+
+    // Scale3x (AdvanceMAME3x)
+    fn scale3x(&self) -> Vec<u8> {
+        self.upscale(3, |x, y, out| {
+            let rgb = |x, y| self.at(x, y).rgb;
+            let (a, b, c) = (rgb(x - 1, y - 1), rgb(x, y - 1), rgb(x + 1, y - 1));
+            let (d, e, f) = (rgb(x - 1, y), rgb(x, y), rgb(x + 1, y));
+            let (g, h, i) = (rgb(x - 1, y + 1), rgb(x, y + 1), rgb(x + 1, y + 1));
+            out.fill(e);
+            if b != h && d != f {
+                out[0] = if d == b { d } else { e };
+                out[1] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+                out[2] = if b == f { f } else { e };
+                out[3] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+                out[5] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+                out[6] = if d == h { d } else { e };
+                out[7] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+                out[8] = if h == f { f } else { e };
+            }
+        })
+    }
+
+    // HQ2x/HQ3x style smoothing. This uses hqx's YUV similarity test and its blend
+    // weights, but decides per corner from the two edge neighbours and the diagonal
+    // instead of the original 256 entry pattern tables
+    fn hqx(&self, factor: usize) -> Vec<u8> {
+        // corner of the block nearest to neighbours (dx, dy)
+        let corner = |e: Pixel, side_x: Pixel, side_y: Pixel, diagonal: Pixel| -> Option<Rgb> {
+            if similar(side_x, side_y) && !similar(e, side_x) {
+                if similar(diagonal, side_x) {
+                    // an edge running across the corner
+                    Some(blend(&[(e.rgb, 2), (side_x.rgb, 3), (side_y.rgb, 3)], 3))
+                } else {
+                    Some(blend(&[(e.rgb, 2), (side_x.rgb, 1), (side_y.rgb, 1)], 2))
+                }
+            } else if !similar(e, diagonal) && similar(e, side_x) && similar(e, side_y) {
+                Some(blend(&[(e.rgb, 3), (diagonal.rgb, 1)], 2))
+            } else {
+                None
+            }
+        };
+
+        self.upscale(factor, |x, y, out| {
+            let e = self.at(x, y);
+            let n = |dx: isize, dy: isize| self.at(x + dx, y + dy);
+            if [n(-1, -1), n(0, -1), n(1, -1), n(-1, 0), n(1, 0), n(-1, 1), n(0, 1), n(1, 1)]
+                .iter()
+                .all(|&neighbour| neighbour == e)
+            {
+                out.fill(e.rgb);
+                return;
+            }
+
+            let top_left = corner(e, n(-1, 0), n(0, -1), n(-1, -1));
+            let top_right = corner(e, n(1, 0), n(0, -1), n(1, -1));
+            let bottom_left = corner(e, n(-1, 0), n(0, 1), n(-1, 1));
+            let bottom_right = corner(e, n(1, 0), n(0, 1), n(1, 1));
+
+            out.fill(e.rgb);
+            let last = factor * factor - 1;
+            out[0] = top_left.unwrap_or(e.rgb);
+            out[factor - 1] = top_right.unwrap_or(e.rgb);
+            out[last + 1 - factor] = bottom_left.unwrap_or(e.rgb);
+            out[last] = bottom_right.unwrap_or(e.rgb);
+
+            if factor == 3 {
+                // edge middles follow an edge that bends both of their corners
+                let edge = |a: Option<Rgb>, b: Option<Rgb>, side: Pixel| match (a, b) {
+                    (Some(_), Some(_)) => blend(&[(e.rgb, 3), (side.rgb, 1)], 2),
+                    _ => e.rgb,
+                };
+                out[1] = edge(top_left, top_right, n(0, -1));
+                out[3] = edge(top_left, bottom_left, n(-1, 0));
+                out[5] = edge(top_right, bottom_right, n(1, 0));
+                out[7] = edge(bottom_left, bottom_right, n(0, 1));
+            }
+        })
+    }
+
+    // 2xBR (Hyllian's xBR level 1 at 2x). Each of the four rotations looks at the
+    // corner towards PI and blends the output cells along the detected edge
+    fn xbr2x(&self) -> Vec<u8> {
+        let eq = |a: Pixel, b: Pixel| distance(a, b) < 155;
+
+        self.upscale(2, |x, y, out| {
+            let n = |dx: isize, dy: isize| self.at(x + dx, y + dy);
+            let e = n(0, 0);
+            out.fill(e.rgb);
+
+            for ((rx, ry), (dx, dy), [n3, n2, n1]) in XBR_ROTATIONS {
+                // p(i, j): i steps along the rotated x axis, j along the rotated y axis
+                let p = |i: isize, j: isize| n(rx * i + dx * j, ry * i + dy * j);
+                let (pe, pf, ph, pi) = (e, p(1, 0), p(0, 1), p(1, 1));
+                let (pb, pc, pd, pg) = (p(0, -1), p(1, -1), p(-1, 0), p(-1, 1));
+                let (f4, i4, h5, i5) = (p(2, 0), p(2, 1), p(0, 2), p(1, 2));
+
+                if pe == ph || pe == pf {
+                    continue;
+                }
+                let d = distance;
+                let edge = d(pe, pc) + d(pe, pg) + d(pi, h5) + d(pi, f4) + (d(ph, pf) << 2);
+                let across = d(ph, pd) + d(ph, i5) + d(pf, i4) + d(pf, pb) + (d(pe, pi) << 2);
+                let px = if d(pe, pf) <= d(pe, ph) { pf } else { ph };
+
+                let corner = (!eq(pf, pb) && !eq(ph, pd))
+                    || (eq(pe, pi) && !eq(pf, i4) && !eq(ph, i5))
+                    || eq(pe, pg)
+                    || eq(pe, pc);
+                if edge < across && corner {
+                    let ke = d(pf, pg);
+                    let ki = d(ph, pc);
+                    let ex2 = pe != pc && pb != pc;
+                    let ex3 = pe != pg && pd != pg;
+                    let towards = |cell: Rgb, weight: u32| blend(&[(cell, 256 - weight), (px.rgb, weight)], 8);
+                    let shallow = (ke << 1) <= ki && ex3;
+                    let steep = ke >= (ki << 1) && ex2;
+
+                    if !shallow && !steep {
+                        out[n3] = towards(out[n3], 128);
+                        continue;
+                    }
+                    out[n3] = towards(out[n3], 192);
+                    if shallow {
+                        out[n2] = towards(out[n2], 64);
+                    }
+                    if steep {
+                        out[n1] = if shallow { out[n2] } else { towards(out[n1], 64) };
+                    }
+                } else if edge <= across {
+                    out[n3] = blend(&[(out[n3], 128), (px.rgb, 128)], 8);
+                }
+            }
+        })
+    }
+}
+
+// Where to draw a width x height picture in a window: the largest size that fits while
+// keeping the aspect ratio, optionally stretched by `pixel_aspect` horizontally (8/7
+// for NES pixels) and limited to whole multiples of the picture height. Returns
+// (x, y, width, height)
+pub fn fit(width: u32, height: u32, window: (u32, u32), pixel_aspect: f32, integer: bool) -> (i32, i32, u32, u32) {
+    let (window_width, window_height) = window;
+    let display_width = width as f32 * pixel_aspect;
+
+    let mut scale = (window_width as f32 / display_width).min(window_height as f32 / height as f32);
+    if integer && scale >= 1.0 {
+        scale = scale.floor();
+    }
+
+    let out_width = ((display_width * scale).round() as u32).min(window_width);
+    let out_height = ((height as f32 * scale).round() as u32).min(window_height);
+    let x = (window_width - out_width) / 2;
+    let y = (window_height - out_height) / 2;
+    (x as i32, y as i32, out_width, out_height)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const W: [u8; 3] = [255, 255, 255];
+    const K: [u8; 3] = [0, 0, 0];
+
+    fn image(pixels: &[[u8; 3]]) -> Vec<u8> {
+        pixels.concat()
+    }
+
+    // 3x3 picture with a black diagonal from top left to bottom right
+    fn diagonal() -> Vec<u8> {
+        image(&[K, W, W, W, K, W, W, W, K])
+    }
+
+    fn cell(out: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * width + x) * 3;
+        [out[i], out[i + 1], out[i + 2]]
+    }
+
+    #[test]
+    fn test_flat_images_stay_flat() {
+        let flat = image(&[W; 9]);
+        for filter in ScaleFilter::ALL {
+            let out = filter.apply(&flat, 3, 3);
+            assert_eq!(out.len(), 27 * filter.factor() * filter.factor(), "{}", filter);
+            assert!(out.iter().all(|&v| v == 255), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_scale2x_rounds_diagonals() {
+        let out = ScaleFilter::Scale2x.apply(&diagonal(), 3, 3);
+        // the white pixel above the centre takes black in the corner between its black
+        // left and bottom neighbours
+        assert_eq!(cell(&out, 6, 2, 0), W);
+        assert_eq!(cell(&out, 6, 2, 1), K);
+        assert_eq!(cell(&out, 6, 3, 1), W);
+        // the centre has white all around and stays black
+        assert_eq!(cell(&out, 6, 2, 2), K);
+    }
+
+    #[test]
+    fn test_scale3x_rounds_diagonals() {
+        let out = ScaleFilter::Scale3x.apply(&diagonal(), 3, 3);
+        // white pixel above the centre
+        assert_eq!(cell(&out, 9, 3, 2), K);
+        assert_eq!(cell(&out, 9, 3, 1), K);
+        assert_eq!(cell(&out, 9, 4, 1), W);
+        assert_eq!(cell(&out, 9, 5, 2), W);
+    }
+
+    #[test]
+    fn test_hqx_and_xbr_smooth_edges() {
+        for filter in [ScaleFilter::Hq2x, ScaleFilter::Hq3x, ScaleFilter::Xbr2x] {
+            let factor = filter.factor();
+            let out = filter.apply(&diagonal(), 3, 3);
+            // the white pixel right of the centre gets some grey in its bottom left
+            let c = cell(&out, 3 * factor, 2 * factor, 2 * factor - 1);
+            assert!(c[0] > 0 && c[0] < 255, "{}: {:?}", filter, c);
+        }
+    }
+
+    #[test]
+    fn test_parse_and_cycle() {
+        assert_eq!("HQ3x".parse::<ScaleFilter>().unwrap(), ScaleFilter::Hq3x);
+        assert!("eagle".parse::<ScaleFilter>().is_err());
+        assert_eq!(ScaleFilter::Xbr2x.next(), ScaleFilter::None);
+    }
+
+    #[test]
+    fn test_fit() {
+        // square pixels in a 4:3 window: pillarboxed
+        assert_eq!(fit(256, 240, (1024, 768), 1.0, false), (102, 0, 819, 768));
+        // integer scaling only uses whole multiples
+        assert_eq!(fit(256, 240, (1000, 700), 1.0, true), (244, 110, 512, 480));
+        // 8:7 aspect widens the picture
+        assert_eq!(fit(256, 240, (1000, 480), 8.0 / 7.0, true), (207, 0, 585, 480));
+    }
+}
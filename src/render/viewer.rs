@@ -0,0 +1,330 @@
+// Pictures of the PPU state for the debug viewers: the four nametables with the scroll
+// window, both pattern tables, the OAM sprite list and palette RAM. Everything is read
+// with NesPPU::peek, so drawing them doesn't disturb the emulation
+
+use std::fmt;
+
+use crate::ppu::NesPPU;
+use crate::render::font::{self, CHAR_HEIGHT, CHAR_WIDTH};
+use crate::render::palette::Palette;
+
+const SCROLL_COLOR: (u8, u8, u8) = (0xff, 0x00, 0xff);
+pub const TEXT_COLOR: (u8, u8, u8) = (0xff, 0xff, 0xff);
+
+// An RGB24 picture
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            rgb: vec![0; width * height * 3],
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, (r, g, b): (u8, u8, u8)) {
+        if x < self.width && y < self.height {
+            let index = (y * self.width + x) * 3;
+            self.rgb[index..index + 3].copy_from_slice(&[r, g, b]);
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        let index = (y * self.width + x) * 3;
+        (self.rgb[index], self.rgb[index + 1], self.rgb[index + 2])
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.set_pixel(column, row, color);
+            }
+        }
+    }
+
+    // Draw `text` with its top left corner at (x, y), CHAR_WIDTH pixels per character
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: (u8, u8, u8)) {
+        for (i, c) in text.chars().enumerate() {
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        self.set_pixel(x + i * CHAR_WIDTH + column, y + row, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Colour value (0-3) of pixel (x, y) of the tile at `addr` in the pattern tables
+fn tile_pixel(ppu: &NesPPU, addr: u16, x: usize, y: usize) -> u8 {
+    let lo = ppu.peek(addr + y as u16);
+    let hi = ppu.peek(addr + y as u16 + 8);
+    ((lo >> (7 - x)) & 1) | (((hi >> (7 - x)) & 1) << 1)
+}
+
+// RGB of palette RAM entry `entry` (0-31); colour 0 of every palette shows the backdrop
+fn entry_color(ppu: &NesPPU, palette: &Palette, entry: u8) -> (u8, u8, u8) {
+    let entry = if entry & 0b11 == 0 { 0 } else { entry };
+    palette.rgb(ppu.peek(0x3f00 + entry as u16) as u16)
+}
+
+// Draw the 8x8 tile at `addr` under palette `palette_index` (0-7)
+fn draw_tile(image: &mut Image, ppu: &NesPPU, palette: &Palette, addr: u16, palette_index: u8, (x, y): (usize, usize)) {
+    for row in 0..8 {
+        for column in 0..8 {
+            let value = tile_pixel(ppu, addr, column, row);
+            let color = entry_color(ppu, palette, palette_index << 2 | value);
+            image.set_pixel(x + column, y + row, color);
+        }
+    }
+}
+
+// The four nametables as laid out in PPU memory, $2000 top left to $2C00 bottom right,
+// with the 256x240 window the scroll registers select outlined, wrapping at the edges
+pub fn nametables(ppu: &NesPPU, palette: &Palette) -> Image {
+    let mut image = Image::new(512, 480);
+    let bank = ppu.ctrl.bknd_pattern_addr();
+
+    for table in 0..4 {
+        let base = 0x2000 + table as u16 * 0x400;
+        let (left, top) = ((table & 1) * 256, (table >> 1) * 240);
+        for tile_y in 0..30 {
+            for tile_x in 0..32 {
+                let tile = ppu.peek(base + (tile_y * 32 + tile_x) as u16) as u16;
+                let attr = ppu.peek(base + 0x3c0 + ((tile_y / 4) * 8 + tile_x / 4) as u16);
+                let shift = ((tile_y & 2) << 1) | (tile_x & 2);
+                let palette_index = (attr >> shift) & 0b11;
+                draw_tile(&mut image, ppu, palette, bank + tile * 16, palette_index, (left + tile_x * 8, top + tile_y * 8));
+            }
+        }
+    }
+
+    let (scroll_x, scroll_y) = ppu.scroll();
+    for i in 0..256 {
+        let x = (scroll_x + i) % 512;
+        image.set_pixel(x, scroll_y % 480, SCROLL_COLOR);
+        image.set_pixel(x, (scroll_y + 239) % 480, SCROLL_COLOR);
+    }
+    for i in 0..240 {
+        let y = (scroll_y + i) % 480;
+        image.set_pixel(scroll_x % 512, y, SCROLL_COLOR);
+        image.set_pixel((scroll_x + 255) % 512, y, SCROLL_COLOR);
+    }
+    image
+}
+
+// Both pattern tables side by side as 16x16 tile grids, $0000 on the left, coloured with
+// palette `palette_index`: 0-3 background, 4-7 sprites
+pub fn pattern_tables(ppu: &NesPPU, palette: &Palette, palette_index: u8) -> Image {
+    let mut image = Image::new(256, 128);
+    for tile in 0..512 {
+        let (x, y) = ((tile / 256) * 128 + (tile % 16) * 8, ((tile % 256) / 16) * 8);
+        draw_tile(&mut image, ppu, palette, tile as u16 * 16, palette_index & 0b111, (x, y));
+    }
+    image
+}
+
+// Height of one palette RAM cell: a swatch over its colour number
+const SWATCH_SIZE: usize = 16;
+const PALETTE_CELL_HEIGHT: usize = SWATCH_SIZE + CHAR_HEIGHT + 1;
+
+// The 32 bytes of palette RAM, background palettes on the top row and sprites below
+pub fn palette_ram(ppu: &NesPPU, palette: &Palette) -> Image {
+    let mut image = Image::new(16 * SWATCH_SIZE, 2 * PALETTE_CELL_HEIGHT);
+    for entry in 0..32 {
+        let value = ppu.peek(0x3f00 + entry as u16);
+        let (x, y) = ((entry % 16) * SWATCH_SIZE, (entry / 16) * PALETTE_CELL_HEIGHT);
+        image.fill_rect(x, y, SWATCH_SIZE - 1, SWATCH_SIZE - 1, palette.rgb(value as u16));
+        image.draw_text(x + 4, y + SWATCH_SIZE, &format!("{:02X}", value), TEXT_COLOR);
+    }
+    image
+}
+
+// One OAM entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sprite {
+    pub index: usize,
+    pub x: u8,
+    // OAM Y; the sprite shows from the line below
+    pub y: u8,
+    pub tile: u8,
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+pub fn sprites(ppu: &NesPPU) -> Vec<Sprite> {
+    ppu.oam_data
+        .chunks(4)
+        .enumerate()
+        .map(|(index, oam)| Sprite {
+            index,
+            x: oam[3],
+            y: oam[0],
+            tile: oam[1],
+            palette: oam[2] & 0b11,
+            behind_background: oam[2] & 0x20 != 0,
+            flip_horizontal: oam[2] & 0x40 != 0,
+            flip_vertical: oam[2] & 0x80 != 0,
+        })
+        .collect()
+}
+
+impl fmt::Display for Sprite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02} X{:3} Y{:3} T{:02X} P{} {}{}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.palette,
+            if self.flip_horizontal { 'H' } else { '-' },
+            if self.flip_vertical { 'V' } else { '-' },
+            if self.behind_background { 'B' } else { 'F' }
+        )
+    }
+}
+
+const OAM_ROW_HEIGHT: usize = 17;
+const OAM_COLUMN_WIDTH: usize = 120;
+
+// The 64 sprites in two columns, each with its tile(s) and OAM fields: index, position,
+// tile number, palette, horizontal/vertical flip and Front or Behind the background
+pub fn oam(ppu: &NesPPU, palette: &Palette) -> Image {
+    let mut image = Image::new(2 * OAM_COLUMN_WIDTH, 32 * OAM_ROW_HEIGHT);
+    let tall = ppu.ctrl.sprite_size() == 16;
+
+    for sprite in sprites(ppu) {
+        let (x, y) = ((sprite.index / 32) * OAM_COLUMN_WIDTH, (sprite.index % 32) * OAM_ROW_HEIGHT);
+        let tile = sprite.tile as u16;
+        let tiles = if tall {
+            let top = (tile & 1) * 0x1000 + (tile & 0xfe) * 16;
+            vec![top, top + 16]
+        } else {
+            vec![ppu.ctrl.sprt_pattern_addr() + tile * 16]
+        };
+
+        for (half, &addr) in tiles.iter().enumerate() {
+            for row in 0..8 {
+                for column in 0..8 {
+                    let value = tile_pixel(ppu, addr, column, row);
+                    let color = entry_color(ppu, palette, (4 + sprite.palette) << 2 | value);
+                    let mut px = column;
+                    let mut py = half * 8 + row;
+                    if sprite.flip_horizontal {
+                        px = 7 - px;
+                    }
+                    if sprite.flip_vertical {
+                        py = tiles.len() * 8 - 1 - py;
+                    }
+                    image.set_pixel(x + px, y + py, color);
+                }
+            }
+        }
+        image.draw_text(x + 12, y + 5, &sprite.to_string(), TEXT_COLOR);
+    }
+    image
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+
+    fn test_ppu() -> NesPPU {
+        let mut chr = vec![0; 0x2000];
+        // tile 1: top row colour 1, second row colour 3
+        chr[0x10] = 0xff;
+        chr[0x11] = 0xff;
+        chr[0x19] = 0xff;
+        let mut ppu = NesPPU::new(chr, Mirroring::Vertical);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[1] = 0x16;
+        ppu.palette_table[3] = 0x30;
+        ppu.palette_table[0x15] = 0x2a;
+        ppu
+    }
+
+    #[test]
+    fn test_pattern_tables() {
+        let ppu = test_ppu();
+        let palette = Palette::default();
+        let image = pattern_tables(&ppu, &palette, 0);
+        assert_eq!((image.width, image.height), (256, 128));
+        assert_eq!(image.pixel(8, 0), palette.rgb(0x16));
+        assert_eq!(image.pixel(15, 1), palette.rgb(0x30));
+        assert_eq!(image.pixel(8, 2), palette.rgb(0x0f));
+        // the same tile in the right hand table is blank
+        assert_eq!(image.pixel(136, 0), palette.rgb(0x0f));
+
+        // sprite palette 1
+        let image = pattern_tables(&ppu, &palette, 5);
+        assert_eq!(image.pixel(8, 0), palette.rgb(0x2a));
+    }
+
+    #[test]
+    fn test_nametables_and_scroll_window() {
+        let mut ppu = test_ppu();
+        // tile 1 at the top left of nametable $2400, which vertical mirroring puts at $0400
+        ppu.vram[0x400] = 1;
+        // scroll x = 8, y = 16
+        ppu.write_register(0x2005, 8);
+        ppu.write_register(0x2005, 16);
+
+        let palette = Palette::default();
+        let image = nametables(&ppu, &palette);
+        assert_eq!((image.width, image.height), (512, 480));
+        assert_eq!(image.pixel(256, 0), palette.rgb(0x16));
+        assert_eq!(image.pixel(256 + 7, 1), palette.rgb(0x30));
+        // $2C00 mirrors $2400
+        assert_eq!(image.pixel(256, 240), palette.rgb(0x16));
+        assert_eq!(image.pixel(0, 0), palette.rgb(0x0f));
+
+        // the window's corners
+        assert_eq!(image.pixel(8, 16), SCROLL_COLOR);
+        assert_eq!(image.pixel(263, 255), SCROLL_COLOR);
+        assert_eq!(image.pixel(100, 100), palette.rgb(0x0f));
+
+        // scrolled to the bottom right, the window wraps round to the top left nametable
+        ppu.write_register(0x2000, 0b11);
+        let image = nametables(&ppu, &palette);
+        assert_eq!(image.pixel(264, 256), SCROLL_COLOR);
+        assert_eq!(image.pixel(7, 300), SCROLL_COLOR);
+        assert_eq!(image.pixel(0, 15), SCROLL_COLOR);
+        assert_eq!(image.pixel(263, 255), palette.rgb(0x0f));
+    }
+
+    #[test]
+    fn test_sprite_list() {
+        let mut ppu = test_ppu();
+        ppu.oam_data[4..8].copy_from_slice(&[0x20, 0x01, 0b0110_0001, 0x40]);
+        let list = sprites(&ppu);
+        assert_eq!(list.len(), 64);
+        let sprite = list[1];
+        assert_eq!((sprite.x, sprite.y, sprite.tile, sprite.palette), (0x40, 0x20, 1, 1));
+        assert!(sprite.flip_horizontal && sprite.behind_background && !sprite.flip_vertical);
+        assert_eq!(sprite.to_string(), "01 X 64 Y 32 T01 P1 H-B");
+
+        let image = oam(&ppu, &Palette::default());
+        assert_eq!(image.pixel(0, OAM_ROW_HEIGHT), Palette::default().rgb(0x2a));
+    }
+
+    #[test]
+    fn test_palette_ram() {
+        let ppu = test_ppu();
+        let palette = Palette::default();
+        let image = palette_ram(&ppu, &palette);
+        assert_eq!(image.pixel(SWATCH_SIZE, 0), palette.rgb(0x16));
+        // $3F10 mirrors the backdrop
+        assert_eq!(image.pixel(0, PALETTE_CELL_HEIGHT), palette.rgb(0x0f));
+    }
+}
@@ -0,0 +1,280 @@
+// Symbol file loaders filling a disasm::SymbolTable:
+//
+// ca65 .dbg   (ld65 --dbgfile) labels, plus source lines from its line/span/seg records
+// NESASM .fns `Label = $8000` lines, `;` starts a comment
+// Mesen .mlb  `P:1A3C:Label[:comment]` lines. P/NesPrgRom offsets are into PRG ROM,
+//             R/NesInternalRam into the 2KB RAM, S/W (NesSaveRam/NesWorkRam) into
+//             $6000-$7FFF and G/NesMemory are CPU addresses
+//
+// PRG ROM offsets are mapped the way NROM does: 16KB images are mirrored at $8000 and
+// $C000, so their labels are entered at both addresses
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::disasm::SymbolTable;
+
+// Load a symbol file by its extension; `prg_size` is the PRG ROM size Mesen offsets
+// refer to
+pub fn load(path: &str, prg_size: usize) -> Result<SymbolTable, String> {
+    let text = std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let path = Path::new(path);
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+    let result = match extension.as_deref() {
+        Some("dbg") => parse_ca65_dbg(&text, path.parent().unwrap_or(Path::new(""))),
+        Some("fns") => parse_fns(&text),
+        Some("mlb") => parse_mlb(&text, prg_size),
+        _ => return Err(format!("{}: expected a .dbg, .fns or .mlb file", path.display())),
+    };
+    result.map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// CPU addresses PRG ROM `offset` appears at
+fn prg_addresses(offset: usize, prg_size: usize) -> Vec<u16> {
+    match prg_size {
+        0x4000 if offset < 0x4000 => vec![0x8000 + offset as u16, 0xc000 + offset as u16],
+        _ if offset < 0x8000 => vec![0x8000 + offset as u16],
+        _ => Vec::new(),
+    }
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix('$')) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+pub fn parse_fns(text: &str) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(name, value)| {
+            let addr = parse_number(value.trim()).filter(|&addr| addr <= 0xffff)?;
+            Some((name.trim(), addr as u16))
+        });
+        match parsed {
+            Some((name, addr)) if !name.is_empty() => symbols.insert(addr, name),
+            _ => return Err(format!("line {}: expected `<label> = $<addr>`", number + 1)),
+        }
+    }
+
+    Ok(symbols)
+}
+
+pub fn parse_mlb(text: &str, prg_size: usize) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(4, ':');
+        let (kind, range, label) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(range), Some(label)) => (kind, range, label),
+            _ => return Err(format!("line {}: expected `<type>:<addr>:<label>`", number + 1)),
+        };
+        // comment-only entries have no label
+        if label.is_empty() {
+            continue;
+        }
+        // a range labels the first byte
+        let start = range.split('-').next().unwrap_or("");
+        let offset = usize::from_str_radix(start, 16).map_err(|_| format!("line {}: invalid address {}", number + 1, range))?;
+
+        let addresses = match kind {
+            "P" | "NesPrgRom" => prg_addresses(offset, prg_size),
+            "R" | "NesInternalRam" => vec![(offset & 0x7ff) as u16],
+            "S" | "NesSaveRam" | "W" | "NesWorkRam" if offset < 0x2000 => vec![0x6000 + offset as u16],
+            "G" | "NesMemory" if offset <= 0xffff => vec![offset as u16],
+            // CHR, other mappers' memory and so on
+            _ => Vec::new(),
+        };
+        for addr in addresses {
+            symbols.insert(addr, label);
+        }
+    }
+
+    Ok(symbols)
+}
+
+// Split `key=value,key="quoted, value"` into a map, without the quotes
+fn dbg_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        fields.insert(key.trim(), value);
+        rest = next.trim_start_matches(',');
+    }
+
+    fields
+}
+
+// Preference when several source lines cover a byte: C source over assembler, macro
+// expansions last
+fn line_priority(kind: Option<&str>) -> u8 {
+    match kind {
+        Some("1") => 2,
+        Some("2") => 0,
+        _ => 1,
+    }
+}
+
+pub fn parse_ca65_dbg(text: &str, base_dir: &Path) -> Result<SymbolTable, String> {
+    let mut symbols = SymbolTable::new();
+
+    // records by id
+    let mut files = HashMap::new();
+    let mut segs = HashMap::new();
+    let mut spans = HashMap::new();
+    let mut lines = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let Some((record, rest)) = line.split_once(|c: char| c.is_whitespace()) else {
+            continue;
+        };
+        let fields = dbg_fields(rest.trim());
+        let id = fields.get("id").and_then(|id| parse_number(id));
+        let number_field = |name: &str| fields.get(name).and_then(|value| parse_number(value));
+
+        match (record, id) {
+            ("file", Some(id)) => {
+                let name = fields.get("name").ok_or(format!("line {}: file without a name", number + 1))?;
+                let path = base_dir.join(name);
+                files.insert(id, symbols.add_file(&path.to_string_lossy()));
+            }
+            ("seg", Some(id)) => {
+                segs.insert(id, number_field("start").unwrap_or(0));
+            }
+            ("span", Some(id)) => {
+                if let (Some(seg), Some(start), Some(size)) = (number_field("seg"), number_field("start"), number_field("size")) {
+                    spans.insert(id, (seg, start, size));
+                }
+            }
+            ("line", Some(_)) => {
+                if let (Some(file), Some(line), Some(span)) = (number_field("file"), number_field("line"), fields.get("span")) {
+                    let spans: Vec<usize> = span.split('+').filter_map(parse_number).collect();
+                    lines.push((file, line, spans, line_priority(fields.get("type").copied())));
+                }
+            }
+            // labels only, not equates (constants that happen to look like addresses)
+            ("sym", Some(_)) if fields.get("type") == Some(&"lab") => {
+                let name = fields.get("name").ok_or(format!("line {}: symbol without a name", number + 1))?;
+                let value = number_field("val").filter(|&value| value <= 0xffff);
+                if let Some(value) = value {
+                    symbols.insert(value as u16, name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut best: HashMap<u16, u8> = HashMap::new();
+    for (file, line, line_spans, priority) in lines {
+        let Some(&file) = files.get(&file) else {
+            continue;
+        };
+        for span in line_spans {
+            let Some(&(seg, start, size)) = spans.get(&span) else {
+                continue;
+            };
+            let base = segs.get(&seg).copied().unwrap_or(0) + start;
+            for addr in base..base + size {
+                if addr > 0xffff {
+                    break;
+                }
+                let addr = addr as u16;
+                if best.get(&addr).is_none_or(|&current| priority > current) {
+                    best.insert(addr, priority);
+                    symbols.insert_line(addr, file, line);
+                }
+            }
+        }
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fns() {
+        let symbols = parse_fns("; main.asm\nReset\t\t= $C000\nNMI = $C0A2 ; vblank\n\n").unwrap();
+        assert_eq!(symbols.label(0xc000), Some("Reset"));
+        assert_eq!(symbols.lookup("NMI"), Some(0xc0a2));
+        assert!(parse_fns("Reset $C000").is_err());
+    }
+
+    #[test]
+    fn test_parse_mlb() {
+        let text = "P:0A3C:UpdatePlayer\nR:0010-0011:player_x:position\nW:0000:save\nG:2000:PPUCTRL\nP:0100::comment only\n";
+        let symbols = parse_mlb(text, 0x4000).unwrap();
+        assert_eq!(symbols.label(0x8a3c), Some("UpdatePlayer"));
+        assert_eq!(symbols.label(0xca3c), Some("UpdatePlayer"));
+        assert_eq!(symbols.label(0x0010), Some("player_x"));
+        assert_eq!(symbols.label(0x6000), Some("save"));
+        assert_eq!(symbols.label(0x2000), Some("PPUCTRL"));
+        assert_eq!(symbols.len(), 5);
+
+        let symbols = parse_mlb("NesPrgRom:4A3C:UpdatePlayer\n", 0x8000).unwrap();
+        assert_eq!(symbols.lookup("UpdatePlayer"), Some(0xca3c));
+        assert!(parse_mlb("P:zz:x", 0x8000).is_err());
+    }
+
+    #[test]
+    fn test_parse_ca65_dbg() {
+        let text = "\
+version\tmajor=2,minor=0
+file\tid=0,name=\"main.s\",size=100,mtime=0x5F000000,mod=0
+file\tid=1,name=\"game.c\",size=100,mtime=0x5F000000,mod=0
+line\tid=0,file=0,line=12,span=0
+line\tid=1,file=0,line=13,span=1+2
+line\tid=2,file=1,line=7,type=1,span=2
+seg\tid=0,name=\"CODE\",start=0x008000,size=0x0010,addrsize=absolute,type=ro,oname=\"game.nes\",ooffs=16
+span\tid=0,seg=0,start=0,size=3
+span\tid=1,seg=0,start=3,size=2
+span\tid=2,seg=0,start=5,size=1
+sym\tid=0,name=\"UpdatePlayer\",addrsize=absolute,scope=0,def=0,val=0x8000,seg=0,type=lab
+sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=1,val=0x3,type=equ
+sym\tid=2,name=\"player_x\",addrsize=zeropage,scope=0,def=2,val=0x10,type=lab
+";
+        let symbols = parse_ca65_dbg(text, Path::new("src")).unwrap();
+        assert_eq!(symbols.label(0x8000), Some("UpdatePlayer"));
+        assert_eq!(symbols.label(0x0010), Some("player_x"));
+        assert_eq!(symbols.label(0x0003), None);
+
+        let main = Path::new("src").join("main.s");
+        assert_eq!(symbols.source_line(0x8002), Some((main.to_str().unwrap(), 12)));
+        assert_eq!(symbols.source_line(0x8004), Some((main.to_str().unwrap(), 13)));
+        // the C line wins over the assembler line for the same byte
+        let game = Path::new("src").join("game.c");
+        assert_eq!(symbols.source_line(0x8005), Some((game.to_str().unwrap(), 7)));
+        assert_eq!(symbols.source_line(0x8006), None);
+    }
+
+    #[test]
+    fn test_dbg_fields() {
+        let fields = dbg_fields("id=0,name=\"a,b\",start=0x10");
+        assert_eq!(fields.get("name"), Some(&"a,b"));
+        assert_eq!(fields.get("start"), Some(&"0x10"));
+    }
+}
@@ -0,0 +1,268 @@
+// CPU trace logger producing lines in the Nintendulator / nestest.log format:
+//
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+//
+// Memory is only ever read through Mem::mem_peek so tracing never changes emulation state
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::cpu::{AddressingMode, Mem, StepResult, CPU};
+use crate::disasm::{self, SymbolTable};
+use crate::opcodes;
+use crate::region::Region;
+
+// PPU scanline and dot after `cycles` CPU cycles from power on: 341 dots per scanline,
+// 3 dots per CPU cycle and 262 scanlines per frame on NTSC, 3.2 dots and 312 lines on PAL
+pub fn ppu_position(cycles: usize, region: Region) -> (usize, usize) {
+    let (num, den) = region.dots_per_cpu_cycle();
+    let dots = cycles * num / den;
+    ((dots / 341) % region.scanlines_per_frame() as usize, dots % 341)
+}
+
+fn peek_u16_zero_page<B: Bus>(cpu: &CPU<B>, ptr: u8) -> u16 {
+    let lo = cpu.mem_peek(ptr as u16);
+    let hi = cpu.mem_peek(ptr.wrapping_add(1) as u16);
+    (hi as u16) << 8 | (lo as u16)
+}
+
+fn peek_u16<B: Bus>(cpu: &CPU<B>, addr: u16) -> u16 {
+    let lo = cpu.mem_peek(addr);
+    let hi = cpu.mem_peek(addr.wrapping_add(1));
+    (hi as u16) << 8 | (lo as u16)
+}
+
+// Effective address / value annotation nestest appends after the operand
+fn annotation<B: Bus>(cpu: &CPU<B>, opcode: &opcodes::OpCode, operand: &[u8]) -> String {
+    let byte = || operand[0];
+    let word = || (operand[1] as u16) << 8 | operand[0] as u16;
+
+    match opcode.mode {
+        AddressingMode::Immediate
+        | AddressingMode::Relative
+        | AddressingMode::ZeroPage_Relative
+        | AddressingMode::Indirect_Absolute_X
+        | AddressingMode::Accumulator
+        | AddressingMode::NoneAddressing => String::new(),
+
+        // JMP/JSR targets are code, not data
+        AddressingMode::Absolute if opcode.mnemonic == "JMP" || opcode.mnemonic == "JSR" => {
+            String::new()
+        }
+
+        AddressingMode::ZeroPage => format!(" = {:02X}", cpu.mem_peek(byte() as u16)),
+        AddressingMode::Absolute => format!(" = {:02X}", cpu.mem_peek(word())),
+
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            let index = if opcode.mode == AddressingMode::ZeroPage_X {
+                cpu.register_x
+            } else {
+                cpu.register_y
+            };
+            let addr = byte().wrapping_add(index) as u16;
+            format!(" @ {:02X} = {:02X}", addr, cpu.mem_peek(addr))
+        }
+
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            let index = if opcode.mode == AddressingMode::Absolute_X {
+                cpu.register_x
+            } else {
+                cpu.register_y
+            };
+            let addr = word().wrapping_add(index as u16);
+            format!(" @ {:04X} = {:02X}", addr, cpu.mem_peek(addr))
+        }
+
+        AddressingMode::Indirect_X => {
+            let ptr = byte().wrapping_add(cpu.register_x);
+            let addr = peek_u16_zero_page(cpu, ptr);
+            format!(" @ {:02X} = {:04X} = {:02X}", ptr, addr, cpu.mem_peek(addr))
+        }
+
+        AddressingMode::ZeroPage_Indirect => {
+            let addr = peek_u16_zero_page(cpu, byte());
+            format!(" = {:04X} = {:02X}", addr, cpu.mem_peek(addr))
+        }
+
+        AddressingMode::Indirect_Y => {
+            let base = peek_u16_zero_page(cpu, byte());
+            let addr = base.wrapping_add(cpu.register_y as u16);
+            format!(" = {:04X} @ {:04X} = {:02X}", base, addr, cpu.mem_peek(addr))
+        }
+
+        // JMP ($xxFF) fetches the high byte from $xx00 (6502 page wrap bug, fixed on the
+        // 65C02)
+        AddressingMode::Indirect => {
+            let ptr = word();
+            let target = if ptr & 0x00FF == 0x00FF && !cpu.variant.is_cmos() {
+                let lo = cpu.mem_peek(ptr);
+                let hi = cpu.mem_peek(ptr & 0xFF00);
+                (hi as u16) << 8 | (lo as u16)
+            } else {
+                peek_u16(cpu, ptr)
+            };
+            format!(" = {:04X}", target)
+        }
+    }
+}
+
+// Format the instruction at the program counter, before it executes
+pub fn trace<B: Bus>(cpu: &CPU<B>) -> String {
+    trace_with_symbols(cpu, None)
+}
+
+// Same as trace() with operand addresses shown as labels where known
+pub fn trace_with_symbols<B: Bus>(cpu: &CPU<B>, symbols: Option<&SymbolTable>) -> String {
+    let begin = cpu.program_counter;
+    let bytes: Vec<u8> = (0..3).map(|i| cpu.mem_peek(begin.wrapping_add(i))).collect();
+    let instruction = disasm::decode_variant(cpu.variant, &bytes, begin, symbols);
+
+    let mut operand = instruction.operand.clone();
+    if let Some(opcode) = opcodes::lookup(cpu.variant, bytes[0]) {
+        operand.push_str(&annotation(cpu, opcode, &instruction.bytes[1..]));
+    }
+
+    let hex: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let asm = format!(
+        "{:04X}  {:8} {:>4} {}",
+        begin,
+        hex.join(" "),
+        instruction.mnemonic,
+        operand
+    );
+
+    let (scanline, dot) = ppu_position(cpu.cycles, cpu.bus.region());
+
+    format!(
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        asm.trim_end(),
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.stack_pointer,
+        scanline,
+        dot,
+        cpu.cycles
+    )
+}
+
+// Writes one trace line per executed instruction to a file or stdout
+pub struct Tracer {
+    out: Box<dyn Write>,
+    symbols: Option<SymbolTable>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Tracer { out, symbols: None }
+    }
+
+    // Show labels from `symbols` in the logged operands
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn stdout() -> Self {
+        Tracer::new(Box::new(BufWriter::new(io::stdout())))
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Tracer::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    // Log the next instruction, then execute it
+    pub fn step<B: Bus>(&mut self, cpu: &mut CPU<B>) -> io::Result<StepResult> {
+        writeln!(self.out, "{}", trace_with_symbols(cpu, self.symbols.as_ref()))?;
+        Ok(cpu.step())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::CpuVariant;
+
+    fn cpu_with_program(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        let mut prg = vec![0; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        cpu.load_prg_rom(&prg).unwrap();
+        cpu.reset();
+        cpu.program_counter = 0xC000;
+        cpu
+    }
+
+    #[test]
+    fn test_format_trace() {
+        let mut cpu = cpu_with_program(&[0xa2, 0x01, 0xca, 0x88, 0x00]);
+        cpu.register_a = 1;
+        cpu.register_x = 2;
+        cpu.register_y = 3;
+
+        let mut result = vec![];
+        for _ in 0..3 {
+            result.push(trace(&cpu));
+            cpu.step();
+        }
+
+        assert_eq!(
+            "C000  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7",
+            result[0]
+        );
+        assert_eq!(
+            "C002  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0, 27 CYC:9",
+            result[1]
+        );
+        assert_eq!(
+            "C003  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 33 CYC:11",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        let mut cpu = cpu_with_program(&[0x11, 0x33, 0x00]);
+        cpu.register_y = 0;
+        cpu.mem_write(0x33, 0x00);
+        cpu.mem_write(0x34, 0x04);
+        cpu.mem_write(0x400, 0xAA);
+
+        assert_eq!(
+            "C000  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            trace(&cpu)
+        );
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap_annotation() {
+        for (variant, target) in [(CpuVariant::Nes2A03, "0700"), (CpuVariant::Cmos65C02, "0800")] {
+            let mut cpu = CPU::with_variant(variant);
+            cpu.load(vec![0x6c, 0xff, 0x02]);
+            cpu.reset();
+            cpu.mem_write(0x02ff, 0x00);
+            cpu.mem_write(0x0200, 0x07);
+            cpu.mem_write(0x0300, 0x08);
+
+            assert!(trace(&cpu).contains(&format!("JMP ($02FF) = {} ", target)), "{}", trace(&cpu));
+        }
+    }
+
+    #[test]
+    fn test_ppu_position_by_region() {
+        assert_eq!(ppu_position(7, Region::Ntsc), (0, 21));
+        assert_eq!(ppu_position(7, Region::Pal), (0, 22));
+        // a frame's worth of dots wraps on NTSC but is still inside a PAL frame
+        let cycles = 341 * 262 / 3 + 1;
+        assert_eq!(ppu_position(cycles, Region::Ntsc).0, 0);
+        assert_eq!(ppu_position(cycles, Region::Dendy).0, 262);
+        assert_eq!(ppu_position(cycles, Region::Pal).0, 279);
+    }
+}
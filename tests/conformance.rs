@@ -0,0 +1,213 @@
+// Conformance tests against community test ROMs (nestest, blargg's suites, Klaus Dormann's
+// 6502 functional and decimal tests).
+// The ROMs are not redistributable, so they are looked up under fixtures/ and every
+// test is skipped when its ROM is missing. See fixtures/README.md for the layout.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_nes_emulator::bus::NesBus;
+use rust_nes_emulator::cartridge::Rom;
+use rust_nes_emulator::cpu::{Mem, CPU};
+use rust_nes_emulator::klaus;
+use rust_nes_emulator::trace;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+
+// blargg ROMs report through $6000: 0x80 while running, 0x81 when they need a reset,
+// anything else is the final result code. $6001-$6003 holds a signature proving the
+// status byte is valid and $6004 a zero terminated text report
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+// Frames to run before giving up on a ROM (~1 minute of emulated time)
+const MAX_FRAMES: usize = 60 * 60;
+// Frames to wait before pressing reset when a ROM asks for it
+const RESET_DELAY_FRAMES: usize = 6;
+
+fn fixture(path: &str) -> Option<PathBuf> {
+    let path = Path::new(FIXTURES).join(path);
+    if path.exists() {
+        Some(path)
+    } else {
+        eprintln!("skipping: {} not found", path.display());
+        None
+    }
+}
+
+fn load_nrom(path: &Path) -> Result<CPU<NesBus>, String> {
+    let raw = fs::read(path).map_err(|err| err.to_string())?;
+    let rom = Rom::new(&raw)?;
+
+    let mut cpu = CPU::with_bus(NesBus::new(rom)?);
+    cpu.reset();
+    Ok(cpu)
+}
+
+fn read_text(cpu: &CPU<NesBus>) -> String {
+    let mut text = String::new();
+    let mut addr = TEXT_ADDR;
+    while addr < 0x8000 {
+        let byte = cpu.mem_peek(addr);
+        if byte == 0 {
+            break;
+        }
+        text.push(byte as char);
+        addr += 1;
+    }
+    text.trim().to_string()
+}
+
+// Run a blargg test ROM to completion; Ok holds the text report of a passing ROM
+fn run_blargg(path: &Path) -> Result<String, String> {
+    let mut cpu = load_nrom(path)?;
+    let mut reset_countdown = None;
+
+    for _ in 0..MAX_FRAMES {
+        if let Some(halt) = cpu.run_frame().halt {
+            return Err(format!("CPU halted: {}", halt));
+        }
+
+        let signature = [
+            cpu.mem_peek(SIGNATURE_ADDR),
+            cpu.mem_peek(SIGNATURE_ADDR + 1),
+            cpu.mem_peek(SIGNATURE_ADDR + 2),
+        ];
+        if signature != SIGNATURE {
+            continue;
+        }
+
+        match cpu.mem_peek(STATUS_ADDR) {
+            STATUS_RUNNING => {}
+            STATUS_NEEDS_RESET => match reset_countdown {
+                Some(0) => {
+                    cpu.reset();
+                    reset_countdown = None;
+                }
+                Some(frames) => reset_countdown = Some(frames - 1),
+                None => reset_countdown = Some(RESET_DELAY_FRAMES),
+            },
+            0 => return Ok(read_text(&cpu)),
+            code => return Err(format!("result code {}: {}", code, read_text(&cpu))),
+        }
+    }
+
+    Err(format!("timed out after {} frames: {}", MAX_FRAMES, read_text(&cpu)))
+}
+
+// Run every ROM of a blargg suite directory and report all failures at once
+fn run_blargg_suite(dir: &str) {
+    let dir = match fixture(dir) {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "nes"))
+        .collect();
+    roms.sort();
+
+    let mut failures = vec![];
+    for rom in roms {
+        let name = rom.file_name().unwrap().to_string_lossy().to_string();
+        match run_blargg(&rom) {
+            Ok(_) => eprintln!("{}: passed", name),
+            Err(err) => failures.push(format!("{}: {}", name, err)),
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// nestest.nes in automation mode (PC = $C000) against the reference nestest.log.
+// The CPU does not implement unofficial opcodes, so the comparison stops at the first
+// reference line using one (marked with '*' in front of the mnemonic)
+#[test]
+fn test_nestest_log() {
+    let (rom, log) = match (fixture("nestest/nestest.nes"), fixture("nestest/nestest.log")) {
+        (Some(rom), Some(log)) => (rom, log),
+        _ => return,
+    };
+
+    let mut cpu = load_nrom(&rom).unwrap();
+    cpu.program_counter = 0xC000;
+
+    let reference = fs::read_to_string(log).unwrap();
+    for (line_no, expected) in reference.lines().enumerate() {
+        if expected.as_bytes().get(15) == Some(&b'*') {
+            break;
+        }
+
+        let actual = trace::trace(&cpu);
+        assert_eq!(actual, expected.trim_end(), "nestest.log line {}", line_no + 1);
+
+        if let Some(halt) = cpu.step().halt {
+            panic!("CPU halted at nestest.log line {}: {}", line_no + 1, halt);
+        }
+    }
+
+    // nestest stores the number of the first failed official opcode test here
+    assert_eq!(cpu.mem_peek(0x0002), 0x00, "official opcode test failed");
+}
+
+#[test]
+fn test_blargg_instr_test() {
+    run_blargg_suite("blargg/instr_test-v5");
+}
+
+#[test]
+fn test_blargg_instr_misc() {
+    run_blargg_suite("blargg/instr_misc");
+}
+
+#[test]
+fn test_blargg_cpu_timing() {
+    run_blargg_suite("blargg/cpu_timing");
+}
+
+#[test]
+fn test_blargg_cpu_interrupts() {
+    run_blargg_suite("blargg/cpu_interrupts_v2");
+}
+
+#[test]
+fn test_blargg_ppu_vbl_nmi() {
+    run_blargg_suite("blargg/ppu_vbl_nmi");
+}
+
+#[test]
+fn test_blargg_apu_test() {
+    run_blargg_suite("blargg/apu_test");
+}
+
+#[test]
+fn test_klaus_functional() {
+    let image = match fixture("klaus/6502_functional_test.bin") {
+        Some(path) => fs::read(path).unwrap(),
+        None => return,
+    };
+
+    let result = klaus::run_functional_test(
+        &image,
+        klaus::FUNCTIONAL_TEST_START,
+        klaus::FUNCTIONAL_TEST_SUCCESS,
+    );
+    assert!(result.is_ok(), "{}", result.unwrap_err());
+}
+
+#[test]
+fn test_klaus_decimal() {
+    let image = match fixture("klaus/6502_decimal_test.bin") {
+        Some(path) => fs::read(path).unwrap(),
+        None => return,
+    };
+
+    let result = klaus::run_decimal_test(&image, klaus::DECIMAL_TEST_START, klaus::DECIMAL_TEST_START);
+    assert!(result.is_ok(), "{}", result.unwrap_err());
+}